@@ -0,0 +1,102 @@
+//! Compares tick times with the simulation's systems run on a single thread
+//! against running them on a thread pool, with a large wave on the map.
+//!
+//! Requires nightly: `cargo +nightly bench --features unstable`
+#![feature(test)]
+
+extern crate rust_tower_defense;
+extern crate test;
+
+use test::Bencher;
+
+use rust_tower_defense::game::archetypes::ArchetypeRegistry;
+use rust_tower_defense::game::collision::CollisionShape;
+use rust_tower_defense::game::combat::DamageType;
+use rust_tower_defense::game::entities::{GameEntity, GameEntityType, Projectile};
+use rust_tower_defense::game::map::GameMap;
+use rust_tower_defense::game::waves::WaveSet;
+use rust_tower_defense::game::{self, ActiveGame};
+use rust_tower_defense::geometry::{BoundingBox, Point};
+
+const WORLD_SIZE: u32 = 1024;
+const ENEMIES: u32 = 5000;
+const TOWERS: u32 = 200;
+const PROJECTILES: u32 = 1000;
+
+fn large_wave(threads: usize) -> ActiveGame {
+    let map = GameMap::new(
+        "Benchmark",
+        BoundingBox::new(Point::new(0, 0), Point::new(WORLD_SIZE, WORLD_SIZE)),
+    );
+    let archetypes =
+        ArchetypeRegistry::from_json(include_str!("../tests/fixtures/archetypes.json")).unwrap();
+
+    let mut game = game::start_game(map, archetypes, WaveSet::default());
+    game.scheduler.set_threads(threads).unwrap();
+
+    // Spread everything out on a grid so the result doesn't depend on an RNG
+    let spread = |i: u32, count: u32| {
+        let per_row = (f64::from(count).sqrt().ceil()) as u32;
+        let step = WORLD_SIZE / per_row;
+        Point::new((i % per_row) * step, (i / per_row) * step)
+    };
+
+    for i in 0..ENEMIES {
+        let mut grunt = game
+            .state
+            .archetypes
+            .instantiate("grunt", spread(i, ENEMIES))
+            .unwrap();
+        // Nothing should die while we're timing
+        grunt.health = Some(1_000_000);
+        game.state.add_entity(grunt);
+    }
+    for i in 0..TOWERS {
+        let mut tower = game
+            .state
+            .archetypes
+            .instantiate("arrow", spread(i, TOWERS))
+            .unwrap();
+        // Long enough range that every tower has enemies to pick from
+        tower.tower.as_mut().unwrap().range = 40;
+        game.state.add_entity(tower);
+    }
+    for i in 0..PROJECTILES {
+        // Parked between enemies, so they're swept every tick without hitting
+        let location = spread(i, PROJECTILES);
+        let location = Point::new(location.x() + 5, location.y() + 5);
+        let mut projectile = GameEntity::new(0, GameEntityType::Projectile, location);
+        projectile.shape = Some(CollisionShape::Circle { radius: 0 });
+        projectile.projectile = Some(Projectile {
+            source: 0,
+            damage: 1,
+            damage_type: DamageType::Physical,
+            effects: Vec::new(),
+            splash: false,
+            target: None,
+            speed: 0,
+            previous_location: location,
+        });
+        game.state.add_entity(projectile);
+    }
+
+    game
+}
+
+#[bench]
+fn tick_single_threaded(b: &mut Bencher) {
+    let mut game = large_wave(1);
+    b.iter(|| game.tick());
+}
+
+#[bench]
+fn tick_two_threads(b: &mut Bencher) {
+    let mut game = large_wave(2);
+    b.iter(|| game.tick());
+}
+
+#[bench]
+fn tick_four_threads(b: &mut Bencher) {
+    let mut game = large_wave(4);
+    b.iter(|| game.tick());
+}
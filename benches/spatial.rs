@@ -0,0 +1,137 @@
+//! Compares spatial grid range queries against a linear scan over every
+//! entity, which is what targeting did before the grid existed.
+//!
+//! Requires nightly: `cargo +nightly bench --features unstable`
+#![feature(test)]
+
+extern crate rand;
+extern crate rust_tower_defense;
+extern crate test;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use test::Bencher;
+
+use rust_tower_defense::game::spatial::SpatialGrid;
+use rust_tower_defense::geometry::Point;
+
+const WORLD_SIZE: u32 = 1024;
+const TOWER_RANGE: u32 = 40;
+const TOWERS: u32 = 50;
+
+fn random_points(count: u32) -> Vec<Point> {
+    let mut rng = StdRng::seed_from_u64(count.into());
+    (0..count)
+        .map(|_| Point::new(rng.gen_range(0, WORLD_SIZE), rng.gen_range(0, WORLD_SIZE)))
+        .collect()
+}
+
+fn linear_scan(b: &mut Bencher, enemies: u32) {
+    let points = random_points(enemies);
+    let towers = random_points(TOWERS);
+    let range_squared = u64::from(TOWER_RANGE * TOWER_RANGE);
+
+    b.iter(|| {
+        let mut found = 0;
+        for tower in towers.iter() {
+            found += points
+                .iter()
+                .filter(|p| p.distance_squared(*tower) <= range_squared)
+                .count();
+        }
+        found
+    });
+}
+
+fn grid_query(b: &mut Bencher, enemies: u32) {
+    let points = random_points(enemies);
+    let towers = random_points(TOWERS);
+
+    let mut grid = SpatialGrid::default();
+    for (id, point) in points.iter().enumerate() {
+        grid.insert(id as u32, *point);
+    }
+
+    b.iter(|| {
+        let mut found = 0;
+        for tower in towers.iter() {
+            found += grid.query_radius(*tower, TOWER_RANGE).len();
+        }
+        found
+    });
+}
+
+fn grid_move_all(b: &mut Bencher, enemies: u32) {
+    let points = random_points(enemies);
+
+    let mut grid = SpatialGrid::default();
+    for (id, point) in points.iter().enumerate() {
+        grid.insert(id as u32, *point);
+    }
+
+    let mut step = 0;
+    b.iter(|| {
+        step = (step + 1) % 8;
+        for (id, point) in points.iter().enumerate() {
+            grid.update(id as u32, Point::new(point.x() + step, *point.y()));
+        }
+    });
+}
+
+#[bench]
+fn linear_scan_100(b: &mut Bencher) {
+    linear_scan(b, 100);
+}
+
+#[bench]
+fn linear_scan_1000(b: &mut Bencher) {
+    linear_scan(b, 1000);
+}
+
+#[bench]
+fn linear_scan_10000(b: &mut Bencher) {
+    linear_scan(b, 10000);
+}
+
+#[bench]
+fn grid_query_100(b: &mut Bencher) {
+    grid_query(b, 100);
+}
+
+#[bench]
+fn grid_query_1000(b: &mut Bencher) {
+    grid_query(b, 1000);
+}
+
+#[bench]
+fn grid_query_10000(b: &mut Bencher) {
+    grid_query(b, 10000);
+}
+
+#[bench]
+fn grid_nearest_10000(b: &mut Bencher) {
+    let points = random_points(10000);
+    let towers = random_points(TOWERS);
+
+    let mut grid = SpatialGrid::default();
+    for (id, point) in points.iter().enumerate() {
+        grid.insert(id as u32, *point);
+    }
+
+    b.iter(|| {
+        towers
+            .iter()
+            .map(|tower| grid.nearest_k(*tower, 5).len())
+            .sum::<usize>()
+    });
+}
+
+#[bench]
+fn grid_move_1000(b: &mut Bencher) {
+    grid_move_all(b, 1000);
+}
+
+#[bench]
+fn grid_move_10000(b: &mut Bencher) {
+    grid_move_all(b, 10000);
+}
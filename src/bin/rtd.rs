@@ -3,23 +3,376 @@
 #[macro_use]
 extern crate log;
 
+extern crate rand;
 extern crate rust_tower_defense;
 
-use rust_tower_defense::geometry::Polygon;
+use std::env;
+use std::fs;
+use std::io;
+use std::process;
+
+use rust_tower_defense::game::archetypes::{ArchetypeRegistry, DEFAULT_ARCHETYPES};
+use rust_tower_defense::game::campaign::{Campaign, DEFAULT_CAMPAIGN};
+use rust_tower_defense::game::editor::MapEditor;
+use rust_tower_defense::game::generator::{self, MapGenConfig};
+use rust_tower_defense::game::hero;
+use rust_tower_defense::game::map::{GameMap, MapError, DEFAULT_MAP, DEFAULT_MAP_DIMENSIONS};
+use rust_tower_defense::game::profile::{Difficulty, GameResult, PlayerProfile, DEFAULT_PROFILE};
+use rust_tower_defense::game::reload::{DataFile, DataWatcher};
+use rust_tower_defense::game::tiled;
+use rust_tower_defense::game::validation;
+use rust_tower_defense::game::waves::{WaveSet, DEFAULT_WAVES};
+use rust_tower_defense::geometry::{Point, Polygon};
 use rust_tower_defense::logging;
 use rust_tower_defense::{game, graphics};
 
+const COMBAT_LOG_EXPORT: &str = "data/combat_log.json";
+const REPLAY_EXPORT: &str = "data/replay.bin";
+
 fn main() {
     // instantiate logging
     logging::init().unwrap();
 
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("validate") {
+        process::exit(validate(&args[2..]));
+    }
+    if args.get(1).map(String::as_str) == Some("edit") {
+        process::exit(edit(&args[2..]));
+    }
+    if args.get(1).map(String::as_str) == Some("import") {
+        process::exit(import(&args[2..]));
+    }
+
+    // A profile which fails to load is left alone rather than replaced
+    let mut profile = match PlayerProfile::load_or_default(DEFAULT_PROFILE) {
+        Ok(profile) => Some(profile),
+        Err(e) => {
+            error!(
+                "Unable to load player profile, progress won't be saved: {}",
+                e
+            );
+            None
+        }
+    };
+
+    let archetypes = match ArchetypeRegistry::load(DEFAULT_ARCHETYPES) {
+        Ok(archetypes) => archetypes,
+        Err(e) => panic!("Can't load archetypes: {}", e),
+    };
+
+    let campaign = match Campaign::load(DEFAULT_CAMPAIGN, &archetypes) {
+        Ok(campaign) => Some(campaign),
+        Err(e) => {
+            warn!("Unable to load campaign: {}", e);
+            None
+        }
+    };
+
+    // Practice on a generated map, which can be shared by its seed
+    let generate = match args.iter().position(|arg| arg == "--generate") {
+        Some(i) => match args.get(i + 1).and_then(|seed| seed.parse().ok()) {
+            Some(seed) => Some(seed),
+            None => panic!("--generate needs a number"),
+        },
+        None => None,
+    };
+
+    // Carry on with the campaign if there's anything left to play, otherwise
+    // fall back to the default map.
+    let level = match (campaign.as_ref(), profile.as_ref(), generate) {
+        (Some(campaign), Some(profile), None) => campaign.next_level(&profile.campaign).cloned(),
+        _ => None,
+    };
+
+    let (mut game, map_path, waves_path) = match (generate, campaign.as_ref(), level.as_ref()) {
+        (Some(seed), _, _) => (start_generated_game(archetypes, seed), None, DEFAULT_WAVES),
+        (None, Some(campaign), Some(level)) => {
+            info!("Starting campaign level {}", level.name);
+            let progress = &profile.as_ref().unwrap().campaign;
+            match campaign.start_level(level, &archetypes, progress) {
+                Ok(game) => {
+                    let map_path = level.map.as_ref().map_or(DEFAULT_MAP, String::as_str);
+                    (game, Some(map_path), level.waves.as_str())
+                }
+                Err(e) => panic!("Can't start level {}: {}", level.name, e),
+            }
+        }
+        _ => (
+            start_default_game(archetypes),
+            Some(DEFAULT_MAP),
+            DEFAULT_WAVES,
+        ),
+    };
+
+    let dimensions = game.map.dimensions;
+    let center = Point::new(
+        (dimensions.lower_left().x() + dimensions.upper_right().x()) / 2,
+        (dimensions.lower_left().y() + dimensions.upper_right().y()) / 2,
+    );
+    hero::spawn_hero(&mut game.state, center);
+
+    game.endless = args.iter().any(|arg| arg == "--endless");
+
+    // Pass the seed of an earlier run to get the same endless waves again
+    let seed = match args.iter().position(|arg| arg == "--seed") {
+        Some(i) => match args.get(i + 1).and_then(|seed| seed.parse().ok()) {
+            Some(seed) => seed,
+            None => panic!("--seed needs a number"),
+        },
+        None => rand::random(),
+    };
+    info!("Game seed: {}", seed);
+    game.set_seed(seed);
+
+    // Systems which don't conflict can run in parallel; results are the same
+    // either way
+    if let Some(i) = args.iter().position(|arg| arg == "--threads") {
+        match args.get(i + 1).and_then(|threads| threads.parse().ok()) {
+            Some(threads) => {
+                if let Err(e) = game.scheduler.set_threads(threads) {
+                    error!("Unable to start {} threads: {}", threads, e);
+                }
+            }
+            None => panic!("--threads needs a number"),
+        }
+    }
+
+    let mut watcher = DataWatcher::new();
+    // Reloaded archetypes normally only apply to what spawns next
+    watcher.update_existing = args.iter().any(|arg| arg == "--update-existing");
+    watcher.watch(DataFile::Archetypes, DEFAULT_ARCHETYPES);
+    watcher.watch(DataFile::Waves, waves_path);
+    if let Some(map_path) = map_path {
+        watcher.watch(DataFile::Map, map_path);
+    }
+    game.watcher = Some(watcher);
+
+    graphics::run(&mut game);
+    info!("Game bbox area: {}", game.map.dimensions.area());
+
+    if let Err(e) = game.combat_log.export_json(COMBAT_LOG_EXPORT) {
+        error!("Unable to export combat log: {}", e);
+    }
+
+    if let Err(e) = game.replay.save(REPLAY_EXPORT) {
+        error!("Unable to save replay: {}", e);
+    }
+
+    if let Some(profile) = profile.as_mut() {
+        let difficulty = level
+            .as_ref()
+            .map_or(Difficulty::Normal, |level| level.difficulty);
+        let result = GameResult::from_game(&game, difficulty);
+        for achievement in profile.record_game(&result) {
+            info!("Achievement unlocked: {}", achievement.name);
+        }
+
+        if let Some(level) = level.as_ref().filter(|_| result.cleared) {
+            info!("Completed {}", level.name);
+            for reward in profile.campaign.complete(level) {
+                info!("Reward: {:?}", reward);
+            }
+        }
+
+        if let Err(e) = profile.save(DEFAULT_PROFILE) {
+            error!("Unable to save player profile: {}", e);
+        }
+    }
+}
+
+/// `rtd validate [MAP] [--waves WAVES]` checks a map, and the waves played on
+/// it, printing every problem found. Exits with 1 if there are any errors.
+fn validate(args: &[String]) -> i32 {
+    let (map_path, waves_path) = map_and_waves_paths(args);
+
+    let map = match game::load_map(map_path) {
+        Ok(map) => map,
+        Err(e) => {
+            println!("{}: {}", map_path, e);
+            return 1;
+        }
+    };
+    let archetypes = match ArchetypeRegistry::load(DEFAULT_ARCHETYPES) {
+        Ok(archetypes) => archetypes,
+        Err(e) => {
+            println!("{}: {}", DEFAULT_ARCHETYPES, e);
+            return 1;
+        }
+    };
+    let waves = match fs::read_to_string(waves_path) {
+        Ok(json) => match WaveSet::parse(&json) {
+            Ok(waves) => Some(waves),
+            Err(e) => {
+                println!("{}: {}", waves_path, e);
+                return 1;
+            }
+        },
+        Err(e) => {
+            println!("{}: {}, checking the map without waves", waves_path, e);
+            None
+        }
+    };
+
+    let report = validation::validate_map(&map, waves.as_ref(), &archetypes);
+    for problem in report.problems.iter() {
+        println!("{}: {}", map_path, problem);
+    }
+    println!(
+        "{} error(s), {} warning(s)",
+        report.errors().count(),
+        report.warnings().count()
+    );
+
+    if report.is_valid() {
+        0
+    } else {
+        1
+    }
+}
+
+/// `rtd edit [MAP] [--waves WAVES]` opens the map editor. Maps which don't
+/// exist yet start out blank.
+fn edit(args: &[String]) -> i32 {
+    let (map_path, waves_path) = map_and_waves_paths(args);
+
+    let map = match game::load_map(map_path) {
+        Ok(map) => map,
+        Err(MapError::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => {
+            info!("Creating new map {}", map_path);
+            GameMap::new(map_path, DEFAULT_MAP_DIMENSIONS)
+        }
+        Err(e) => {
+            println!("{}: {}", map_path, e);
+            return 1;
+        }
+    };
+    let archetypes = match ArchetypeRegistry::load(DEFAULT_ARCHETYPES) {
+        Ok(archetypes) => archetypes,
+        Err(e) => {
+            println!("{}: {}", DEFAULT_ARCHETYPES, e);
+            return 1;
+        }
+    };
+    let waves = match fs::read_to_string(waves_path) {
+        Ok(json) => match WaveSet::parse(&json) {
+            Ok(waves) => waves,
+            Err(e) => {
+                println!("{}: {}", waves_path, e);
+                return 1;
+            }
+        },
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => WaveSet::default(),
+        Err(e) => {
+            println!("{}: {}", waves_path, e);
+            return 1;
+        }
+    };
+
+    let editor = MapEditor::new(
+        map.clone(),
+        waves.clone(),
+        archetypes.clone(),
+        map_path,
+        waves_path,
+    );
+    let mut game = game::start_game(map, archetypes, waves);
+    game.editor = Some(editor);
+
+    graphics::run(&mut game);
+
+    if game.editor.iter().any(MapEditor::is_modified) {
+        warn!("Closed the editor without saving {}", map_path);
+    }
+    0
+}
+
+/// `rtd import TILED_MAP OUTPUT` converts a map made in Tiled, saving it as
+/// JSON if `OUTPUT` ends in `.json` and as bincode otherwise.
+fn import(args: &[String]) -> i32 {
+    let (tiled_path, output) = match (args.first(), args.get(1)) {
+        (Some(tiled_path), Some(output)) => (tiled_path, output),
+        _ => {
+            println!("usage: rtd import TILED_MAP OUTPUT");
+            return 1;
+        }
+    };
+
+    let map = match tiled::import(tiled_path) {
+        Ok(map) => map,
+        Err(e) => {
+            println!("{}", e);
+            return 1;
+        }
+    };
+    if let Err(e) = map.save(output) {
+        println!("{}: {}", output, e);
+        return 1;
+    }
+    println!("Imported {} to {}", tiled_path, output);
+
+    // Problems aren't fatal, they can be fixed in the editor
+    if let Ok(archetypes) = ArchetypeRegistry::load(DEFAULT_ARCHETYPES) {
+        let report = validation::validate_map(&map, None, &archetypes);
+        for problem in report.problems.iter() {
+            println!("{}: {}", output, problem);
+        }
+    }
+    0
+}
+
+/// The map, and the waves played on it, given to the subcommands which work
+/// on maps.
+fn map_and_waves_paths(args: &[String]) -> (&str, &str) {
+    let waves_path = match args.iter().position(|arg| arg == "--waves") {
+        Some(i) => match args.get(i + 1) {
+            Some(path) => path.as_str(),
+            None => panic!("--waves needs a path"),
+        },
+        None => DEFAULT_WAVES,
+    };
+    let map_path = args
+        .first()
+        .filter(|arg| !arg.starts_with("--"))
+        .map_or(DEFAULT_MAP, String::as_str);
+
+    (map_path, waves_path)
+}
+
+fn start_generated_game(archetypes: ArchetypeRegistry, seed: u64) -> game::ActiveGame {
+    let map = generator::generate_map(seed, &MapGenConfig::default());
+    info!("Generated map from seed {}", seed);
+
+    let mut waves = match WaveSet::load(DEFAULT_WAVES, &archetypes) {
+        Ok(waves) => waves,
+        Err(e) => panic!("Can't load waves: {}", e),
+    };
+
+    // The waves were written for the default map, so send them from the
+    // generated map's spawn points instead
+    let mut spawns = map.spawns.iter().cycle();
+    for wave in waves.waves.iter_mut() {
+        for group in wave.groups.iter_mut() {
+            group.location = *spawns.next().unwrap();
+        }
+    }
+    let mut endless = waves.endless.clone().unwrap_or_default();
+    endless.spawn_points = map.spawns.clone();
+    waves.endless = Some(endless);
+
+    game::start_game(map, archetypes, waves)
+}
+
+fn start_default_game(archetypes: ArchetypeRegistry) -> game::ActiveGame {
     let map = match game::get_default_map() {
         Ok(map) => map,
-        Err(e) => panic!("Can't open default map: {}", e),
+        Err(e) => panic!("Can't open default map {}: {}", DEFAULT_MAP, e),
     };
 
-    let game = game::start_game(map);
+    let waves = match WaveSet::load(DEFAULT_WAVES, &archetypes) {
+        Ok(waves) => waves,
+        Err(e) => panic!("Can't load waves: {}", e),
+    };
 
-    graphics::run();
-    info!("Game bbox area: {}", game.map.dimensions.area());
+    game::start_game(map, archetypes, waves)
 }
@@ -1,37 +1,280 @@
+pub mod archetypes;
+pub mod campaign;
+pub mod clock;
+pub mod collision;
+pub mod combat;
+pub mod command_buffer;
+pub mod editor;
+pub mod endless;
+pub mod enemies;
 pub mod entities;
+pub mod files;
+pub mod generator;
+pub mod hero;
+pub mod history;
 pub mod map;
+pub mod pathfinding;
+pub mod placement;
+pub mod profile;
+pub mod reload;
+pub mod replay;
+pub mod schedule;
+pub mod scripting;
+pub mod spatial;
+pub mod synergy;
+pub mod targeting;
+pub mod terrain;
+pub mod tiled;
+pub mod tiles;
+pub mod towers;
+pub mod validation;
+pub mod visibility;
+pub mod waves;
 
 use std::collections::HashMap;
 use std::error::Error;
-use std::fs::File;
-use std::io::Read;
-use std::io::Write;
-use std::rc::Rc;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
 
-use super::geometry::Point;
-use crate::bincode::{deserialize, serialize};
-use entities::GameEntity;
-use map::{GameMap, DEFAULT_MAP, DEFAULT_MAP_DIMENSIONS, DEFAULT_MAP_NAME};
+use rand::SeedableRng;
+use rand_pcg::Pcg32;
 
-#[derive(Debug)]
-#[allow(dead_code)]
-enum GameMessage {
+use super::geometry::{BoundingBox, Point};
+use archetypes::ArchetypeRegistry;
+use campaign::LevelRules;
+use clock::SimulationClock;
+use combat::{CombatEvent, CombatLog, CombatStats};
+use command_buffer::CommandBuffer;
+use editor::MapEditor;
+use entities::{EntityCell, EntityHandle, EntityId, GameEntity, GameEntityType, Zone};
+use hero::FallenHero;
+use history::{BuildAction, BuildHistory};
+use map::{GameMap, MapError, DEFAULT_MAP, DEFAULT_MAP_DIMENSIONS, DEFAULT_MAP_NAME};
+use reload::{DataFile, DataWatcher};
+use replay::{GameCommand, Replay};
+use schedule::Scheduler;
+use scripting::ScriptRuntime;
+use spatial::SpatialGrid;
+use tiles::{TileMap, DEFAULT_TILE_SIZE};
+use visibility::FogOfWar;
+use waves::WaveSet;
+
+/// Messages are how systems (and map scripts) ask for changes to the game
+/// state. They are queued on the `GameState` bus and applied in order at the
+/// end of each tick.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GameMessage {
     Interact {
-        source: GameEntity,
-        target: GameEntity,
+        source: EntityId,
+        target: EntityId,
     },
     TriggerAbility {
-        target: GameEntity,
+        target: EntityId,
     },
     Move {
-        target: GameEntity,
+        target: EntityId,
         destination: Point,
     },
+    Spawn {
+        archetype: String,
+        location: Point,
+    },
+    SetZoneOpen {
+        zone: String,
+        open: bool,
+    },
 }
 
+/// Gold the player starts each game with.
+pub const STARTING_GOLD: u32 = 100;
+
 #[derive(Debug)]
 pub struct GameState {
-    pub entities: Vec<Rc<GameEntity>>,
+    pub entities: Vec<Arc<EntityCell>>,
+    /// Index over entity locations; kept in sync by `spawn_entity` and
+    /// `set_entity_location`.
+    pub spatial: SpatialGrid,
+    /// Spawns and despawns queued by systems during the tick.
+    pub commands: CommandBuffer,
+    pub zones: Vec<Zone>,
+    /// Playable area of the map.
+    pub bounds: BoundingBox,
+    /// The map's tiles, for systems which move things around.
+    pub tiles: TileMap,
+    /// Where enemies are headed. Enemies stand still on maps without any.
+    pub goals: Vec<Point>,
+    pub messages: Vec<GameMessage>,
+    pub archetypes: ArchetypeRegistry,
+    pub gold: u32,
+    pub tick: u32,
+    pub wave: u32,
+    /// Heroes waiting to respawn.
+    pub fallen_heroes: Vec<FallenHero>,
+    /// What each player can see. Without it, everything is visible.
+    pub fog: Option<FogOfWar>,
+    /// Seed the game's randomness was started from. Two games with the same
+    /// seed and the same commands play out identically.
+    pub seed: u64,
+    /// All game logic randomness must come from here.
+    pub rng: Pcg32,
+    next_entity_id: EntityId,
+    /// Set when towers are added, removed or changed, so synergy buffs get
+    /// recomputed.
+    synergies_dirty: bool,
+    by_id: HashMap<EntityId, Arc<EntityCell>>,
+}
+
+impl GameState {
+    pub fn new(zones: Vec<Zone>) -> GameState {
+        GameState {
+            entities: Vec::new(),
+            spatial: SpatialGrid::default(),
+            commands: CommandBuffer::new(),
+            zones,
+            bounds: DEFAULT_MAP_DIMENSIONS,
+            tiles: TileMap::new(DEFAULT_MAP_DIMENSIONS, DEFAULT_TILE_SIZE),
+            goals: Vec::new(),
+            messages: Vec::new(),
+            archetypes: ArchetypeRegistry::default(),
+            gold: STARTING_GOLD,
+            tick: 0,
+            wave: 0,
+            fallen_heroes: Vec::new(),
+            fog: None,
+            seed: 0,
+            rng: Pcg32::seed_from_u64(0),
+            next_entity_id: 0,
+            synergies_dirty: false,
+            by_id: HashMap::new(),
+        }
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = Pcg32::seed_from_u64(seed);
+    }
+
+    pub fn spawn_entity(&mut self, entity_type: GameEntityType, location: Point) -> EntityId {
+        self.add_entity(GameEntity::new(0, entity_type, location))
+    }
+
+    /// Adds a fully set up entity, assigning it a fresh ID.
+    ///
+    /// This must not be called while iterating over `entities`; systems
+    /// should queue spawns on `commands` instead.
+    pub fn add_entity(&mut self, mut entity: GameEntity) -> EntityId {
+        let id = self.next_entity_id;
+        self.next_entity_id += 1;
+
+        entity.id = id;
+        self.spatial.insert(id, entity.location);
+        if entity.tower.is_some() {
+            self.synergies_dirty = true;
+        }
+
+        let entity = Arc::new(EntityCell::new(entity));
+        self.by_id.insert(id, entity.clone());
+        self.entities.push(entity);
+
+        id
+    }
+
+    /// Removes an entity immediately. Any handles to it stop upgrading.
+    ///
+    /// Like `add_entity`, systems should go through `commands` instead.
+    pub fn remove_entity(&mut self, id: EntityId) {
+        if let Some(entity) = self.by_id.remove(&id) {
+            if entity.borrow().tower.is_some() {
+                self.synergies_dirty = true;
+            }
+            self.entities.retain(|entity| entity.borrow().id != id);
+            self.spatial.remove(id);
+        }
+    }
+
+    /// The sync point: applies every spawn and despawn queued on `commands`.
+    pub fn apply_commands(&mut self) {
+        let (spawns, despawns) = self.commands.take();
+
+        for id in despawns {
+            self.remove_entity(id);
+        }
+        for entity in spawns {
+            self.add_entity(entity);
+        }
+    }
+
+    /// Makes the synergy system recompute every tower's buffs, e.g. after a
+    /// tower has been upgraded.
+    pub fn invalidate_synergies(&mut self) {
+        self.synergies_dirty = true;
+    }
+
+    /// Returns whether synergies need recomputing, and clears the flag.
+    pub fn take_synergies_dirty(&mut self) -> bool {
+        let dirty = self.synergies_dirty;
+        self.synergies_dirty = false;
+        dirty
+    }
+
+    pub fn entity(&self, id: EntityId) -> Option<Arc<EntityCell>> {
+        self.by_id.get(&id).cloned()
+    }
+
+    pub fn handle(&self, id: EntityId) -> Option<EntityHandle> {
+        self.by_id.get(&id).map(EntityHandle::new)
+    }
+
+    /// Moves an entity, keeping the spatial index up to date.
+    pub fn set_entity_location(&mut self, id: EntityId, location: Point) {
+        if let Some(entity) = self.entity(id) {
+            entity.borrow_mut().location = location;
+            self.spatial.update(id, location);
+        }
+    }
+
+    pub fn post_message(&mut self, message: GameMessage) {
+        self.messages.push(message);
+    }
+
+    pub fn zone(&self, name: &str) -> Option<&Zone> {
+        self.zones.iter().find(|zone| zone.name == name)
+    }
+
+    /// Applies every queued message in the order they were posted.
+    pub fn process_messages(&mut self) {
+        let messages: Vec<GameMessage> = self.messages.drain(..).collect();
+
+        for message in messages {
+            match message {
+                GameMessage::Spawn {
+                    archetype,
+                    location,
+                } => match self.archetypes.instantiate(&archetype, location) {
+                    Ok(entity) => {
+                        self.add_entity(entity);
+                    }
+                    Err(e) => warn!("Unable to spawn: {}", e),
+                },
+                GameMessage::Move {
+                    target,
+                    destination,
+                } => {
+                    if !hero::order_move(self, target, destination) {
+                        debug!("Entity {} can't move to {:?}", target, destination);
+                    }
+                }
+                GameMessage::SetZoneOpen { zone, open } => {
+                    match self.zones.iter_mut().find(|z| z.name == zone) {
+                        Some(z) => z.open = open,
+                        None => warn!("Message for unknown zone {}", zone),
+                    }
+                }
+                message => debug!("Unhandled message {:?}", message),
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -39,6 +282,310 @@ pub struct ActiveGame {
     pub map: GameMap,
     pub state: GameState,
     pub started_time: u32,
+    pub combat_log: CombatLog,
+    pub scripts: Option<ScriptRuntime>,
+    pub clock: SimulationClock,
+    pub replay: Replay,
+    pub waves: WaveSet,
+    /// The first tick of the current wave.
+    pub wave_started_tick: u32,
+    /// Reloads data files when they change on disk, if set.
+    pub watcher: Option<DataWatcher>,
+    /// Whether to keep generating waves once the authored ones run out.
+    pub endless: bool,
+    /// Number of waves in `waves` which came from the wave file.
+    pub authored_waves: usize,
+    /// Runs the simulation's systems each tick.
+    pub scheduler: Scheduler,
+    /// Tower placements and sales which can still be undone.
+    pub history: BuildHistory,
+    /// The campaign level's towers and upgrades, if this is a campaign game.
+    pub level_rules: Option<LevelRules>,
+    /// Set when the client was opened to edit a map rather than play it.
+    /// The simulation doesn't run while editing.
+    pub editor: Option<MapEditor>,
+}
+
+impl ActiveGame {
+    /// Applies a player command and records it in the replay.
+    pub fn execute_command(&mut self, command: GameCommand) {
+        self.replay.record(self.state.tick, command.clone());
+
+        match command {
+            GameCommand::Clock(clock_command) => self.clock.apply(clock_command),
+            GameCommand::PlaceTower {
+                archetype,
+                location,
+            } => match placement::place_tower(&mut self.state, &self.map, &archetype, location) {
+                Ok(tower) => {
+                    if self.in_build_phase() {
+                        let cost = self
+                            .state
+                            .archetypes
+                            .tower(&archetype)
+                            .map_or(0, |tower| tower.cost);
+                        self.history.record(BuildAction::PlaceTower {
+                            archetype,
+                            location,
+                            tower,
+                            cost,
+                        });
+                    }
+                }
+                Err(e) => info!("Can't place {}: {}", archetype, e),
+            },
+            GameCommand::MoveHero { destination } => match hero::hero_id(&self.state) {
+                Some(target) => self.state.post_message(GameMessage::Move {
+                    target,
+                    destination,
+                }),
+                None => info!("No hero to move"),
+            },
+            GameCommand::SellTower { tower } => {
+                let sold = self.state.entity(tower).map(|entity| {
+                    let entity = entity.borrow();
+                    (entity.archetype.clone(), entity.location)
+                });
+
+                match placement::sell_tower(&mut self.state, tower) {
+                    Ok(refund) => {
+                        let sold = sold.filter(|_| self.in_build_phase());
+                        if let Some((Some(archetype), location)) = sold {
+                            self.history.record(BuildAction::SellTower {
+                                archetype,
+                                location,
+                                tower,
+                                refund,
+                            });
+                        }
+                    }
+                    Err(e) => info!("Can't sell tower {}: {}", tower, e),
+                }
+            }
+            GameCommand::StartWave => self.start_wave(self.state.wave + 1),
+            GameCommand::Undo => {
+                if !self.in_build_phase() {
+                    info!("Can't undo while a wave is running");
+                } else if let Err(e) = self.history.undo(&mut self.state, &self.map) {
+                    info!("Can't undo: {}", e);
+                }
+            }
+            GameCommand::Redo => {
+                if !self.in_build_phase() {
+                    info!("Can't redo while a wave is running");
+                } else if let Err(e) = self.history.redo(&mut self.state, &self.map) {
+                    info!("Can't redo: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Runs however many ticks `elapsed` real time corresponds to at the
+    /// current game speed. Returns the number of ticks run.
+    pub fn update(&mut self, elapsed: Duration) -> u32 {
+        self.reload_changed_data(elapsed);
+
+        let ticks = self.clock.advance(elapsed);
+        for _ in 0..ticks {
+            self.tick();
+        }
+
+        ticks
+    }
+
+    /// Advances the simulation by a single tick.
+    pub fn tick(&mut self) {
+        self.state.tick += 1;
+
+        if let Some(scripts) = self.scripts.as_mut() {
+            scripts.on_tick(&mut self.state);
+        }
+
+        self.spawn_wave_enemies();
+
+        self.state.process_messages();
+
+        self.scheduler.run(&mut self.state, &mut self.combat_log);
+
+        self.state.apply_commands();
+    }
+
+    /// Sets the seed for all of the game's randomness, and records it in the
+    /// replay.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.state.reseed(seed);
+        self.replay.seed = seed;
+    }
+
+    fn spawn_wave_enemies(&mut self) {
+        let wave = match self.waves.wave(self.state.wave) {
+            Some(wave) => wave,
+            None => return,
+        };
+        let offset = match self.state.tick.checked_sub(self.wave_started_tick) {
+            Some(offset) => offset,
+            None => return,
+        };
+
+        for group in wave.spawns_at(offset) {
+            match self
+                .state
+                .archetypes
+                .instantiate(&group.archetype, group.location)
+            {
+                Ok(mut entity) => {
+                    entity.health = entity.health.map(|health| group.scale_health(health));
+                    self.state.add_entity(entity);
+                }
+                Err(e) => warn!("Unable to spawn wave enemy: {}", e),
+            }
+        }
+    }
+
+    pub fn start_wave(&mut self, wave: u32) {
+        // Top up the waves from the endless generator as they run out
+        while self.endless && wave as usize > self.waves.len() {
+            let n = (self.waves.len() - self.authored_waves) as u32;
+            let config = self.waves.endless.clone().unwrap_or_default();
+            let generated = config.generate_wave(n, &self.state.archetypes, &mut self.state.rng);
+            self.waves.push(generated);
+        }
+
+        self.state.wave = wave;
+        self.wave_started_tick = self.state.tick + 1;
+        // Build phase actions can't be undone once enemies are coming
+        self.history.commit();
+
+        if let Some(scripts) = self.scripts.as_mut() {
+            scripts.on_wave_start(&mut self.state);
+        }
+
+        self.state.process_messages();
+    }
+
+    /// Whether the current wave has been sent and no enemies are left. This
+    /// is also the case before the first wave.
+    pub fn wave_finished(&self) -> bool {
+        if let Some(wave) = self.waves.wave(self.state.wave) {
+            if self.state.tick < self.wave_started_tick + wave.duration() {
+                return false;
+            }
+        }
+
+        !self
+            .state
+            .entities
+            .iter()
+            .any(|entity| entity.borrow().entity_type == GameEntityType::Enemy)
+    }
+
+    /// Whether the player is building between waves, when placing and selling
+    /// towers can be undone.
+    pub fn in_build_phase(&self) -> bool {
+        self.wave_finished()
+    }
+
+    /// Whether every wave has been sent and no enemies are left. Endless
+    /// games are never cleared.
+    pub fn is_cleared(&self) -> bool {
+        if self.endless {
+            return false;
+        }
+
+        match self.waves.wave(self.state.wave) {
+            Some(_) if self.state.wave as usize == self.waves.len() => self.wave_finished(),
+            _ => false,
+        }
+    }
+
+    /// Reloads any watched data files which have changed. A file which fails
+    /// to load or validate is logged and the previous data is kept.
+    fn reload_changed_data(&mut self, elapsed: Duration) {
+        let changed = match self.watcher.as_mut() {
+            Some(watcher) => watcher.poll(elapsed),
+            None => return,
+        };
+
+        for kind in changed {
+            let path = match self.watcher.as_ref().and_then(|w| w.path(kind)) {
+                Some(path) => path.to_string(),
+                None => continue,
+            };
+
+            match self.reload_data(kind, &path) {
+                Ok(()) => info!("Reloaded {}", path),
+                Err(e) => error!("Unable to reload {}: {}", path, e),
+            }
+        }
+    }
+
+    /// Replaces one kind of gameplay data with the contents of `path`. New
+    /// archetype stats apply to future spawns, and to existing entities too if
+    /// the watcher asks for it.
+    pub fn reload_data(&mut self, kind: DataFile, path: &str) -> Result<(), Box<dyn Error>> {
+        match kind {
+            DataFile::Archetypes => {
+                let mut archetypes = ArchetypeRegistry::load(path)?;
+                // Campaign towers stay locked, and upgraded
+                if let Some(ref rules) = self.level_rules {
+                    rules.apply(&mut archetypes);
+                }
+                // Don't strand the current waves with missing enemies
+                self.waves.validate(&archetypes)?;
+
+                let update_existing = match self.watcher {
+                    Some(ref watcher) => watcher.update_existing,
+                    None => false,
+                };
+                if update_existing {
+                    for entity in self.state.entities.iter() {
+                        archetypes.refresh(&mut entity.borrow_mut());
+                    }
+                }
+
+                self.state.archetypes = archetypes;
+                // Auras may have changed even if existing stats weren't
+                self.state.invalidate_synergies();
+            }
+            DataFile::Waves => {
+                // Endless waves past the new authored ones are regenerated
+                // as they're needed
+                self.waves = WaveSet::load(path, &self.state.archetypes)?;
+                self.authored_waves = self.waves.len();
+            }
+            DataFile::Map => {
+                let map = load_map(path)?;
+
+                // Zones keep whatever state scripts have put them in
+                let mut zones = map.zones.clone();
+                for zone in zones.iter_mut() {
+                    if let Some(current) = self.state.zone(&zone.name) {
+                        zone.open = current.open;
+                    }
+                }
+
+                self.scripts = load_scripts(&map);
+                self.state.zones = zones;
+                self.state.bounds = map.dimensions;
+                self.state.tiles = map.tiles.clone();
+                self.state.goals = map.goals.clone();
+                self.state.fog = Some(FogOfWar::for_map(&map));
+                self.map = map;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Appends an event to the combat log, stamped with the current tick and wave.
+    pub fn record_combat_event(&mut self, event: CombatEvent) {
+        self.combat_log
+            .record(self.state.tick, self.state.wave, event);
+    }
+
+    pub fn combat_stats(&self) -> &CombatStats {
+        self.combat_log.stats()
+    }
 }
 
 #[allow(dead_code)]
@@ -110,15 +657,8 @@ fn call_with_different_types() {
 pub fn recreate_default_map() -> Result<GameMap, Box<dyn Error>> {
     println!("Recreating default map");
 
-    let mut file = File::create(DEFAULT_MAP)?;
-
-    let map_struct = GameMap {
-        dimensions: DEFAULT_MAP_DIMENSIONS,
-        name: DEFAULT_MAP_NAME.to_string(),
-    };
-
-    let encoded: Vec<u8> = serialize(&map_struct).unwrap();
-    file.write_all(&encoded)?;
+    let map_struct = GameMap::new(DEFAULT_MAP_NAME, DEFAULT_MAP_DIMENSIONS);
+    map_struct.save(DEFAULT_MAP)?;
 
     Ok(map_struct)
 }
@@ -128,30 +668,62 @@ pub fn recreate_default_map() -> Result<GameMap, Box<dyn Error>> {
 ///
 /// # Errors
 ///
-/// If there is any issue loading the map file from disk, an Err variant will
-/// be returned.
+/// If the map file exists but can't be loaded, an Err variant will be
+/// returned. Only a missing map is recreated, so a map from a newer version
+/// of the game, or one which is damaged, is never overwritten.
 pub fn get_default_map() -> Result<GameMap, Box<dyn Error>> {
-    // I will probably want to use some human-readable JSON config for top-level
-    // map configurations.
-    let mut map_file = match File::open(DEFAULT_MAP) {
-        Ok(f) => f,
-        Err(_error) => return recreate_default_map(),
-    };
-    let mut map_data = Vec::<u8>::new();
-    map_file.read_to_end(&mut map_data)?;
-
-    match deserialize(&map_data) {
+    match load_map(DEFAULT_MAP) {
         Ok(map_struct) => Ok(map_struct),
-        Err(_error) => recreate_default_map(),
+        Err(MapError::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => recreate_default_map(),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+/// Loads a map file, bincode or JSON, without falling back to the default
+/// map on failure.
+pub fn load_map(path: &str) -> Result<GameMap, MapError> {
+    GameMap::load(path)
+}
+
+fn load_scripts(map: &GameMap) -> Option<ScriptRuntime> {
+    match map.script {
+        Some(ref path) => match ScriptRuntime::from_file(path) {
+            Ok(scripts) => Some(scripts),
+            Err(e) => {
+                error!("Unable to load map script {}: {}", path, e);
+                None
+            }
+        },
+        None => None,
     }
 }
 
-pub fn start_game(map: GameMap) -> ActiveGame {
+pub fn start_game(map: GameMap, archetypes: ArchetypeRegistry, waves: WaveSet) -> ActiveGame {
+    let scripts = load_scripts(&map);
+
+    let mut state = GameState::new(map.zones.clone());
+    state.bounds = map.dimensions;
+    state.tiles = map.tiles.clone();
+    state.goals = map.goals.clone();
+    state.fog = Some(FogOfWar::for_map(&map));
+    state.archetypes = archetypes;
+
     ActiveGame {
+        state,
+        replay: Replay::new(&map.name),
         map,
-        state: GameState {
-            entities: Vec::new(),
-        },
         started_time: 0,
+        combat_log: CombatLog::new(),
+        scripts,
+        clock: SimulationClock::new(),
+        authored_waves: waves.len(),
+        waves,
+        wave_started_tick: 0,
+        watcher: None,
+        endless: false,
+        scheduler: Scheduler::new(schedule::simulation_systems()),
+        history: BuildHistory::new(),
+        level_rules: None,
+        editor: None,
     }
 }
@@ -0,0 +1,470 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+
+use super::collision::CollisionShape;
+use super::combat::DamageType;
+use super::entities::{Enemy, GameEntity, GameEntityType, Sight, Tower};
+use super::synergy::Aura;
+use super::terrain::Terrain;
+use super::visibility::{DETECTOR_ABILITY, STEALTH_ABILITY};
+use crate::geometry::Point;
+use crate::serde_derive::{Deserialize, Serialize};
+
+pub const DEFAULT_ARCHETYPES: &str = "data/archetypes.json";
+
+#[derive(Debug)]
+pub struct ArchetypeError {
+    details: String,
+}
+
+impl ArchetypeError {
+    fn new(msg: &str) -> ArchetypeError {
+        ArchetypeError {
+            details: msg.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ArchetypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for ArchetypeError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+/// A status effect applied by a tower's attacks, e.g. a slow.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Effect {
+    pub status: String,
+    /// Duration in ticks.
+    pub duration: u32,
+    #[serde(default)]
+    pub magnitude: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TowerArchetype {
+    pub name: String,
+    pub model: String,
+    pub cost: u32,
+    pub range: u32,
+    pub damage: u32,
+    #[serde(default = "default_damage_type")]
+    pub damage_type: DamageType,
+    /// Ticks between shots.
+    pub fire_interval: u32,
+    #[serde(default = "default_radius")]
+    pub radius: u32,
+    #[serde(default)]
+    pub abilities: Vec<String>,
+    #[serde(default)]
+    pub effects: Vec<Effect>,
+    /// Buffs this tower gives to towers around it.
+    #[serde(default)]
+    pub auras: Vec<Aura>,
+    /// How far the tower can see. Defaults to its range.
+    #[serde(default)]
+    pub sight_range: Option<u32>,
+    /// Terrain the tower can only be built on, even if it isn't normally
+    /// buildable. If empty, any buildable terrain will do.
+    #[serde(default)]
+    pub terrain: Vec<Terrain>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct EnemyArchetype {
+    pub name: String,
+    pub model: String,
+    pub health: u32,
+    /// World units moved per tick.
+    pub speed: u32,
+    /// Gold awarded for killing this enemy.
+    #[serde(default)]
+    pub bounty: u32,
+    /// Lives lost if this enemy reaches the goal.
+    #[serde(default = "default_lives_cost")]
+    pub lives_cost: u32,
+    /// Damage dealt to heroes the enemy runs into.
+    #[serde(default = "default_enemy_damage")]
+    pub damage: u32,
+    #[serde(default = "default_radius")]
+    pub radius: u32,
+    #[serde(default)]
+    pub abilities: Vec<String>,
+}
+
+fn default_damage_type() -> DamageType {
+    DamageType::Physical
+}
+
+fn default_radius() -> u32 {
+    1
+}
+
+fn default_lives_cost() -> u32 {
+    1
+}
+
+fn default_enemy_damage() -> u32 {
+    1
+}
+
+/// Layout of the archetype data file.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct ArchetypeFile {
+    #[serde(default)]
+    towers: Vec<TowerArchetype>,
+    #[serde(default)]
+    enemies: Vec<EnemyArchetype>,
+}
+
+impl TowerArchetype {
+    fn validate(&self, problems: &mut Vec<String>) {
+        let mut problem = |msg: &str| problems.push(format!("tower '{}': {}", self.name, msg));
+
+        if self.model.is_empty() {
+            problem("model must not be empty");
+        }
+        if self.range == 0 {
+            problem("range must be greater than 0");
+        }
+        if self.fire_interval == 0 {
+            problem("fire_interval must be greater than 0");
+        }
+        for effect in self.effects.iter() {
+            if effect.status.is_empty() {
+                problem("effect status must not be empty");
+            }
+            if effect.duration == 0 {
+                problem(&format!(
+                    "effect '{}' duration must be greater than 0",
+                    effect.status
+                ));
+            }
+        }
+        for aura in self.auras.iter() {
+            if aura.radius == 0 {
+                problem("aura radius must be greater than 0");
+            }
+        }
+    }
+}
+
+impl EnemyArchetype {
+    fn validate(&self, problems: &mut Vec<String>) {
+        let mut problem = |msg: &str| problems.push(format!("enemy '{}': {}", self.name, msg));
+
+        if self.model.is_empty() {
+            problem("model must not be empty");
+        }
+        if self.health == 0 {
+            problem("health must be greater than 0");
+        }
+        if self.speed == 0 {
+            problem("speed must be greater than 0");
+        }
+    }
+}
+
+/// All the tower and enemy types available to a game, loaded from JSON.
+#[derive(Debug, Default, Clone)]
+pub struct ArchetypeRegistry {
+    towers: HashMap<String, TowerArchetype>,
+    enemies: HashMap<String, EnemyArchetype>,
+}
+
+impl ArchetypeRegistry {
+    pub fn load(path: &str) -> Result<ArchetypeRegistry, Box<dyn Error>> {
+        let json = fs::read_to_string(path)?;
+        ArchetypeRegistry::from_json(&json).map_err(|e| {
+            Box::new(ArchetypeError::new(&format!("{}: {}", path, e))) as Box<dyn Error>
+        })
+    }
+
+    /// Parses and validates archetypes. Every problem found is reported, one
+    /// per line, rather than stopping at the first.
+    pub fn from_json(json: &str) -> Result<ArchetypeRegistry, ArchetypeError> {
+        let file: ArchetypeFile = serde_json::from_str(json)
+            .map_err(|e| ArchetypeError::new(&format!("invalid archetype file: {}", e)))?;
+
+        let mut problems = Vec::new();
+        let mut registry = ArchetypeRegistry::default();
+
+        for tower in file.towers {
+            tower.validate(&mut problems);
+            if registry.contains(&tower.name) {
+                problems.push(format!("duplicate archetype name '{}'", tower.name));
+            }
+            registry.towers.insert(tower.name.clone(), tower);
+        }
+
+        for enemy in file.enemies {
+            enemy.validate(&mut problems);
+            if registry.contains(&enemy.name) {
+                problems.push(format!("duplicate archetype name '{}'", enemy.name));
+            }
+            registry.enemies.insert(enemy.name.clone(), enemy);
+        }
+
+        if !problems.is_empty() {
+            return Err(ArchetypeError::new(&problems.join("\n")));
+        }
+
+        Ok(registry)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.towers.contains_key(name) || self.enemies.contains_key(name)
+    }
+
+    pub fn tower(&self, name: &str) -> Option<&TowerArchetype> {
+        self.towers.get(name)
+    }
+
+    pub fn enemy(&self, name: &str) -> Option<&EnemyArchetype> {
+        self.enemies.get(name)
+    }
+
+    /// Enemy archetype names in sorted order.
+    pub fn enemy_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.enemies.keys().map(|name| name.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    /// Tower archetype names in sorted order.
+    pub fn tower_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.towers.keys().map(|name| name.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    /// Removes every tower archetype for which `keep` returns false, e.g. to
+    /// restrict a campaign level to the towers the player has unlocked.
+    pub fn retain_towers<F>(&mut self, mut keep: F)
+    where
+        F: FnMut(&str) -> bool,
+    {
+        self.towers.retain(|name, _| keep(name));
+    }
+
+    /// Changes every tower archetype in place, e.g. to apply campaign
+    /// upgrades.
+    pub fn update_towers<F>(&mut self, change: F)
+    where
+        F: FnMut(&mut TowerArchetype),
+    {
+        self.towers.values_mut().for_each(change);
+    }
+
+    /// Builds an entity for the named archetype, which may be either a tower
+    /// or an enemy. The entity's ID is assigned when it is added to the game.
+    pub fn instantiate(&self, name: &str, location: Point) -> Result<GameEntity, ArchetypeError> {
+        if let Some(tower) = self.tower(name) {
+            return Ok(tower.instantiate(location));
+        }
+        if let Some(enemy) = self.enemy(name) {
+            return Ok(enemy.instantiate(location));
+        }
+
+        Err(ArchetypeError::new(&format!(
+            "unknown archetype '{}'",
+            name
+        )))
+    }
+
+    /// Brings an existing entity's stats in line with its archetype, e.g.
+    /// after the archetypes have been reloaded. Current health, tower targets
+    /// and cooldowns, and enemy routes and statuses are kept. Returns false if
+    /// the archetype no longer exists.
+    pub fn refresh(&self, entity: &mut GameEntity) -> bool {
+        let name = match entity.archetype {
+            Some(ref name) => name.clone(),
+            None => return false,
+        };
+
+        if let Some(archetype) = self.tower(&name) {
+            let old = entity.tower.take();
+            let mut refreshed = archetype.instantiate(entity.location);
+            if let (Some(tower), Some(old)) = (refreshed.tower.as_mut(), old) {
+                tower.target = old.target;
+                tower.buffs = old.buffs;
+                tower.cooldown = old.cooldown.min(tower.fire_interval);
+            }
+            entity.shape = refreshed.shape;
+            entity.tower = refreshed.tower;
+            entity.sight = refreshed.sight;
+            return true;
+        }
+
+        if let Some(archetype) = self.enemy(&name) {
+            let old = entity.enemy.take();
+            let mut refreshed = archetype.instantiate(entity.location);
+            if let (Some(enemy), Some(old)) = (refreshed.enemy.as_mut(), old) {
+                enemy.path = old.path;
+                enemy.statuses = old.statuses;
+            }
+            entity.shape = refreshed.shape;
+            entity.enemy = refreshed.enemy;
+            // Wave scaling can put an enemy's health above its archetype's,
+            // so it's left alone
+            if entity.health.is_none() {
+                entity.health = refreshed.health;
+            }
+            return true;
+        }
+
+        false
+    }
+}
+
+impl TowerArchetype {
+    pub fn instantiate(&self, location: Point) -> GameEntity {
+        let mut entity = GameEntity::new(0, GameEntityType::Structure, location);
+        entity.archetype = Some(self.name.clone());
+        entity.shape = Some(CollisionShape::Circle {
+            radius: self.radius,
+        });
+        entity.tower = Some(Tower {
+            range: self.range,
+            damage: self.damage,
+            damage_type: self.damage_type,
+            fire_interval: self.fire_interval,
+            cooldown: 0,
+            effects: self.effects.clone(),
+            abilities: self.abilities.clone(),
+            target: None,
+            buffs: Vec::new(),
+        });
+        entity.sight = Some(Sight {
+            range: self.sight_range.unwrap_or(self.range),
+            detects_stealth: self.abilities.iter().any(|a| a == DETECTOR_ABILITY),
+        });
+
+        entity
+    }
+}
+
+impl EnemyArchetype {
+    pub fn instantiate(&self, location: Point) -> GameEntity {
+        let mut entity = GameEntity::new(0, GameEntityType::Enemy, location);
+        entity.archetype = Some(self.name.clone());
+        entity.shape = Some(CollisionShape::Circle {
+            radius: self.radius,
+        });
+        entity.health = Some(self.health);
+        entity.enemy = Some(Enemy {
+            speed: self.speed,
+            bounty: self.bounty,
+            lives_cost: self.lives_cost,
+            damage: self.damage,
+            cooldown: 0,
+            stealth: self.abilities.iter().any(|a| a == STEALTH_ABILITY),
+            path: Vec::new(),
+            statuses: Vec::new(),
+        });
+
+        entity
+    }
+}
+
+/// The towers and enemies most tests play with. The integration tests and
+/// benchmarks load the same file.
+#[cfg(test)]
+pub(crate) fn test_archetypes() -> ArchetypeRegistry {
+    ArchetypeRegistry::from_json(include_str!("../../tests/fixtures/archetypes.json")).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::archetypes::*;
+
+    const ARCHETYPES: &str = r#"{
+        "towers": [
+            {
+                "name": "cannon",
+                "model": "cannon",
+                "cost": 20,
+                "range": 30,
+                "damage": 5,
+                "fire_interval": 30,
+                "effects": [{ "status": "stun", "duration": 10 }]
+            }
+        ],
+        "enemies": [
+            { "name": "grunt", "model": "grunt", "health": 10, "speed": 1 }
+        ]
+    }"#;
+
+    #[test]
+    fn load_and_instantiate() {
+        let registry = ArchetypeRegistry::from_json(ARCHETYPES).unwrap();
+
+        let cannon = registry.tower("cannon").unwrap();
+        assert_eq!(cannon.damage_type, DamageType::Physical);
+        assert_eq!(cannon.effects[0].status, "stun");
+
+        let grunt = registry.instantiate("grunt", Point::new(1, 2)).unwrap();
+        assert_eq!(grunt.entity_type, GameEntityType::Enemy);
+        assert_eq!(grunt.health, Some(10));
+        assert_eq!(grunt.archetype, Some("grunt".to_string()));
+
+        let tower = registry.instantiate("cannon", Point::new(1, 2)).unwrap();
+        let tower = tower.tower.unwrap();
+        assert_eq!(tower.range, 30);
+        assert_eq!(tower.effects[0].status, "stun");
+
+        assert!(registry.instantiate("dragon", Point::new(0, 0)).is_err());
+    }
+
+    #[test]
+    fn refreshing_keeps_scaled_health() {
+        let registry = ArchetypeRegistry::from_json(ARCHETYPES).unwrap();
+        let mut grunt = registry.instantiate("grunt", Point::new(1, 2)).unwrap();
+        grunt.health = Some(25);
+
+        let reloaded =
+            ArchetypeRegistry::from_json(&ARCHETYPES.replace("\"speed\": 1", "\"speed\": 4"))
+                .unwrap();
+        assert!(reloaded.refresh(&mut grunt));
+        assert_eq!(grunt.enemy.unwrap().speed, 4);
+        assert_eq!(grunt.health, Some(25));
+    }
+
+    #[test]
+    fn validation_reports_every_problem() {
+        let err = ArchetypeRegistry::from_json(
+            r#"{
+                "towers": [
+                    { "name": "bad", "model": "", "cost": 1, "range": 0, "damage": 1, "fire_interval": 1 }
+                ],
+                "enemies": [
+                    { "name": "bad", "model": "m", "health": 0, "speed": 1 }
+                ]
+            }"#,
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(err.contains("tower 'bad': model must not be empty"));
+        assert!(err.contains("tower 'bad': range must be greater than 0"));
+        assert!(err.contains("enemy 'bad': health must be greater than 0"));
+        assert!(err.contains("duplicate archetype name 'bad'"));
+    }
+
+    #[test]
+    fn malformed_json_is_an_error() {
+        let err = ArchetypeRegistry::from_json(r#"{ "towers": [ { "name": "x" } ] }"#)
+            .unwrap_err()
+            .to_string();
+
+        assert!(err.starts_with("invalid archetype file"));
+    }
+}
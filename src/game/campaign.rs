@@ -0,0 +1,393 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+
+use super::archetypes::ArchetypeRegistry;
+use super::archetypes::TowerArchetype;
+use super::profile::Difficulty;
+use super::synergy::Stat;
+use super::waves::WaveSet;
+use super::ActiveGame;
+use crate::serde_derive::{Deserialize, Serialize};
+
+pub const DEFAULT_CAMPAIGN: &str = "data/campaign.json";
+
+#[derive(Debug)]
+pub struct CampaignError {
+    details: String,
+}
+
+impl CampaignError {
+    fn new(msg: &str) -> CampaignError {
+        CampaignError {
+            details: msg.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for CampaignError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for CampaignError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+/// Something the player gets for completing a level.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum Reward {
+    /// Makes a tower archetype available for the rest of the campaign.
+    Tower(String),
+    /// Applies one of the campaign's upgrades for the rest of the campaign.
+    Upgrade(String),
+}
+
+/// A permanent boost to tower stats, handed out as a reward.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Upgrade {
+    pub name: String,
+    pub stat: Stat,
+    /// Added to the stat, as a percentage of its value.
+    pub percent: u32,
+    /// Tower archetypes which are upgraded. If empty, every tower is.
+    #[serde(default)]
+    pub targets: Vec<String>,
+}
+
+impl Upgrade {
+    pub fn apply(&self, tower: &mut TowerArchetype) {
+        if !self.targets.is_empty() && !self.targets.contains(&tower.name) {
+            return;
+        }
+
+        let multiplier = u64::from(100 + self.percent);
+        let boost =
+            |value: u32| (u64::from(value) * multiplier / 100).min(u64::from(u32::MAX)) as u32;
+        match self.stat {
+            Stat::Range => tower.range = boost(tower.range),
+            Stat::Damage => tower.damage = boost(tower.damage),
+            // Firing faster means a shorter interval, but never less than a tick
+            Stat::FireRate => {
+                tower.fire_interval =
+                    (u64::from(tower.fire_interval) * 100 / multiplier).max(1) as u32
+            }
+        }
+    }
+}
+
+/// Which towers a level may use and how they're upgraded. Kept with the game
+/// so it can be applied again when archetypes are reloaded.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LevelRules {
+    pub towers: BTreeSet<String>,
+    pub upgrades: Vec<Upgrade>,
+}
+
+impl LevelRules {
+    pub fn apply(&self, archetypes: &mut ArchetypeRegistry) {
+        archetypes.retain_towers(|name| self.towers.contains(name));
+        for upgrade in self.upgrades.iter() {
+            archetypes.update_towers(|tower| upgrade.apply(tower));
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Level {
+    pub id: String,
+    pub name: String,
+    /// Path to the level's map file. Levels without one are played on the
+    /// default map.
+    #[serde(default)]
+    pub map: Option<String>,
+    /// Path to the level's wave file.
+    pub waves: String,
+    #[serde(default = "default_difficulty")]
+    pub difficulty: Difficulty,
+    /// IDs of levels which must be completed before this one is unlocked.
+    #[serde(default)]
+    pub requires: Vec<String>,
+    #[serde(default)]
+    pub rewards: Vec<Reward>,
+}
+
+fn default_difficulty() -> Difficulty {
+    Difficulty::Normal
+}
+
+/// The player's progress through the campaign, stored in their profile.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CampaignProgress {
+    pub completed_levels: BTreeSet<String>,
+    pub unlocked_towers: BTreeSet<String>,
+    pub upgrades: BTreeSet<String>,
+}
+
+impl CampaignProgress {
+    pub fn has_completed(&self, level_id: &str) -> bool {
+        self.completed_levels.contains(level_id)
+    }
+
+    /// Marks a level as completed and hands out its rewards. Returns the
+    /// rewards which were new, so replaying a level gives nothing twice.
+    pub fn complete(&mut self, level: &Level) -> Vec<Reward> {
+        self.completed_levels.insert(level.id.clone());
+
+        let mut new_rewards = Vec::new();
+        for reward in level.rewards.iter() {
+            let new = match reward {
+                Reward::Tower(tower) => self.unlocked_towers.insert(tower.clone()),
+                Reward::Upgrade(upgrade) => self.upgrades.insert(upgrade.clone()),
+            };
+            if new {
+                new_rewards.push(reward.clone());
+            }
+        }
+
+        new_rewards
+    }
+}
+
+/// An ordered list of levels, played one after the other.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Campaign {
+    pub name: String,
+    /// Towers which are available from the very first level.
+    #[serde(default)]
+    pub starting_towers: Vec<String>,
+    /// Upgrades which levels can hand out as rewards.
+    #[serde(default)]
+    pub upgrades: Vec<Upgrade>,
+    pub levels: Vec<Level>,
+}
+
+impl Campaign {
+    pub fn load(path: &str, archetypes: &ArchetypeRegistry) -> Result<Campaign, Box<dyn Error>> {
+        let json = fs::read_to_string(path)?;
+        Campaign::from_json(&json, archetypes).map_err(|e| {
+            Box::new(CampaignError::new(&format!("{}: {}", path, e))) as Box<dyn Error>
+        })
+    }
+
+    pub fn from_json(
+        json: &str,
+        archetypes: &ArchetypeRegistry,
+    ) -> Result<Campaign, CampaignError> {
+        let campaign: Campaign = serde_json::from_str(json)
+            .map_err(|e| CampaignError::new(&format!("invalid campaign file: {}", e)))?;
+        campaign.validate(archetypes)?;
+
+        Ok(campaign)
+    }
+
+    /// Reports every problem found, one per line. Levels may only require
+    /// levels which come before them.
+    pub fn validate(&self, archetypes: &ArchetypeRegistry) -> Result<(), CampaignError> {
+        let mut problems = Vec::new();
+
+        for tower in self.starting_towers.iter() {
+            if archetypes.tower(tower).is_none() {
+                problems.push(format!("unknown starting tower '{}'", tower));
+            }
+        }
+        for upgrade in self.upgrades.iter() {
+            for tower in upgrade.targets.iter() {
+                if archetypes.tower(tower).is_none() {
+                    problems.push(format!(
+                        "upgrade '{}' targets unknown tower '{}'",
+                        upgrade.name, tower
+                    ));
+                }
+            }
+        }
+
+        let mut seen: Vec<&str> = Vec::new();
+        for level in self.levels.iter() {
+            let mut problem = |msg: &str| problems.push(format!("level '{}': {}", level.id, msg));
+
+            if seen.contains(&level.id.as_str()) {
+                problem("duplicate level id");
+            }
+            for required in level.requires.iter() {
+                if !seen.contains(&required.as_str()) {
+                    problem(&format!("requires unknown or later level '{}'", required));
+                }
+            }
+            for reward in level.rewards.iter() {
+                match reward {
+                    Reward::Tower(tower) => {
+                        if archetypes.tower(tower).is_none() {
+                            problem(&format!("rewards unknown tower '{}'", tower));
+                        }
+                    }
+                    Reward::Upgrade(upgrade) => {
+                        if self.upgrade(upgrade).is_none() {
+                            problem(&format!("rewards unknown upgrade '{}'", upgrade));
+                        }
+                    }
+                }
+            }
+
+            seen.push(&level.id);
+        }
+
+        if !problems.is_empty() {
+            return Err(CampaignError::new(&problems.join("\n")));
+        }
+
+        Ok(())
+    }
+
+    pub fn level(&self, id: &str) -> Option<&Level> {
+        self.levels.iter().find(|level| level.id == id)
+    }
+
+    pub fn upgrade(&self, name: &str) -> Option<&Upgrade> {
+        self.upgrades.iter().find(|upgrade| upgrade.name == name)
+    }
+
+    pub fn is_unlocked(&self, level: &Level, progress: &CampaignProgress) -> bool {
+        level
+            .requires
+            .iter()
+            .all(|required| progress.has_completed(required))
+    }
+
+    /// The first unlocked level which hasn't been completed yet, or `None`
+    /// once the campaign is finished.
+    pub fn next_level(&self, progress: &CampaignProgress) -> Option<&Level> {
+        self.levels
+            .iter()
+            .find(|level| !progress.has_completed(&level.id) && self.is_unlocked(level, progress))
+    }
+
+    /// Towers the player may build at this point in the campaign.
+    pub fn available_towers(&self, progress: &CampaignProgress) -> BTreeSet<String> {
+        self.starting_towers
+            .iter()
+            .chain(progress.unlocked_towers.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// The towers and upgrades the player has earned so far.
+    pub fn level_rules(&self, progress: &CampaignProgress) -> LevelRules {
+        LevelRules {
+            towers: self.available_towers(progress),
+            upgrades: progress
+                .upgrades
+                .iter()
+                .filter_map(|name| self.upgrade(name))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Loads a level's map and waves and starts it, with only the towers the
+    /// player has unlocked so far available, and upgraded.
+    pub fn start_level(
+        &self,
+        level: &Level,
+        archetypes: &ArchetypeRegistry,
+        progress: &CampaignProgress,
+    ) -> Result<ActiveGame, Box<dyn Error>> {
+        let map = match level.map {
+            Some(ref path) => super::load_map(path)?,
+            None => super::get_default_map()?,
+        };
+
+        let rules = self.level_rules(progress);
+        let mut archetypes = archetypes.clone();
+        rules.apply(&mut archetypes);
+
+        let waves = WaveSet::load(&level.waves, &archetypes)?;
+
+        let mut game = super::start_game(map, archetypes, waves);
+        game.level_rules = Some(rules);
+        Ok(game)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::archetypes::test_archetypes;
+    use crate::game::campaign::*;
+
+    const CAMPAIGN: &str = r#"{
+        "name": "Test Campaign",
+        "starting_towers": ["arrow"],
+        "upgrades": [{ "name": "sharper_arrows", "stat": "Damage", "percent": 50, "targets": ["arrow"] }],
+        "levels": [
+            { "id": "one", "name": "One", "map": "one.bin", "waves": "one.json",
+              "rewards": [{ "Tower": "frost" }] },
+            { "id": "two", "name": "Two", "map": "two.bin", "waves": "two.json",
+              "requires": ["one"], "rewards": [{ "Upgrade": "sharper_arrows" }] }
+        ]
+    }"#;
+
+    #[test]
+    fn levels_unlock_in_order() {
+        let campaign = Campaign::from_json(CAMPAIGN, &test_archetypes()).unwrap();
+        let mut progress = CampaignProgress::default();
+
+        let two = campaign.level("two").unwrap();
+        assert!(!campaign.is_unlocked(two, &progress));
+        assert_eq!(campaign.next_level(&progress).unwrap().id, "one");
+        assert!(!campaign.available_towers(&progress).contains("frost"));
+
+        let rewards = progress.complete(campaign.level("one").unwrap());
+        assert_eq!(rewards, vec![Reward::Tower("frost".to_string())]);
+        assert!(campaign.is_unlocked(two, &progress));
+        assert_eq!(campaign.next_level(&progress).unwrap().id, "two");
+        assert!(campaign.available_towers(&progress).contains("frost"));
+
+        // Replaying a level doesn't hand out its rewards again
+        assert!(progress.complete(campaign.level("one").unwrap()).is_empty());
+
+        progress.complete(two);
+        assert!(progress.upgrades.contains("sharper_arrows"));
+        assert!(campaign.next_level(&progress).is_none());
+    }
+
+    #[test]
+    fn level_rules_restrict_and_upgrade_towers() {
+        let campaign = Campaign::from_json(CAMPAIGN, &test_archetypes()).unwrap();
+        let mut progress = CampaignProgress::default();
+        progress.complete(campaign.level("two").unwrap());
+
+        let mut archetypes = test_archetypes();
+        campaign.level_rules(&progress).apply(&mut archetypes);
+        assert_eq!(archetypes.tower_names(), vec!["arrow"]);
+        assert_eq!(archetypes.tower("arrow").unwrap().damage, 3);
+    }
+
+    #[test]
+    fn invalid_campaigns_are_rejected() {
+        let err = Campaign::from_json(
+            r#"{
+                "name": "Bad",
+                "starting_towers": ["cannon"],
+                "levels": [
+                    { "id": "one", "name": "One", "map": "m", "waves": "w", "requires": ["two"] },
+                    { "id": "two", "name": "Two", "map": "m", "waves": "w", "rewards": [{ "Tower": "laser" }, { "Upgrade": "armour" }] },
+                    { "id": "two", "name": "Two", "map": "m", "waves": "w" }
+                ]
+            }"#,
+            &test_archetypes(),
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(err.contains("unknown starting tower 'cannon'"));
+        assert!(err.contains("level 'one': requires unknown or later level 'two'"));
+        assert!(err.contains("level 'two': rewards unknown tower 'laser'"));
+        assert!(err.contains("level 'two': rewards unknown upgrade 'armour'"));
+        assert!(err.contains("level 'two': duplicate level id"));
+    }
+}
@@ -0,0 +1,184 @@
+use std::time::Duration;
+
+use crate::serde_derive::{Deserialize, Serialize};
+
+/// Number of simulation ticks per second of game time at normal speed.
+pub const TICKS_PER_SECOND: u32 = 30;
+
+/// Cap on how many ticks a single update may run, so that a long hitch
+/// (e.g. dragging the window) doesn't make the simulation try to catch up
+/// forever.
+const MAX_TICKS_PER_UPDATE: u32 = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum GameSpeed {
+    Normal,
+    Double,
+    Quadruple,
+}
+
+impl GameSpeed {
+    pub fn multiplier(self) -> u32 {
+        match self {
+            GameSpeed::Normal => 1,
+            GameSpeed::Double => 2,
+            GameSpeed::Quadruple => 4,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ClockCommand {
+    Pause,
+    Resume,
+    TogglePause,
+    SetSpeed(GameSpeed),
+    /// Runs exactly one tick. Only has an effect while paused.
+    Step,
+}
+
+/// Converts real time into simulation ticks, taking the game speed and
+/// pause state into account. Ticks are always a fixed length so the
+/// simulation itself doesn't care how fast it is being run.
+#[derive(Debug)]
+pub struct SimulationClock {
+    speed: GameSpeed,
+    paused: bool,
+    accumulator: Duration,
+    pending_steps: u32,
+}
+
+impl Default for SimulationClock {
+    fn default() -> SimulationClock {
+        SimulationClock::new()
+    }
+}
+
+impl SimulationClock {
+    pub fn new() -> SimulationClock {
+        SimulationClock {
+            speed: GameSpeed::Normal,
+            paused: false,
+            accumulator: Duration::from_secs(0),
+            pending_steps: 0,
+        }
+    }
+
+    pub fn tick_duration() -> Duration {
+        Duration::from_secs(1) / TICKS_PER_SECOND
+    }
+
+    pub fn speed(&self) -> GameSpeed {
+        self.speed
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn apply(&mut self, command: ClockCommand) {
+        match command {
+            ClockCommand::Pause => self.paused = true,
+            ClockCommand::Resume => self.paused = false,
+            ClockCommand::TogglePause => self.paused = !self.paused,
+            ClockCommand::SetSpeed(speed) => self.speed = speed,
+            ClockCommand::Step => {
+                if self.paused {
+                    self.pending_steps += 1;
+                }
+            }
+        }
+
+        if self.paused {
+            // Don't let time spent paused turn into a burst of ticks on resume
+            self.accumulator = Duration::from_secs(0);
+        } else {
+            // Steps only make sense while paused, don't run them on top of
+            // the normal ticks
+            self.pending_steps = 0;
+        }
+    }
+
+    /// Returns how many ticks should be simulated for `elapsed` real time.
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        if self.paused {
+            let steps = self.pending_steps;
+            self.pending_steps = 0;
+            return steps;
+        }
+
+        self.accumulator += elapsed * self.speed.multiplier();
+
+        let tick = SimulationClock::tick_duration();
+        let mut ticks = 0;
+        while self.accumulator >= tick && ticks < MAX_TICKS_PER_UPDATE {
+            self.accumulator -= tick;
+            ticks += 1;
+        }
+
+        if ticks == MAX_TICKS_PER_UPDATE {
+            self.accumulator = Duration::from_secs(0);
+        }
+
+        ticks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::clock::*;
+
+    #[test]
+    fn speed_scales_ticks() {
+        let mut clock = SimulationClock::new();
+        let frame = Duration::from_millis(100);
+
+        assert_eq!(clock.advance(frame), 3);
+
+        clock.apply(ClockCommand::SetSpeed(GameSpeed::Quadruple));
+        assert_eq!(clock.advance(frame), 12);
+    }
+
+    #[test]
+    fn pause_and_step() {
+        let mut clock = SimulationClock::new();
+
+        clock.apply(ClockCommand::Step);
+        clock.apply(ClockCommand::Pause);
+        assert_eq!(clock.advance(Duration::from_secs(1)), 0);
+
+        clock.apply(ClockCommand::Step);
+        clock.apply(ClockCommand::Step);
+        assert_eq!(clock.advance(Duration::from_secs(1)), 2);
+        assert_eq!(clock.advance(Duration::from_secs(1)), 0);
+
+        clock.apply(ClockCommand::TogglePause);
+        assert!(!clock.is_paused());
+        assert_eq!(clock.advance(Duration::from_millis(100)), 3);
+    }
+
+    #[test]
+    fn steps_are_dropped_on_resume() {
+        let mut clock = SimulationClock::new();
+
+        clock.apply(ClockCommand::Pause);
+        clock.apply(ClockCommand::Step);
+        clock.apply(ClockCommand::Resume);
+        assert_eq!(clock.advance(Duration::from_millis(0)), 0);
+        clock.apply(ClockCommand::Pause);
+        assert_eq!(clock.advance(Duration::from_secs(1)), 0);
+
+        clock.apply(ClockCommand::Step);
+        clock.apply(ClockCommand::TogglePause);
+        clock.apply(ClockCommand::TogglePause);
+        assert_eq!(clock.advance(Duration::from_secs(1)), 0);
+    }
+
+    #[test]
+    fn long_frames_are_capped() {
+        let mut clock = SimulationClock::new();
+
+        assert_eq!(clock.advance(Duration::from_secs(10)), MAX_TICKS_PER_UPDATE);
+        assert_eq!(clock.advance(Duration::from_secs(0)), 0);
+    }
+}
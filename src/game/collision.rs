@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+
+use super::entities::{EntityId, GameEntityType};
+use super::GameState;
+use crate::geometry::Point;
+use crate::serde_derive::{Deserialize, Serialize};
+
+/// Collision shapes are centered on the entity's location.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum CollisionShape {
+    Circle { radius: u32 },
+    AxisAlignedBox { half_width: u32, half_height: u32 },
+}
+
+impl CollisionShape {
+    pub fn half_extents(self) -> (u32, u32) {
+        match self {
+            CollisionShape::Circle { radius } => (radius, radius),
+            CollisionShape::AxisAlignedBox {
+                half_width,
+                half_height,
+            } => (half_width, half_height),
+        }
+    }
+}
+
+/// A projectile hit reported by the collision pass.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Collision {
+    pub projectile: EntityId,
+    pub target: EntityId,
+    /// How far along this tick's movement the projectile made contact, from
+    /// 0.0 (where it started the tick) to 1.0 (where it ended it).
+    pub time_of_impact: f32,
+}
+
+fn to_f32(point: Point) -> (f32, f32) {
+    (*point.x() as f32, *point.y() as f32)
+}
+
+/// Earliest time in `[0, 1]` at which the segment `from -> to` comes within
+/// `radius` of `center`.
+fn sweep_circle(from: Point, to: Point, center: Point, radius: f32) -> Option<f32> {
+    let (fx, fy) = to_f32(from);
+    let (tx, ty) = to_f32(to);
+    let (cx, cy) = to_f32(center);
+
+    let (dx, dy) = (tx - fx, ty - fy);
+    let (mx, my) = (fx - cx, fy - cy);
+
+    let c = mx * mx + my * my - radius * radius;
+    if c <= 0.0 {
+        // Already overlapping at the start of the tick
+        return Some(0.0);
+    }
+
+    let a = dx * dx + dy * dy;
+    if a == 0.0 {
+        return None;
+    }
+
+    let b = 2.0 * (mx * dx + my * dy);
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let t = (-b - discriminant.sqrt()) / (2.0 * a);
+    if (0.0..=1.0).contains(&t) {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+/// Earliest time in `[0, 1]` at which the segment `from -> to` enters the
+/// box centered at `center`, using the slab method.
+fn sweep_box(from: Point, to: Point, center: Point, half_extents: (f32, f32)) -> Option<f32> {
+    let (fx, fy) = to_f32(from);
+    let (tx, ty) = to_f32(to);
+    let (cx, cy) = to_f32(center);
+
+    let mut t_enter: f32 = 0.0;
+    let mut t_exit: f32 = 1.0;
+
+    for &(start, delta, min, max) in [
+        (fx, tx - fx, cx - half_extents.0, cx + half_extents.0),
+        (fy, ty - fy, cy - half_extents.1, cy + half_extents.1),
+    ]
+    .iter()
+    {
+        if delta == 0.0 {
+            if start < min || start > max {
+                return None;
+            }
+        } else {
+            let t1 = (min - start) / delta;
+            let t2 = (max - start) / delta;
+            t_enter = t_enter.max(t1.min(t2));
+            t_exit = t_exit.min(t1.max(t2));
+
+            if t_enter > t_exit {
+                return None;
+            }
+        }
+    }
+
+    Some(t_enter)
+}
+
+/// Swept test of a moving shape against a stationary one. Returns the time of
+/// impact if the two touch at any point along the movement.
+///
+/// Circle against circle is exact. Any pairing involving a box treats both
+/// shapes as boxes, which is slightly generous at the corners but never
+/// misses a hit.
+pub fn sweep(
+    moving: CollisionShape,
+    from: Point,
+    to: Point,
+    target: CollisionShape,
+    target_location: Point,
+) -> Option<f32> {
+    match (moving, target) {
+        (CollisionShape::Circle { radius: r1 }, CollisionShape::Circle { radius: r2 }) => {
+            sweep_circle(from, to, target_location, r1 as f32 + r2 as f32)
+        }
+        _ => {
+            let (mw, mh) = moving.half_extents();
+            let (tw, th) = target.half_extents();
+            sweep_box(
+                from,
+                to,
+                target_location,
+                (mw as f32 + tw as f32, mh as f32 + th as f32),
+            )
+        }
+    }
+}
+
+/// Finds every projectile which hit an enemy during the last tick.
+///
+/// Projectiles are swept from where they were at the end of the previous pass
+/// to where they are now, so fast projectiles can't tunnel through small
+/// enemies. Each projectile hits at most one enemy: the first one it reached.
+pub fn detect_collisions(state: &GameState) -> Vec<Collision> {
+    let targets: HashMap<EntityId, (Point, CollisionShape)> = state
+        .entities
+        .iter()
+        .filter_map(|entity| {
+            let entity = entity.borrow();
+            match (entity.entity_type, entity.shape) {
+                (GameEntityType::Enemy, Some(shape)) => Some((entity.id, (entity.location, shape))),
+                _ => None,
+            }
+        })
+        .collect();
+
+    let max_extent = targets
+        .values()
+        .map(|(_, shape)| {
+            let (w, h) = shape.half_extents();
+            w.max(h)
+        })
+        .max()
+        .unwrap_or(0);
+
+    let mut collisions = Vec::new();
+
+    for entity in state.entities.iter() {
+        // Only lock projectiles for writing, other systems may be reading the
+        // rest
+        if entity.borrow().projectile.is_none() {
+            continue;
+        }
+        let mut entity = entity.borrow_mut();
+        let to = entity.location;
+        let shape = entity.shape.unwrap_or(CollisionShape::Circle { radius: 0 });
+
+        let from = match entity.projectile.as_mut() {
+            Some(projectile) => {
+                let from = projectile.previous_location;
+                projectile.previous_location = to;
+                from
+            }
+            None => continue,
+        };
+
+        // Everything the projectile could possibly have touched is within
+        // this distance of the middle of its path.
+        let middle = Point::new((from.x() / 2) + (to.x() / 2), (from.y() / 2) + (to.y() / 2));
+        let half_path = (from.distance_squared(to) as f64).sqrt() / 2.0;
+        let (w, h) = shape.half_extents();
+        // Doubled to cover box corners.
+        let search_radius = half_path.ceil() as u32 + 2 * (w.max(h) + max_extent) + 1;
+
+        let mut best: Option<Collision> = None;
+        for target_id in state.spatial.query_radius(middle, search_radius) {
+            let (target_location, target_shape) = match targets.get(&target_id) {
+                Some(target) => *target,
+                None => continue,
+            };
+
+            if let Some(t) = sweep(shape, from, to, target_shape, target_location) {
+                // query_radius returns IDs in order, so on a tie the lowest ID wins
+                let earlier = match best {
+                    Some(ref b) => t < b.time_of_impact,
+                    None => true,
+                };
+                if earlier {
+                    best = Some(Collision {
+                        projectile: entity.id,
+                        target: target_id,
+                        time_of_impact: t,
+                    });
+                }
+            }
+        }
+
+        if let Some(collision) = best {
+            collisions.push(collision);
+        }
+    }
+
+    collisions
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::collision::*;
+
+    #[test]
+    fn fast_projectiles_do_not_tunnel() {
+        let small = CollisionShape::Circle { radius: 1 };
+
+        // Starts and ends well clear of the target but passes straight
+        // through it.
+        let t = sweep(
+            small,
+            Point::new(0, 50),
+            Point::new(100, 50),
+            small,
+            Point::new(50, 50),
+        )
+        .unwrap();
+        assert!((t - 0.48).abs() < 0.001);
+
+        assert!(sweep(
+            small,
+            Point::new(0, 60),
+            Point::new(100, 60),
+            small,
+            Point::new(50, 50),
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn box_sweeps() {
+        let target = CollisionShape::AxisAlignedBox {
+            half_width: 5,
+            half_height: 2,
+        };
+        let point = CollisionShape::Circle { radius: 0 };
+
+        let t = sweep(
+            point,
+            Point::new(50, 0),
+            Point::new(50, 100),
+            target,
+            Point::new(50, 50),
+        )
+        .unwrap();
+        assert!((t - 0.48).abs() < 0.001);
+
+        // Stationary and inside
+        assert_eq!(
+            sweep(
+                point,
+                Point::new(52, 51),
+                Point::new(52, 51),
+                target,
+                Point::new(50, 50)
+            ),
+            Some(0.0)
+        );
+
+        // Passes beside it
+        assert!(sweep(
+            point,
+            Point::new(60, 0),
+            Point::new(60, 100),
+            target,
+            Point::new(50, 50),
+        )
+        .is_none());
+    }
+}
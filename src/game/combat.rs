@@ -0,0 +1,504 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+
+use super::collision::Collision;
+use super::entities::{EntityId, GameEntityType, Projectile, Status};
+use super::schedule::SystemOutput;
+use super::towers::SPLASH_RADIUS;
+use super::GameState;
+use crate::serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum DamageType {
+    Physical,
+    Magic,
+    Fire,
+    Ice,
+    Poison,
+}
+
+/// Everything that happens in combat which we want to keep track of for
+/// balancing purposes.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub enum CombatEvent {
+    DamageDealt {
+        source: EntityId,
+        target: EntityId,
+        amount: u32,
+        damage_type: DamageType,
+    },
+    Kill {
+        source: EntityId,
+        target: EntityId,
+    },
+    StatusApplied {
+        source: EntityId,
+        target: EntityId,
+        status: String,
+    },
+    /// An enemy made it to the goal.
+    Leak {
+        target: EntityId,
+        lives_lost: u32,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CombatLogEntry {
+    pub tick: u32,
+    pub wave: u32,
+    pub event: CombatEvent,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct TowerStats {
+    pub damage_dealt: u64,
+    pub kills: u32,
+    pub status_applications: u32,
+    pub damage_by_type: HashMap<DamageType, u64>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct WaveStats {
+    pub damage_dealt: u64,
+    pub kills: u32,
+    pub leaks: u32,
+    pub lives_lost: u32,
+}
+
+/// Aggregated statistics, kept up to date as events are appended to the log
+/// so querying them mid-game is cheap.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct CombatStats {
+    /// Keyed by the source entity of the damage, which is a tower
+    /// in nearly every case.
+    pub per_tower: HashMap<EntityId, TowerStats>,
+    pub per_wave: HashMap<u32, WaveStats>,
+    pub per_damage_type: HashMap<DamageType, u64>,
+}
+
+impl CombatStats {
+    fn apply(&mut self, entry: &CombatLogEntry) {
+        let wave = self.per_wave.entry(entry.wave).or_default();
+
+        match entry.event {
+            CombatEvent::DamageDealt {
+                source,
+                amount,
+                damage_type,
+                ..
+            } => {
+                let tower = self.per_tower.entry(source).or_default();
+                tower.damage_dealt += u64::from(amount);
+                *tower.damage_by_type.entry(damage_type).or_insert(0) += u64::from(amount);
+
+                wave.damage_dealt += u64::from(amount);
+                *self.per_damage_type.entry(damage_type).or_insert(0) += u64::from(amount);
+            }
+            CombatEvent::Kill { source, .. } => {
+                self.per_tower.entry(source).or_default().kills += 1;
+                wave.kills += 1;
+            }
+            CombatEvent::StatusApplied { source, .. } => {
+                self.per_tower
+                    .entry(source)
+                    .or_default()
+                    .status_applications += 1;
+            }
+            CombatEvent::Leak { lives_lost, .. } => {
+                wave.leaks += 1;
+                wave.lives_lost += lives_lost;
+            }
+        }
+    }
+
+    pub fn tower(&self, id: EntityId) -> Option<&TowerStats> {
+        self.per_tower.get(&id)
+    }
+
+    pub fn wave(&self, wave: u32) -> Option<&WaveStats> {
+        self.per_wave.get(&wave)
+    }
+
+    pub fn damage_of_type(&self, damage_type: DamageType) -> u64 {
+        *self.per_damage_type.get(&damage_type).unwrap_or(&0)
+    }
+}
+
+/// Append-only log of combat events. Entries can't be removed or modified once
+/// recorded.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct CombatLog {
+    entries: Vec<CombatLogEntry>,
+    stats: CombatStats,
+}
+
+impl CombatLog {
+    pub fn new() -> CombatLog {
+        CombatLog::default()
+    }
+
+    pub fn record(&mut self, tick: u32, wave: u32, event: CombatEvent) {
+        let entry = CombatLogEntry { tick, wave, event };
+        self.stats.apply(&entry);
+        self.entries.push(entry);
+    }
+
+    pub fn entries(&self) -> &[CombatLogEntry] {
+        &self.entries
+    }
+
+    pub fn stats(&self) -> &CombatStats {
+        &self.stats
+    }
+
+    /// Writes the full log along with the aggregated statistics to `path`
+    /// as JSON.
+    pub fn export_json(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let mut file = File::create(path)?;
+        let encoded = serde_json::to_string_pretty(self)?;
+        file.write_all(encoded.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+/// The damage system. Applies projectile hits reported by the collision pass
+/// to their targets, and to the enemies around them for splash damage, and
+/// logs the results.
+///
+/// Spent projectiles and anything killed are queued for despawning, and the
+/// bounties of killed enemies are paid out.
+pub fn apply_collisions(state: &GameState, output: &mut SystemOutput, collisions: &[Collision]) {
+    for collision in collisions {
+        if state.commands.is_despawning(collision.target)
+            || output.commands.is_despawning(collision.target)
+        {
+            // Already killed by an earlier projectile this tick
+            continue;
+        }
+
+        let (projectile, target) = match (
+            state.entity(collision.projectile),
+            state.entity(collision.target),
+        ) {
+            (Some(projectile), Some(target)) => (projectile, target),
+            _ => continue,
+        };
+
+        let hit = match projectile.borrow().projectile {
+            Some(ref hit) => hit.clone(),
+            None => continue,
+        };
+        output.commands.despawn(collision.projectile);
+
+        let mut victims = vec![collision.target];
+        if hit.splash {
+            let center = target.borrow().location;
+            for id in state.spatial.query_radius(center, SPLASH_RADIUS) {
+                let entity_type = state.entity(id).map(|entity| entity.borrow().entity_type);
+                if id != collision.target && entity_type == Some(GameEntityType::Enemy) {
+                    victims.push(id);
+                }
+            }
+        }
+        for victim in victims {
+            damage(state, output, &hit, victim);
+        }
+    }
+}
+
+/// Deals a projectile's damage to a single entity. Enemies which survive it
+/// also get its status effects, which replace any of the same kind.
+fn damage(state: &GameState, output: &mut SystemOutput, hit: &Projectile, target: EntityId) {
+    if state.commands.is_despawning(target) || output.commands.is_despawning(target) {
+        return;
+    }
+    let target = match state.entity(target) {
+        Some(target) => target,
+        None => return,
+    };
+
+    let mut target = target.borrow_mut();
+    if !target.can_take_damage() {
+        return;
+    }
+    // Entities without health can't be hurt
+    let health = match target.health {
+        Some(health) => health,
+        None => return,
+    };
+    let amount = hit.damage.min(health);
+    target.health = Some(health - amount);
+
+    output.events.push(CombatEvent::DamageDealt {
+        source: hit.source,
+        target: target.id,
+        amount,
+        damage_type: hit.damage_type,
+    });
+
+    if health - amount == 0 {
+        output.events.push(CombatEvent::Kill {
+            source: hit.source,
+            target: target.id,
+        });
+        output.gold += target.enemy.as_ref().map_or(0, |enemy| enemy.bounty);
+        // Heroes aren't despawned here, the hero system handles their
+        // deaths so they can respawn
+        if target.hero.is_none() {
+            output.commands.despawn(target.id);
+        }
+        return;
+    }
+
+    let id = target.id;
+    let enemy = match target.enemy.as_mut() {
+        Some(enemy) => enemy,
+        None => return,
+    };
+    for effect in hit.effects.iter() {
+        enemy.statuses.retain(|status| status.name != effect.status);
+        enemy.statuses.push(Status {
+            name: effect.status.clone(),
+            source: hit.source,
+            remaining: effect.duration,
+            magnitude: effect.magnitude,
+        });
+        output.events.push(CombatEvent::StatusApplied {
+            source: hit.source,
+            target: id,
+            status: effect.status.clone(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::archetypes::Effect;
+    use crate::game::combat::*;
+    use crate::game::entities::{Enemy, GameEntity};
+    use crate::geometry::Point;
+
+    #[test]
+    fn stats_aggregation() {
+        let mut log = CombatLog::new();
+
+        log.record(
+            1,
+            1,
+            CombatEvent::DamageDealt {
+                source: 1,
+                target: 10,
+                amount: 5,
+                damage_type: DamageType::Fire,
+            },
+        );
+        log.record(
+            2,
+            1,
+            CombatEvent::DamageDealt {
+                source: 1,
+                target: 10,
+                amount: 7,
+                damage_type: DamageType::Physical,
+            },
+        );
+        log.record(
+            2,
+            1,
+            CombatEvent::Kill {
+                source: 1,
+                target: 10,
+            },
+        );
+        log.record(
+            3,
+            2,
+            CombatEvent::Leak {
+                target: 11,
+                lives_lost: 2,
+            },
+        );
+
+        assert_eq!(log.entries().len(), 4);
+
+        let tower = log.stats().tower(1).unwrap();
+        assert_eq!(tower.damage_dealt, 12);
+        assert_eq!(tower.kills, 1);
+        assert_eq!(tower.damage_by_type[&DamageType::Fire], 5);
+
+        assert_eq!(log.stats().wave(1).unwrap().kills, 1);
+        assert_eq!(log.stats().wave(2).unwrap().lives_lost, 2);
+        assert_eq!(log.stats().damage_of_type(DamageType::Physical), 7);
+        assert_eq!(log.stats().damage_of_type(DamageType::Ice), 0);
+        assert!(log.stats().tower(2).is_none());
+    }
+
+    #[test]
+    fn stats_serialize_to_json() {
+        let mut log = CombatLog::new();
+        log.record(
+            1,
+            1,
+            CombatEvent::StatusApplied {
+                source: 3,
+                target: 4,
+                status: "slow".to_string(),
+            },
+        );
+
+        let json = serde_json::to_string(&log).unwrap();
+        let decoded: CombatLog = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(decoded.entries()[0].event, log.entries()[0].event);
+        assert_eq!(decoded.stats().tower(3).unwrap().status_applications, 1);
+    }
+
+    #[test]
+    fn splash_damages_enemies_around_the_one_hit() {
+        let mut state = GameState::new(Vec::new());
+        let enemy = |state: &mut GameState, x| {
+            let mut enemy = GameEntity::new(0, GameEntityType::Enemy, Point::new(x, 10));
+            enemy.health = Some(10);
+            state.add_entity(enemy)
+        };
+        let hit = enemy(&mut state, 20);
+        let near = enemy(&mut state, 24);
+        let far = enemy(&mut state, 40);
+
+        let mut projectile = GameEntity::new(0, GameEntityType::Projectile, Point::new(20, 10));
+        projectile.projectile = Some(Projectile {
+            source: 0,
+            damage: 3,
+            damage_type: DamageType::Fire,
+            effects: Vec::new(),
+            splash: true,
+            target: None,
+            speed: 0,
+            previous_location: Point::new(10, 10),
+        });
+        let projectile = state.add_entity(projectile);
+
+        let mut output = SystemOutput::new();
+        let collision = Collision {
+            projectile,
+            target: hit,
+            time_of_impact: 1.0,
+        };
+        apply_collisions(&state, &mut output, &[collision]);
+
+        let health = |id| state.entity(id).unwrap().borrow().health;
+        assert_eq!(health(hit), Some(7));
+        assert_eq!(health(near), Some(7));
+        assert_eq!(health(far), Some(10));
+        assert_eq!(output.events.len(), 2);
+    }
+
+    #[test]
+    fn surviving_enemies_get_status_effects() {
+        let mut state = GameState::new(Vec::new());
+        let mut enemy = GameEntity::new(0, GameEntityType::Enemy, Point::new(20, 10));
+        enemy.health = Some(10);
+        enemy.enemy = Some(Enemy {
+            speed: 1,
+            bounty: 0,
+            lives_cost: 1,
+            damage: 0,
+            cooldown: 0,
+            stealth: false,
+            path: Vec::new(),
+            statuses: Vec::new(),
+        });
+        let enemy = state.add_entity(enemy);
+
+        let slow = Effect {
+            status: "slow".to_string(),
+            duration: 30,
+            magnitude: 50,
+        };
+        let hit = |state: &mut GameState, damage| {
+            let mut projectile = GameEntity::new(0, GameEntityType::Projectile, Point::new(20, 10));
+            projectile.projectile = Some(Projectile {
+                source: 7,
+                damage,
+                damage_type: DamageType::Ice,
+                effects: vec![slow.clone()],
+                splash: false,
+                target: None,
+                speed: 0,
+                previous_location: Point::new(10, 10),
+            });
+            let projectile = state.add_entity(projectile);
+
+            let mut output = SystemOutput::new();
+            let collision = Collision {
+                projectile,
+                target: enemy,
+                time_of_impact: 1.0,
+            };
+            apply_collisions(state, &mut output, &[collision]);
+            output.events
+        };
+
+        let events = hit(&mut state, 4);
+        assert_eq!(
+            events[1],
+            CombatEvent::StatusApplied {
+                source: 7,
+                target: enemy,
+                status: "slow".to_string(),
+            }
+        );
+        // Hitting again refreshes the slow rather than stacking it
+        hit(&mut state, 4);
+        let statuses = state
+            .entity(enemy)
+            .unwrap()
+            .borrow()
+            .enemy
+            .clone()
+            .unwrap()
+            .statuses;
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].remaining, 30);
+        assert_eq!(statuses[0].source, 7);
+
+        // Nothing is applied to enemies which die
+        let events = hit(&mut state, 4);
+        assert_eq!(events.len(), 2);
+    }
+
+    #[test]
+    fn entities_without_health_are_not_hurt() {
+        let mut state = GameState::new(Vec::new());
+        let target = state.spawn_entity(GameEntityType::Enemy, Point::new(20, 10));
+
+        let mut projectile = GameEntity::new(0, GameEntityType::Projectile, Point::new(20, 10));
+        projectile.projectile = Some(Projectile {
+            source: 0,
+            damage: 3,
+            damage_type: DamageType::Physical,
+            effects: Vec::new(),
+            splash: false,
+            target: None,
+            speed: 0,
+            previous_location: Point::new(10, 10),
+        });
+        let projectile = state.add_entity(projectile);
+
+        let mut output = SystemOutput::new();
+        let collision = Collision {
+            projectile,
+            target,
+            time_of_impact: 1.0,
+        };
+        apply_collisions(&state, &mut output, &[collision]);
+
+        assert!(output.events.is_empty());
+        assert!(output.commands.is_despawning(projectile));
+        assert!(!output.commands.is_despawning(target));
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashSet;
+
+use super::entities::{EntityId, GameEntity};
+
+/// Spawns and despawns requested while systems are iterating over the game
+/// state. Nothing happens until `GameState::apply_commands` is called at the
+/// end of the tick, so entities never disappear out from under a system.
+#[derive(Debug, Default)]
+pub struct CommandBuffer {
+    spawns: Vec<GameEntity>,
+    despawns: Vec<EntityId>,
+    /// The same IDs as `despawns`, for quick lookups.
+    despawning: HashSet<EntityId>,
+}
+
+impl CommandBuffer {
+    pub fn new() -> CommandBuffer {
+        CommandBuffer::default()
+    }
+
+    /// Queues an entity to be added. It is given a fresh ID when applied.
+    pub fn spawn(&mut self, entity: GameEntity) {
+        self.spawns.push(entity);
+    }
+
+    /// Queues an entity for removal. Despawning the same entity more than
+    /// once in a tick is harmless.
+    pub fn despawn(&mut self, id: EntityId) {
+        if self.despawning.insert(id) {
+            self.despawns.push(id);
+        }
+    }
+
+    pub fn is_despawning(&self, id: EntityId) -> bool {
+        self.despawning.contains(&id)
+    }
+
+    /// Queues everything from `other` after what's already queued.
+    pub fn append(&mut self, other: CommandBuffer) {
+        self.spawns.extend(other.spawns);
+        for id in other.despawns {
+            self.despawn(id);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spawns.is_empty() && self.despawns.is_empty()
+    }
+
+    /// Hands back everything queued so far, in the order it was queued, and
+    /// leaves the buffer empty.
+    pub fn take(&mut self) -> (Vec<GameEntity>, Vec<EntityId>) {
+        self.despawning.clear();
+        (
+            self.spawns.drain(..).collect(),
+            self.despawns.drain(..).collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::command_buffer::*;
+    use crate::game::entities::GameEntityType;
+    use crate::geometry::Point;
+
+    fn entity(x: u32) -> GameEntity {
+        GameEntity::new(0, GameEntityType::Enemy, Point::new(x, 0))
+    }
+
+    #[test]
+    fn duplicate_despawns_are_queued_once() {
+        let mut commands = CommandBuffer::new();
+        commands.despawn(3);
+        commands.despawn(1);
+        commands.despawn(3);
+
+        assert!(commands.is_despawning(3));
+        assert!(!commands.is_despawning(2));
+        assert_eq!(commands.take().1, vec![3, 1]);
+    }
+
+    #[test]
+    fn appended_commands_come_after_queued_ones() {
+        let mut commands = CommandBuffer::new();
+        commands.spawn(entity(1));
+        commands.despawn(5);
+
+        let mut other = CommandBuffer::new();
+        other.spawn(entity(2));
+        other.despawn(4);
+        other.despawn(5);
+        commands.append(other);
+
+        assert!(commands.is_despawning(4));
+        let (spawns, despawns) = commands.take();
+        let spawned: Vec<Point> = spawns.iter().map(|entity| entity.location).collect();
+        assert_eq!(spawned, vec![Point::new(1, 0), Point::new(2, 0)]);
+        assert_eq!(despawns, vec![5, 4]);
+    }
+
+    #[test]
+    fn taking_empties_the_buffer() {
+        let mut commands = CommandBuffer::new();
+        commands.spawn(entity(1));
+        commands.despawn(7);
+        assert!(!commands.is_empty());
+
+        let (spawns, despawns) = commands.take();
+        assert_eq!((spawns.len(), despawns.len()), (1, 1));
+        assert!(commands.is_empty());
+        assert!(!commands.is_despawning(7));
+        assert_eq!(commands.take().1, Vec::<EntityId>::new());
+    }
+}
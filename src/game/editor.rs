@@ -0,0 +1,780 @@
+//! Editing maps, and the waves played on them, from inside the client.
+//!
+//! Every change is a `MapEdit`. Applying one gives back the edit which
+//! reverts it, so everything since the editor was opened can be undone and
+//! redone. The map is validated again once editing pauses, and new problems
+//! are logged as they're made.
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::archetypes::ArchetypeRegistry;
+use super::entities::Zone;
+use super::map::GameMap;
+use super::terrain::Terrain;
+use super::tiles::Tile;
+use super::validation::{self, MapReport};
+use super::waves::{SpawnGroup, Wave, WaveSet};
+use crate::geometry::{BoundingBox, Point, Polygon};
+
+/// The order the terrain tool cycles through.
+const TERRAINS: [Terrain; 5] = [
+    Terrain::Grass,
+    Terrain::Road,
+    Terrain::Mud,
+    Terrain::Water,
+    Terrain::Cliff,
+];
+
+/// How long editing has to pause before the map is validated again. Checking
+/// every route on a large map is too slow to do on every click.
+const VALIDATION_DELAY: Duration = Duration::from_millis(300);
+
+/// Enemies in a group added with the enemy tool, and ticks between them.
+const NEW_GROUP_SIZE: u32 = 5;
+const NEW_GROUP_INTERVAL: u32 = 10;
+
+#[derive(Debug)]
+pub struct EditorError {
+    details: String,
+}
+
+impl EditorError {
+    fn new(msg: &str) -> EditorError {
+        EditorError {
+            details: msg.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for EditorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for EditorError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+/// What clicking on the map does.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EditorTool {
+    /// Paints the tile clicked on.
+    Terrain(Terrain),
+    /// Adds a spawn point, or removes the one clicked on.
+    Spawn,
+    /// Adds a goal, or removes the one clicked on.
+    Goal,
+    /// Two clicks mark opposite corners of a new zone. Clicking inside a
+    /// zone removes it.
+    Zone,
+    /// Adds a group of these enemies to the selected wave.
+    Enemy(String),
+}
+
+/// A single undoable change to the map or its waves.
+#[derive(Debug, Clone)]
+pub enum MapEdit {
+    SetTile {
+        x: u32,
+        y: u32,
+        tile: Tile,
+    },
+    AddSpawn(Point),
+    RemoveSpawn(Point),
+    AddGoal(Point),
+    RemoveGoal(Point),
+    AddZone {
+        index: usize,
+        zone: Zone,
+    },
+    RemoveZone {
+        index: usize,
+    },
+    AddWave {
+        index: usize,
+        wave: Wave,
+    },
+    RemoveWave {
+        index: usize,
+    },
+    SetWave {
+        index: usize,
+        wave: Wave,
+    },
+    /// Edits which are undone and redone together.
+    Batch(Vec<MapEdit>),
+}
+
+/// Editor input, the editing counterpart of `GameCommand`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EditorCommand {
+    /// Uses the current tool at a point on the map.
+    Click(Point),
+    NextTerrain,
+    SpawnTool,
+    GoalTool,
+    ZoneTool,
+    NextEnemy,
+    /// Adds an empty wave after the selected one and selects it.
+    AddWave,
+    RemoveWave,
+    PreviousWave,
+    NextWave,
+    Undo,
+    Redo,
+    Save,
+}
+
+#[derive(Debug)]
+pub struct MapEditor {
+    map: GameMap,
+    waves: WaveSet,
+    archetypes: ArchetypeRegistry,
+    map_path: String,
+    waves_path: String,
+    pub tool: EditorTool,
+    /// Index of the wave the enemy tool adds to.
+    pub wave: usize,
+    /// First corner of a zone being drawn.
+    zone_corner: Option<Point>,
+    undo: Vec<MapEdit>,
+    redo: Vec<MapEdit>,
+    report: MapReport,
+    /// When the map last changed, if it's changed since it was validated.
+    unvalidated_since: Option<Instant>,
+    /// Whether anything has changed since the last save.
+    modified: bool,
+}
+
+impl MapEditor {
+    /// Edits `map` and `waves`, which are saved back to the given paths.
+    pub fn new(
+        map: GameMap,
+        waves: WaveSet,
+        archetypes: ArchetypeRegistry,
+        map_path: &str,
+        waves_path: &str,
+    ) -> MapEditor {
+        let report = validation::validate_map(&map, Some(&waves), &archetypes);
+        for problem in report.problems.iter() {
+            warn!("{}", problem);
+        }
+
+        MapEditor {
+            map,
+            waves,
+            archetypes,
+            map_path: map_path.to_string(),
+            waves_path: waves_path.to_string(),
+            tool: EditorTool::Terrain(Terrain::Road),
+            wave: 0,
+            zone_corner: None,
+            undo: Vec::new(),
+            redo: Vec::new(),
+            report,
+            unvalidated_since: None,
+            modified: false,
+        }
+    }
+
+    pub fn map(&self) -> &GameMap {
+        &self.map
+    }
+
+    pub fn waves(&self) -> &WaveSet {
+        &self.waves
+    }
+
+    /// Problems found the last time the map was validated.
+    pub fn report(&self) -> &MapReport {
+        &self.report
+    }
+
+    /// Whether the map has changed since it was last validated.
+    pub fn is_validated(&self) -> bool {
+        self.unvalidated_since.is_none()
+    }
+
+    /// Validates the map once editing has paused. Called every frame.
+    pub fn update(&mut self) {
+        if let Some(changed) = self.unvalidated_since {
+            if changed.elapsed() >= VALIDATION_DELAY {
+                self.validate();
+            }
+        }
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Carries out editor input, logging anything which can't be done.
+    pub fn execute_command(&mut self, command: EditorCommand) {
+        let result = match command {
+            EditorCommand::Click(point) => self.click(point),
+            EditorCommand::NextTerrain => {
+                let next = match self.tool {
+                    EditorTool::Terrain(terrain) => {
+                        let i = TERRAINS.iter().position(|t| *t == terrain).unwrap_or(0);
+                        TERRAINS[(i + 1) % TERRAINS.len()]
+                    }
+                    _ => TERRAINS[0],
+                };
+                self.select_tool(EditorTool::Terrain(next));
+                Ok(())
+            }
+            EditorCommand::SpawnTool => {
+                self.select_tool(EditorTool::Spawn);
+                Ok(())
+            }
+            EditorCommand::GoalTool => {
+                self.select_tool(EditorTool::Goal);
+                Ok(())
+            }
+            EditorCommand::ZoneTool => {
+                self.select_tool(EditorTool::Zone);
+                Ok(())
+            }
+            EditorCommand::NextEnemy => self.next_enemy(),
+            EditorCommand::AddWave => {
+                let index = (self.wave + 1).min(self.waves.len());
+                let result = self.apply(MapEdit::AddWave {
+                    index,
+                    wave: Wave::default(),
+                });
+                if result.is_ok() {
+                    self.select_wave(index);
+                }
+                result
+            }
+            EditorCommand::RemoveWave => self.apply(MapEdit::RemoveWave { index: self.wave }),
+            EditorCommand::PreviousWave => {
+                self.select_wave(self.wave.saturating_sub(1));
+                Ok(())
+            }
+            EditorCommand::NextWave => {
+                self.select_wave(self.wave + 1);
+                Ok(())
+            }
+            EditorCommand::Undo => self.undo(),
+            EditorCommand::Redo => self.redo(),
+            EditorCommand::Save => match self.save() {
+                Ok(()) => {
+                    info!("Saved {} and {}", self.map_path, self.waves_path);
+                    Ok(())
+                }
+                Err(e) => Err(EditorError::new(&format!("Unable to save: {}", e))),
+            },
+        };
+
+        if let Err(e) = result {
+            info!("Can't edit map: {}", e);
+        }
+    }
+
+    pub fn select_tool(&mut self, tool: EditorTool) {
+        info!("Editor tool: {:?}", tool);
+        self.tool = tool;
+        self.zone_corner = None;
+    }
+
+    /// Selects a wave, as long as it exists or would be the next one added.
+    pub fn select_wave(&mut self, wave: usize) {
+        self.wave = wave.min(self.waves.len());
+        info!("Editing wave {} of {}", self.wave + 1, self.waves.len());
+    }
+
+    fn next_enemy(&mut self) -> Result<(), EditorError> {
+        let names = self.archetypes.enemy_names();
+        let next = match self.tool {
+            EditorTool::Enemy(ref current) => {
+                let i = names.iter().position(|name| name == current);
+                names.get(i.map_or(0, |i| (i + 1) % names.len()))
+            }
+            _ => names.first(),
+        };
+
+        match next.map(|name| name.to_string()) {
+            Some(name) => {
+                self.select_tool(EditorTool::Enemy(name));
+                Ok(())
+            }
+            None => Err(EditorError::new("There are no enemies to add")),
+        }
+    }
+
+    /// Uses the current tool at `point`.
+    pub fn click(&mut self, point: Point) -> Result<(), EditorError> {
+        match self.tool.clone() {
+            EditorTool::Terrain(terrain) => self.paint(point, terrain),
+            EditorTool::Spawn => {
+                let edit = match self.near(&self.map.spawns, point) {
+                    Some(spawn) => MapEdit::RemoveSpawn(spawn),
+                    None => MapEdit::AddSpawn(point),
+                };
+                self.apply(edit)
+            }
+            EditorTool::Goal => {
+                let edit = match self.near(&self.map.goals, point) {
+                    Some(goal) => MapEdit::RemoveGoal(goal),
+                    None => MapEdit::AddGoal(point),
+                };
+                self.apply(edit)
+            }
+            EditorTool::Zone => self.mark_zone(point),
+            EditorTool::Enemy(archetype) => {
+                let group = SpawnGroup {
+                    archetype,
+                    count: NEW_GROUP_SIZE,
+                    location: point,
+                    delay: 0,
+                    interval: NEW_GROUP_INTERVAL,
+                    health_percent: 100,
+                };
+                let edit = match self.waves.waves.get(self.wave) {
+                    Some(wave) => {
+                        let mut wave = wave.clone();
+                        wave.groups.push(group);
+                        MapEdit::SetWave {
+                            index: self.wave,
+                            wave,
+                        }
+                    }
+                    None => MapEdit::AddWave {
+                        index: self.waves.len(),
+                        wave: Wave {
+                            groups: vec![group],
+                        },
+                    },
+                };
+                self.apply(edit)
+            }
+        }
+    }
+
+    /// Sets the terrain of the tile under `point`, leaving its other layers
+    /// alone.
+    pub fn paint(&mut self, point: Point, terrain: Terrain) -> Result<(), EditorError> {
+        let (x, y) = match self.map.tiles.cell_at(point) {
+            Some(cell) => cell,
+            None => return Err(EditorError::new("There's no tile there")),
+        };
+        let mut tile = self.map.tiles.tile_at(point);
+        if tile.terrain == terrain {
+            return Ok(());
+        }
+        tile.terrain = terrain;
+
+        self.apply(MapEdit::SetTile { x, y, tile })
+    }
+
+    /// Sets the terrain of every tile in `area` as a single edit.
+    pub fn paint_area(&mut self, area: BoundingBox, terrain: Terrain) -> Result<(), EditorError> {
+        let tiles = &self.map.tiles;
+        let mut edits = Vec::new();
+        for y in 0..tiles.height() {
+            for x in 0..tiles.width() {
+                let tile = match tiles.tile(x, y) {
+                    Some(tile) if tile.terrain != terrain => tile,
+                    _ => continue,
+                };
+                if area.contains(tiles.center(x, y)) {
+                    let tile = Tile { terrain, ..tile };
+                    edits.push(MapEdit::SetTile { x, y, tile });
+                }
+            }
+        }
+        if edits.is_empty() {
+            return Ok(());
+        }
+
+        self.apply(MapEdit::Batch(edits))
+    }
+
+    /// Spawn points and goals are small, so clicks within a tile of one
+    /// count as clicking on it.
+    fn near(&self, points: &[Point], point: Point) -> Option<Point> {
+        let reach = u64::from(self.map.tiles.tile_size());
+        points
+            .iter()
+            .cloned()
+            .filter(|p| p.distance_squared(point) <= reach * reach)
+            .min_by_key(|p| p.distance_squared(point))
+    }
+
+    fn mark_zone(&mut self, point: Point) -> Result<(), EditorError> {
+        let corner = match self.zone_corner.take() {
+            Some(corner) => corner,
+            None => {
+                let clicked = self
+                    .map
+                    .zones
+                    .iter()
+                    .rposition(|zone| zone.get_bounding_box().contains(point));
+                return match clicked {
+                    Some(index) => self.apply(MapEdit::RemoveZone { index }),
+                    None => {
+                        self.zone_corner = Some(point);
+                        Ok(())
+                    }
+                };
+            }
+        };
+
+        let lower_left = Point::new(*corner.x().min(point.x()), *corner.y().min(point.y()));
+        let upper_right = Point::new(*corner.x().max(point.x()), *corner.y().max(point.y()));
+        if lower_left.x() == upper_right.x() || lower_left.y() == upper_right.y() {
+            return Err(EditorError::new("Zones can't be empty"));
+        }
+
+        let mut number = self.map.zones.len() + 1;
+        while self
+            .map
+            .zones
+            .iter()
+            .any(|zone| zone.name == format!("zone {}", number))
+        {
+            number += 1;
+        }
+        let zone = Zone::new(
+            &format!("zone {}", number),
+            BoundingBox::new(lower_left, upper_right),
+        );
+
+        self.apply(MapEdit::AddZone {
+            index: self.map.zones.len(),
+            zone,
+        })
+    }
+
+    /// Makes a change which can be undone. Anything which was undone can no
+    /// longer be redone.
+    pub fn apply(&mut self, edit: MapEdit) -> Result<(), EditorError> {
+        let revert = self.perform(edit)?;
+        self.undo.push(revert);
+        self.redo.clear();
+        self.changed();
+
+        Ok(())
+    }
+
+    /// Reverts the most recent change. If that fails, the change stays
+    /// where it was.
+    pub fn undo(&mut self) -> Result<(), EditorError> {
+        let edit = match self.undo.pop() {
+            Some(edit) => edit,
+            None => return Err(EditorError::new("Nothing to undo")),
+        };
+        match self.perform(edit.clone()) {
+            Ok(redo) => self.redo.push(redo),
+            Err(e) => {
+                self.undo.push(edit);
+                return Err(e);
+            }
+        }
+        self.changed();
+
+        Ok(())
+    }
+
+    /// Makes the most recently undone change again. If that fails, it can
+    /// still be redone later.
+    pub fn redo(&mut self) -> Result<(), EditorError> {
+        let edit = match self.redo.pop() {
+            Some(edit) => edit,
+            None => return Err(EditorError::new("Nothing to redo")),
+        };
+        match self.perform(edit.clone()) {
+            Ok(undo) => self.undo.push(undo),
+            Err(e) => {
+                self.redo.push(edit);
+                return Err(e);
+            }
+        }
+        self.changed();
+
+        Ok(())
+    }
+
+    /// Writes the map and waves back to where they were loaded from.
+    pub fn save(&mut self) -> Result<(), Box<dyn Error>> {
+        self.map.save(&self.map_path)?;
+        self.waves.save(&self.waves_path)?;
+        self.modified = false;
+
+        Ok(())
+    }
+
+    fn changed(&mut self) {
+        self.modified = true;
+        self.wave = self.wave.min(self.waves.len());
+        self.unvalidated_since = Some(Instant::now());
+    }
+
+    /// Validates the map now, if it's changed since it last was.
+    pub fn validate(&mut self) -> &MapReport {
+        if self.unvalidated_since.take().is_none() {
+            return &self.report;
+        }
+
+        // Only mention problems as they come and go, not every one each time
+        let report = validation::validate_map(&self.map, Some(&self.waves), &self.archetypes);
+        for problem in report.problems.iter() {
+            if !self.report.problems.contains(problem) {
+                warn!("{}", problem);
+            }
+        }
+        for problem in self.report.problems.iter() {
+            if !report.problems.contains(problem) {
+                info!("Fixed: {}", problem);
+            }
+        }
+        self.report = report;
+        &self.report
+    }
+
+    /// Makes a change, returning the edit which reverts it. Nothing is
+    /// changed if it fails.
+    fn perform(&mut self, edit: MapEdit) -> Result<MapEdit, EditorError> {
+        let revert = match edit {
+            MapEdit::SetTile { x, y, tile } => match self.map.tiles.tile(x, y) {
+                Some(old) => {
+                    self.map.tiles.set_tile(x, y, tile);
+                    MapEdit::SetTile { x, y, tile: old }
+                }
+                None => return Err(EditorError::new("There's no tile there")),
+            },
+            MapEdit::AddSpawn(point) => {
+                self.map.spawns.push(point);
+                MapEdit::RemoveSpawn(point)
+            }
+            MapEdit::RemoveSpawn(point) => {
+                remove_point(&mut self.map.spawns, point, "spawn point")?;
+                MapEdit::AddSpawn(point)
+            }
+            MapEdit::AddGoal(point) => {
+                self.map.goals.push(point);
+                MapEdit::RemoveGoal(point)
+            }
+            MapEdit::RemoveGoal(point) => {
+                remove_point(&mut self.map.goals, point, "goal")?;
+                MapEdit::AddGoal(point)
+            }
+            MapEdit::AddZone { index, zone } => {
+                if index > self.map.zones.len() {
+                    return Err(EditorError::new("No such zone"));
+                }
+                self.map.zones.insert(index, zone);
+                MapEdit::RemoveZone { index }
+            }
+            MapEdit::RemoveZone { index } => {
+                if index >= self.map.zones.len() {
+                    return Err(EditorError::new("No such zone"));
+                }
+                let zone = self.map.zones.remove(index);
+                MapEdit::AddZone { index, zone }
+            }
+            MapEdit::AddWave { index, wave } => {
+                if index > self.waves.len() {
+                    return Err(EditorError::new("No such wave"));
+                }
+                self.waves.waves.insert(index, wave);
+                MapEdit::RemoveWave { index }
+            }
+            MapEdit::RemoveWave { index } => {
+                if index >= self.waves.len() {
+                    return Err(EditorError::new("No such wave"));
+                }
+                let wave = self.waves.waves.remove(index);
+                MapEdit::AddWave { index, wave }
+            }
+            MapEdit::SetWave { index, wave } => match self.waves.waves.get_mut(index) {
+                Some(old) => MapEdit::SetWave {
+                    index,
+                    wave: std::mem::replace(old, wave),
+                },
+                None => return Err(EditorError::new("No such wave")),
+            },
+            MapEdit::Batch(edits) => {
+                let mut reverts = Vec::with_capacity(edits.len());
+                for edit in edits {
+                    match self.perform(edit) {
+                        Ok(revert) => reverts.push(revert),
+                        Err(e) => {
+                            // Put back whatever was done before the failure
+                            for revert in reverts.into_iter().rev() {
+                                let _ = self.perform(revert);
+                            }
+                            return Err(e);
+                        }
+                    }
+                }
+                reverts.reverse();
+                MapEdit::Batch(reverts)
+            }
+        };
+
+        Ok(revert)
+    }
+}
+
+fn remove_point(points: &mut Vec<Point>, point: Point, what: &str) -> Result<(), EditorError> {
+    match points.iter().position(|p| *p == point) {
+        Some(i) => {
+            points.remove(i);
+            Ok(())
+        }
+        None => Err(EditorError::new(&format!(
+            "There's no {} at ({}, {})",
+            what,
+            point.x(),
+            point.y()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::archetypes::test_archetypes;
+    use crate::game::editor::*;
+    use crate::game::map::DEFAULT_MAP_DIMENSIONS;
+
+    fn editor() -> MapEditor {
+        let archetypes = test_archetypes();
+        let map = GameMap::new("Test Map", DEFAULT_MAP_DIMENSIONS);
+
+        MapEditor::new(map, WaveSet::default(), archetypes, "map.bin", "waves.json")
+    }
+
+    #[test]
+    fn edits_can_be_undone_and_redone() {
+        let mut editor = editor();
+        let point = Point::new(50, 50);
+
+        editor.paint(point, Terrain::Water).unwrap();
+        editor
+            .paint_area(
+                BoundingBox::new(Point::new(0, 0), Point::new(20, 20)),
+                Terrain::Mud,
+            )
+            .unwrap();
+        assert_eq!(editor.map().tiles.terrain_at(point), Terrain::Water);
+        assert_eq!(
+            editor.map().tiles.terrain_at(Point::new(5, 5)),
+            Terrain::Mud
+        );
+        assert!(editor.is_modified());
+
+        // The whole area goes back in one step
+        editor.undo().unwrap();
+        assert_eq!(
+            editor.map().tiles.terrain_at(Point::new(5, 5)),
+            Terrain::Grass
+        );
+        editor.undo().unwrap();
+        assert_eq!(editor.map().tiles.terrain_at(point), Terrain::Grass);
+        assert!(editor.undo().is_err());
+
+        editor.redo().unwrap();
+        assert_eq!(editor.map().tiles.terrain_at(point), Terrain::Water);
+
+        // Making a change drops what could be redone
+        editor.paint(point, Terrain::Cliff).unwrap();
+        assert!(!editor.can_redo());
+    }
+
+    #[test]
+    fn edits_which_fail_to_undo_are_kept() {
+        let mut editor = editor();
+        let spawn = Point::new(0, 50);
+        editor.apply(MapEdit::AddSpawn(spawn)).unwrap();
+
+        // Something other than the editor took the spawn point away
+        editor.map.spawns.clear();
+        assert!(editor.undo().is_err());
+        assert!(editor.can_undo());
+
+        editor.map.spawns.push(spawn);
+        editor.undo().unwrap();
+        assert!(editor.map().spawns.is_empty());
+
+        editor.apply(MapEdit::AddSpawn(spawn)).unwrap();
+        editor.apply(MapEdit::RemoveSpawn(spawn)).unwrap();
+        editor.undo().unwrap();
+        editor.map.spawns.clear();
+        assert!(editor.redo().is_err());
+        assert!(editor.can_redo());
+    }
+
+    #[test]
+    fn tools_edit_spawns_zones_and_waves() {
+        let mut editor = editor();
+
+        editor.select_tool(EditorTool::Spawn);
+        editor.click(Point::new(0, 50)).unwrap();
+        editor.select_tool(EditorTool::Goal);
+        editor.click(Point::new(98, 50)).unwrap();
+        assert_eq!(editor.map().spawns, vec![Point::new(0, 50)]);
+
+        // Clicking next to the spawn point removes it
+        editor.select_tool(EditorTool::Spawn);
+        editor.click(Point::new(2, 51)).unwrap();
+        assert!(editor.map().spawns.is_empty());
+        editor.undo().unwrap();
+
+        editor.select_tool(EditorTool::Zone);
+        editor.click(Point::new(60, 10)).unwrap();
+        editor.click(Point::new(40, 30)).unwrap();
+        let zone = editor.map().zones[0].get_bounding_box();
+        assert_eq!(zone.lower_left(), Point::new(40, 10));
+        assert_eq!(zone.upper_right(), Point::new(60, 30));
+
+        editor.execute_command(EditorCommand::NextEnemy);
+        assert_eq!(editor.tool, EditorTool::Enemy("grunt".to_string()));
+        editor.click(Point::new(0, 50)).unwrap();
+        editor.click(Point::new(0, 50)).unwrap();
+        editor.execute_command(EditorCommand::AddWave);
+        editor.click(Point::new(0, 50)).unwrap();
+        assert_eq!(editor.waves().len(), 2);
+        assert_eq!(editor.waves().waves[0].groups.len(), 2);
+        assert_eq!(editor.wave, 1);
+
+        editor.execute_command(EditorCommand::Undo);
+        editor.execute_command(EditorCommand::Undo);
+        assert_eq!(editor.waves().len(), 1);
+        assert!(editor.validate().is_valid());
+    }
+
+    #[test]
+    fn problems_are_found_as_the_map_changes() {
+        let mut editor = editor();
+        editor.apply(MapEdit::AddSpawn(Point::new(0, 50))).unwrap();
+        editor.apply(MapEdit::AddGoal(Point::new(98, 50))).unwrap();
+        assert!(!editor.is_validated());
+        assert!(editor.validate().is_valid());
+        assert!(editor.is_validated());
+
+        editor
+            .paint_area(
+                BoundingBox::new(Point::new(40, 0), Point::new(60, 110)),
+                Terrain::Cliff,
+            )
+            .unwrap();
+        assert!(!editor.validate().is_valid());
+
+        editor.undo().unwrap();
+        assert!(editor.validate().is_valid());
+    }
+}
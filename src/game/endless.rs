@@ -0,0 +1,154 @@
+//! Endless mode: once a map's authored waves run out, further waves are
+//! generated from the enemy archetypes, getting bigger and tougher each time.
+//!
+//! Waves draw their randomness from the game's rng, so a replay started from
+//! the same seed generates the same waves.
+use rand::Rng;
+
+use super::archetypes::ArchetypeRegistry;
+use super::waves::{SpawnGroup, Wave};
+use crate::geometry::Point;
+use crate::serde_derive::{Deserialize, Serialize};
+
+/// How a value grows with the number of endless waves played so far.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+pub enum Scaling {
+    /// `1 + per_wave * n`
+    Linear { per_wave: f64 },
+    /// `(1 + rate) ^ n`
+    Exponential { rate: f64 },
+}
+
+impl Scaling {
+    /// Multiplier for the `n`th endless wave, starting from 0.
+    pub fn multiplier(self, n: u32) -> f64 {
+        match self {
+            Scaling::Linear { per_wave } => 1.0 + per_wave * f64::from(n),
+            Scaling::Exponential { rate } => {
+                // Repeated multiplication rather than powf, which isn't
+                // guaranteed to give identical results on every platform
+                let mut multiplier = 1.0;
+                for _ in 0..n {
+                    multiplier *= 1.0 + rate;
+                }
+                multiplier
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct EndlessConfig {
+    /// Where generated enemies enter the map.
+    pub spawn_points: Vec<Point>,
+    /// Enemies in the first endless wave, before scaling.
+    pub base_count: u32,
+    pub count_scaling: Scaling,
+    pub health_scaling: Scaling,
+    /// Most enemy archetypes mixed into a single wave.
+    pub max_groups: u32,
+    /// Ticks between enemies in a group.
+    pub interval: u32,
+}
+
+impl Default for EndlessConfig {
+    fn default() -> EndlessConfig {
+        EndlessConfig {
+            spawn_points: vec![Point::new(0, 0)],
+            base_count: 10,
+            count_scaling: Scaling::Linear { per_wave: 0.2 },
+            health_scaling: Scaling::Exponential { rate: 0.1 },
+            max_groups: 2,
+            interval: 30,
+        }
+    }
+}
+
+impl EndlessConfig {
+    /// Generates the `n`th endless wave (counting from 0) from the enemy
+    /// archetypes, drawing its randomness from `rng`. Returns an empty wave if
+    /// there are no enemies to use.
+    pub fn generate_wave<R: Rng>(
+        &self,
+        n: u32,
+        archetypes: &ArchetypeRegistry,
+        rng: &mut R,
+    ) -> Wave {
+        let enemies = archetypes.enemy_names();
+        if enemies.is_empty() || self.spawn_points.is_empty() {
+            return Wave::default();
+        }
+
+        let count = (f64::from(self.base_count) * self.count_scaling.multiplier(n)).round();
+        let count = (count as u32).max(1);
+        let health_percent = (100.0 * self.health_scaling.multiplier(n)).round() as u32;
+
+        let groups = rng.gen_range(1, self.max_groups.max(1) + 1).min(count);
+        let interval = self.interval.max(1);
+
+        let mut wave = Wave::default();
+        for i in 0..groups {
+            // Spread the enemies as evenly as possible over the groups
+            let group_count = count / groups + if i < count % groups { 1 } else { 0 };
+            let archetype = enemies[rng.gen_range(0, enemies.len())];
+            let location = self.spawn_points[rng.gen_range(0, self.spawn_points.len())];
+
+            wave.groups.push(SpawnGroup {
+                archetype: archetype.to_string(),
+                count: group_count,
+                location,
+                delay: i * interval / groups,
+                interval,
+                health_percent,
+            });
+        }
+
+        wave
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::archetypes::test_archetypes;
+    use crate::game::endless::*;
+    use rand::SeedableRng;
+    use rand_pcg::Pcg32;
+
+    #[test]
+    fn scaling_formulas() {
+        assert_eq!(Scaling::Linear { per_wave: 0.5 }.multiplier(4), 3.0);
+        assert_eq!(Scaling::Exponential { rate: 1.0 }.multiplier(3), 8.0);
+        assert_eq!(Scaling::Exponential { rate: 1.0 }.multiplier(0), 1.0);
+    }
+
+    #[test]
+    fn waves_are_reproducible_and_scale() {
+        let config = EndlessConfig::default();
+        let archetypes = test_archetypes();
+
+        for n in 0..10 {
+            assert_eq!(
+                config.generate_wave(n, &archetypes, &mut Pcg32::seed_from_u64(42)),
+                config.generate_wave(n, &archetypes, &mut Pcg32::seed_from_u64(42))
+            );
+        }
+
+        let total = |wave: &Wave| wave.groups.iter().map(|g| g.count).sum::<u32>();
+        let first = config.generate_wave(0, &archetypes, &mut Pcg32::seed_from_u64(42));
+        let later = config.generate_wave(10, &archetypes, &mut Pcg32::seed_from_u64(42));
+        assert_eq!(total(&first), 10);
+        assert_eq!(total(&later), 30);
+        assert_eq!(first.groups[0].health_percent, 100);
+        assert_eq!(later.groups[0].health_percent, 259);
+
+        // Waves are drawn one after another from the game's rng
+        let mut first = Pcg32::seed_from_u64(1);
+        let mut second = Pcg32::seed_from_u64(2);
+        let seeds_differ = (0..10).any(|n| {
+            config.generate_wave(n, &archetypes, &mut first)
+                != config.generate_wave(n, &archetypes, &mut second)
+        });
+        assert!(seeds_differ);
+    }
+}
@@ -0,0 +1,304 @@
+//! Enemies walking to the map's goals, and the status effects slowing them
+//! down on the way.
+//!
+//! Each enemy plans a route to the nearest goal the first time it moves, and
+//! keeps to it, hitting any hero it passes. Enemies which make it to a goal
+//! cost the player lives and are removed.
+use super::clock::TICKS_PER_SECOND;
+use super::combat::{CombatEvent, CombatLog, DamageType};
+use super::entities::{EntityId, Status};
+use super::hero::step_towards;
+use super::pathfinding;
+use super::GameState;
+use crate::geometry::Point;
+
+/// Cuts an enemy's speed by its magnitude, as a percentage.
+pub const SLOW_STATUS: &str = "slow";
+/// Stops an enemy moving at all.
+pub const STUN_STATUS: &str = "stun";
+
+/// How close a hero has to be for an enemy to hit it.
+pub const ENEMY_ATTACK_RANGE: u32 = 4;
+/// Ticks between an enemy's attacks.
+pub const ENEMY_ATTACK_INTERVAL: u32 = TICKS_PER_SECOND;
+
+/// Wears statuses down, moves every enemy along its route and attacks heroes
+/// in reach.
+pub fn update_enemies(state: &mut GameState, log: &mut CombatLog) {
+    let enemies: Vec<EntityId> = state
+        .entities
+        .iter()
+        .filter(|entity| entity.borrow().enemy.is_some())
+        .map(|entity| entity.borrow().id)
+        .collect();
+
+    for id in enemies {
+        if state.commands.is_despawning(id) {
+            continue;
+        }
+        let entity = match state.entity(id) {
+            Some(entity) => entity,
+            None => continue,
+        };
+
+        let (location, needs_route) = {
+            let mut entity = entity.borrow_mut();
+            let location = entity.location;
+            let enemy = entity.enemy.as_mut().unwrap();
+            for status in enemy.statuses.iter_mut() {
+                status.remaining = status.remaining.saturating_sub(1);
+            }
+            enemy.statuses.retain(|status| status.remaining > 0);
+            (location, enemy.path.is_empty())
+        };
+
+        if needs_route {
+            let goal = match nearest(&state.goals, location) {
+                Some(goal) => goal,
+                None => continue,
+            };
+            // Enemies which can't find a way round head straight for the goal
+            let path = pathfinding::find_path(state, state.bounds, location, goal, 0)
+                .unwrap_or_else(|| vec![goal]);
+            entity.borrow_mut().enemy.as_mut().unwrap().path = path;
+        }
+
+        let (next, arrived, lives_cost) = {
+            let mut entity = entity.borrow_mut();
+            let enemy = entity.enemy.as_mut().unwrap();
+            let speed = slowed(
+                state.tiles.terrain_at(location).ground_speed(enemy.speed),
+                &enemy.statuses,
+            );
+            let next = step_towards(location, enemy.path[0], speed);
+            if next == enemy.path[0] {
+                enemy.path.remove(0);
+            }
+            (
+                next,
+                enemy.path.is_empty() && state.goals.contains(&next),
+                enemy.lives_cost,
+            )
+        };
+        state.set_entity_location(id, next);
+
+        if arrived {
+            log.record(
+                state.tick,
+                state.wave,
+                CombatEvent::Leak {
+                    target: id,
+                    lives_lost: lives_cost,
+                },
+            );
+            state.commands.despawn(id);
+            continue;
+        }
+
+        attack(state, log, id);
+    }
+}
+
+/// Hits the closest hero in reach, if the enemy is ready to attack.
+fn attack(state: &GameState, log: &mut CombatLog, id: EntityId) {
+    let entity = state.entity(id).unwrap();
+    let (location, damage) = {
+        let mut entity = entity.borrow_mut();
+        let location = entity.location;
+        let enemy = entity.enemy.as_mut().unwrap();
+        if enemy.cooldown > 0 {
+            enemy.cooldown -= 1;
+            return;
+        }
+        (location, enemy.damage)
+    };
+    if damage == 0 {
+        return;
+    }
+
+    let target = state
+        .spatial
+        .query_radius(location, ENEMY_ATTACK_RANGE)
+        .into_iter()
+        .filter(|&other| !state.commands.is_despawning(other))
+        .filter_map(|other| state.entity(other))
+        .filter(|other| {
+            let other = other.borrow();
+            // Dead heroes stay put until the hero system takes them away
+            other.hero.is_some() && other.health.unwrap_or(0) > 0
+        })
+        .min_by_key(|other| other.borrow().location.distance_squared(location));
+    let target = match target {
+        Some(target) => target,
+        None => return,
+    };
+
+    let mut target = target.borrow_mut();
+    let health = target.health.unwrap_or(0);
+    let amount = damage.min(health);
+    target.health = Some(health - amount);
+    log.record(
+        state.tick,
+        state.wave,
+        CombatEvent::DamageDealt {
+            source: id,
+            target: target.id,
+            amount,
+            damage_type: DamageType::Physical,
+        },
+    );
+    if health - amount == 0 {
+        log.record(
+            state.tick,
+            state.wave,
+            CombatEvent::Kill {
+                source: id,
+                target: target.id,
+            },
+        );
+    }
+
+    entity.borrow_mut().enemy.as_mut().unwrap().cooldown = ENEMY_ATTACK_INTERVAL;
+}
+
+fn nearest(points: &[Point], to: Point) -> Option<Point> {
+    points
+        .iter()
+        .cloned()
+        .min_by_key(|point| point.distance_squared(to))
+}
+
+/// An enemy's speed once its statuses are taken into account.
+fn slowed(speed: u32, statuses: &[Status]) -> u32 {
+    let mut speed = speed;
+    for status in statuses.iter() {
+        if status.name == STUN_STATUS {
+            return 0;
+        }
+        if status.name == SLOW_STATUS {
+            speed = speed * (100 - status.magnitude.min(100)) / 100;
+        }
+    }
+    speed
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::combat::CombatLog;
+    use crate::game::enemies::*;
+    use crate::game::entities::{Enemy, GameEntity, GameEntityType};
+    use crate::game::hero::{hero_id, spawn_hero, update_heroes};
+
+    fn enemy(state: &mut GameState, location: Point) -> EntityId {
+        let mut entity = GameEntity::new(0, GameEntityType::Enemy, location);
+        entity.enemy = Some(Enemy {
+            speed: 2,
+            bounty: 0,
+            lives_cost: 3,
+            damage: 0,
+            cooldown: 0,
+            stealth: false,
+            path: Vec::new(),
+            statuses: Vec::new(),
+        });
+        state.add_entity(entity)
+    }
+
+    fn status(name: &str, remaining: u32, magnitude: u32) -> Status {
+        Status {
+            name: name.to_string(),
+            source: 0,
+            remaining,
+            magnitude,
+        }
+    }
+
+    #[test]
+    fn enemies_leak_at_the_goal() {
+        let mut state = GameState::new(Vec::new());
+        state.goals = vec![Point::new(20, 10)];
+        let mut log = CombatLog::new();
+        let grunt = enemy(&mut state, Point::new(10, 10));
+
+        // Ten units away at two a tick
+        for _ in 0..4 {
+            update_enemies(&mut state, &mut log);
+        }
+        assert!(log.entries().is_empty());
+
+        for _ in 0..3 {
+            update_enemies(&mut state, &mut log);
+        }
+        assert!(state.commands.is_despawning(grunt));
+        assert_eq!(log.entries().len(), 1);
+        assert_eq!(
+            log.entries()[0].event,
+            CombatEvent::Leak {
+                target: grunt,
+                lives_lost: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn statuses_slow_enemies_until_they_wear_off() {
+        let mut state = GameState::new(Vec::new());
+        state.goals = vec![Point::new(90, 10)];
+        let mut log = CombatLog::new();
+        let grunt = enemy(&mut state, Point::new(10, 10));
+        {
+            let entity = state.entity(grunt).unwrap();
+            let mut entity = entity.borrow_mut();
+            let statuses = &mut entity.enemy.as_mut().unwrap().statuses;
+            statuses.push(status(STUN_STATUS, 2, 0));
+            statuses.push(status(SLOW_STATUS, 3, 50));
+        }
+        let x = |state: &GameState| *state.entity(grunt).unwrap().borrow().location.x();
+
+        update_enemies(&mut state, &mut log);
+        assert_eq!(x(&state), 10);
+        update_enemies(&mut state, &mut log);
+        assert_eq!(x(&state), 11);
+        update_enemies(&mut state, &mut log);
+        assert_eq!(x(&state), 13);
+    }
+
+    #[test]
+    fn enemies_hit_heroes_in_reach() {
+        let mut state = GameState::new(Vec::new());
+        state.goals = vec![Point::new(90, 10)];
+        let mut log = CombatLog::new();
+        let hero = spawn_hero(&mut state, Point::new(14, 10));
+        let grunt = enemy(&mut state, Point::new(10, 10));
+        state
+            .entity(grunt)
+            .unwrap()
+            .borrow_mut()
+            .enemy
+            .as_mut()
+            .unwrap()
+            .damage = 60;
+        let health = |state: &GameState| state.entity(hero).unwrap().borrow().health;
+
+        update_enemies(&mut state, &mut log);
+        assert_eq!(health(&state), Some(40));
+        for _ in 0..=ENEMY_ATTACK_INTERVAL {
+            // Keep the hero in the grunt's way
+            let location = state.entity(grunt).unwrap().borrow().location;
+            state.set_entity_location(hero, location);
+            update_enemies(&mut state, &mut log);
+        }
+        assert_eq!(health(&state), Some(0));
+        assert_eq!(
+            log.entries().last().unwrap().event,
+            CombatEvent::Kill {
+                source: grunt,
+                target: hero,
+            }
+        );
+
+        update_heroes(&mut state, &mut log);
+        state.apply_commands();
+        assert!(hero_id(&state).is_none());
+    }
+}
@@ -1,8 +1,26 @@
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak};
+
+use super::archetypes::Effect;
+use super::collision::CollisionShape;
+use super::combat::DamageType;
+use super::synergy::Buff;
 use crate::geometry::{self, Location};
+use crate::serde_derive::{Deserialize, Serialize};
+
+/// Identifies a single entity for the lifetime of a game. IDs are never reused,
+/// so they are safe to keep around in logs and statistics.
+pub type EntityId = u32;
+
+/// Identifies who an entity belongs to, e.g. for working out what they can
+/// see.
+pub type PlayerId = u32;
+
+/// The player at this computer, who owns every tower and hero for now.
+pub const LOCAL_PLAYER: PlayerId = 0;
 
 // Design decision: avoid embedding methods within entities --
 // we will go with a very poor version of ECS pattern (entity component system)
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum GameEntityType {
     Player,
     Enemy,
@@ -13,8 +31,169 @@ pub enum GameEntityType {
 
 #[derive(Debug)]
 pub struct GameEntity {
+    pub id: EntityId,
     pub location: geometry::Point,
     pub entity_type: GameEntityType,
+    pub owner: PlayerId,
+    /// Name of the archetype this entity was created from, if any.
+    pub archetype: Option<String>,
+    pub shape: Option<CollisionShape>,
+    pub health: Option<u32>,
+    pub projectile: Option<Projectile>,
+    pub tower: Option<Tower>,
+    pub enemy: Option<Enemy>,
+    pub hero: Option<Hero>,
+    pub sight: Option<Sight>,
+}
+
+/// Storage for a single entity which can be shared between threads, so
+/// systems which don't conflict can run at the same time. Borrowing works like
+/// `RefCell`, except a conflicting borrow blocks until the other one is
+/// released rather than panicking.
+#[derive(Debug)]
+pub struct EntityCell(RwLock<GameEntity>);
+
+impl EntityCell {
+    pub fn new(entity: GameEntity) -> EntityCell {
+        EntityCell(RwLock::new(entity))
+    }
+
+    pub fn borrow(&self) -> RwLockReadGuard<'_, GameEntity> {
+        self.0.read().expect("Entity lock poisoned")
+    }
+
+    pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, GameEntity> {
+        self.0.write().expect("Entity lock poisoned")
+    }
+}
+
+/// A non-owning reference to an entity. Holding a handle doesn't keep the
+/// entity alive, so anything that keeps track of another entity across ticks
+/// (like a tower's current target) should hold one of these and check that the
+/// entity is still around before using it.
+#[derive(Debug, Clone)]
+pub struct EntityHandle {
+    id: EntityId,
+    entity: Weak<EntityCell>,
+}
+
+impl EntityHandle {
+    pub fn new(entity: &Arc<EntityCell>) -> EntityHandle {
+        EntityHandle {
+            id: entity.borrow().id,
+            entity: Arc::downgrade(entity),
+        }
+    }
+
+    pub fn id(&self) -> EntityId {
+        self.id
+    }
+
+    /// Returns the entity if it hasn't been despawned.
+    pub fn upgrade(&self) -> Option<Arc<EntityCell>> {
+        self.entity.upgrade()
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.upgrade().is_some()
+    }
+}
+
+/// Component for structures which attack enemies in range. The stats here
+/// are base values; use the `effective_*` methods to include synergy buffs.
+#[derive(Debug, Clone)]
+pub struct Tower {
+    pub range: u32,
+    pub damage: u32,
+    pub damage_type: DamageType,
+    /// Ticks between shots.
+    pub fire_interval: u32,
+    /// Ticks until the tower can fire again.
+    pub cooldown: u32,
+    /// Status effects applied by the tower's shots.
+    pub effects: Vec<Effect>,
+    pub abilities: Vec<String>,
+    pub target: Option<EntityHandle>,
+    /// Buffs from nearby towers' auras, kept up to date by the synergy system.
+    pub buffs: Vec<Buff>,
+}
+
+/// Component for enemies walking towards the goal.
+#[derive(Debug, Clone)]
+pub struct Enemy {
+    /// World units moved per tick.
+    pub speed: u32,
+    pub bounty: u32,
+    pub lives_cost: u32,
+    /// Damage dealt to heroes in reach.
+    pub damage: u32,
+    /// Ticks until the enemy can attack again.
+    pub cooldown: u32,
+    /// Stealthy enemies can only be targeted within range of a detector.
+    pub stealth: bool,
+    /// Waypoints still to walk through on the way to a goal, nearest first.
+    pub path: Vec<geometry::Point>,
+    /// Status effects from towers' shots which haven't worn off yet.
+    pub statuses: Vec<Status>,
+}
+
+/// A status effect on an enemy, e.g. a slow.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Status {
+    pub name: String,
+    /// The tower which applied it.
+    pub source: EntityId,
+    /// Ticks until it wears off.
+    pub remaining: u32,
+    pub magnitude: u32,
+}
+
+/// Component for entities which reveal the map around them to their owner.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Sight {
+    pub range: u32,
+    /// Whether stealthy enemies in range are revealed too.
+    pub detects_stealth: bool,
+}
+
+/// Component for the player-controlled hero unit.
+#[derive(Debug, Clone)]
+pub struct Hero {
+    pub level: u32,
+    pub experience: u32,
+    pub max_health: u32,
+    /// World units moved per tick.
+    pub speed: u32,
+    pub attack_range: u32,
+    pub damage: u32,
+    /// Ticks between attacks.
+    pub attack_interval: u32,
+    /// Ticks until the hero can attack again.
+    pub cooldown: u32,
+    /// Where the hero comes back after dying.
+    pub spawn_point: geometry::Point,
+    /// Waypoints still to walk through, nearest first.
+    pub path: Vec<geometry::Point>,
+}
+
+/// Component for entities which fly towards enemies and damage the first one
+/// they hit.
+#[derive(Debug, Clone)]
+pub struct Projectile {
+    /// The tower (or other entity) which fired this projectile.
+    pub source: EntityId,
+    pub damage: u32,
+    pub damage_type: DamageType,
+    pub effects: Vec<Effect>,
+    /// Whether enemies around the one hit are damaged too.
+    pub splash: bool,
+    /// What the projectile flies after. Projectiles without a target don't
+    /// move by themselves.
+    pub target: Option<EntityHandle>,
+    /// World units moved per tick.
+    pub speed: u32,
+    /// Where the projectile was during the last collision pass.
+    pub previous_location: geometry::Point,
 }
 
 impl Location for GameEntity {
@@ -24,8 +203,24 @@ impl Location for GameEntity {
 }
 
 impl GameEntity {
-    #[allow(dead_code)]
-    fn can_take_damage(&self) -> bool {
+    pub fn new(id: EntityId, entity_type: GameEntityType, location: geometry::Point) -> GameEntity {
+        GameEntity {
+            id,
+            location,
+            entity_type,
+            owner: LOCAL_PLAYER,
+            archetype: None,
+            shape: None,
+            health: None,
+            projectile: None,
+            tower: None,
+            enemy: None,
+            hero: None,
+            sight: None,
+        }
+    }
+
+    pub fn can_take_damage(&self) -> bool {
         match self.entity_type {
             GameEntityType::Player => true,
             GameEntityType::Enemy => true,
@@ -36,11 +231,24 @@ impl GameEntity {
     }
 }
 
+/// A named region of the map. Closed zones can be used by map scripts to
+/// block off areas until some event happens.
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Zone {
+    pub name: String,
+    pub open: bool,
     bounding_box: geometry::BoundingBox,
 }
 
 impl Zone {
+    pub fn new(name: &str, bounding_box: geometry::BoundingBox) -> Zone {
+        Zone {
+            name: name.to_string(),
+            open: true,
+            bounding_box,
+        }
+    }
+
     pub fn entity_inside<'a>(&self, entity: &'a GameEntity) -> bool {
         entity.location.inside(self.get_bounding_box())
     }
@@ -57,14 +265,12 @@ mod tests {
 
     #[test]
     fn entities_tests() {
-        let player = GameEntity {
-            location: Point::new(5, 5),
-            entity_type: GameEntityType::Player,
-        };
-
-        let zone = Zone {
-            bounding_box: BoundingBox::new(Point::new(0, 0), Point::new(10, 10)),
-        };
+        let player = GameEntity::new(0, GameEntityType::Player, Point::new(5, 5));
+
+        let zone = Zone::new(
+            "test",
+            BoundingBox::new(Point::new(0, 0), Point::new(10, 10)),
+        );
 
         assert!(zone.entity_inside(&player));
     }
@@ -0,0 +1,30 @@
+//! Helpers for reading and writing the game's files.
+use std::fs;
+use std::io;
+
+/// Writes `contents` to a temporary file next to `path` first, then moves it
+/// into place, so a crash mid-save can't leave a truncated file behind.
+pub fn write_atomically<C: AsRef<[u8]>>(path: &str, contents: C) -> io::Result<()> {
+    let temp = format!("{}.tmp", path);
+    fs::write(&temp, contents)?;
+    fs::rename(&temp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::files::*;
+    use std::env;
+
+    #[test]
+    fn atomic_writes_replace_the_file() {
+        let path = env::temp_dir().join(format!("rtd_atomic_test_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, "old").unwrap();
+
+        write_atomically(path, "new").unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "new");
+        assert!(fs::metadata(format!("{}.tmp", path)).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+}
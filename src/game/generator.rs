@@ -0,0 +1,169 @@
+//! Procedural maps for endless and practice play.
+//!
+//! Lanes of road wind from spawn points on the left edge of the map to a
+//! single goal on the right edge, and obstacles are scattered over the rest.
+//! Obstacles never go on a lane, so every spawn can always reach the goal.
+//!
+//! Everything comes from a random stream seeded with the map seed, so the
+//! same seed and config always give the same map and maps can be shared by
+//! seed alone.
+use rand::Rng;
+use rand_pcg::Pcg32;
+
+use super::map::GameMap;
+use super::terrain::Terrain;
+use super::tiles::{Buildability, DEFAULT_TILE_SIZE};
+use crate::geometry::{BoundingBox, Point};
+use crate::serde_derive::{Deserialize, Serialize};
+
+/// Keeps map generation from sharing a random stream with anything else
+/// seeded from the same number.
+const MAP_STREAM: u64 = 0x6d61_7067_656e;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MapGenConfig {
+    /// Size of the map, in world units.
+    pub width: u32,
+    pub height: u32,
+    /// Number of separate routes from the left edge to the goal.
+    pub lanes: u32,
+    /// Chance, as a percentage, of a lane turning at each step.
+    pub winding: u32,
+    /// Percentage of tiles off the lanes covered by obstacles.
+    pub obstacle_density: u32,
+}
+
+impl Default for MapGenConfig {
+    fn default() -> MapGenConfig {
+        MapGenConfig {
+            width: 100,
+            height: 100,
+            lanes: 1,
+            winding: 30,
+            obstacle_density: 10,
+        }
+    }
+}
+
+/// Generates a map from `seed`.
+pub fn generate_map(seed: u64, config: &MapGenConfig) -> GameMap {
+    let mut rng = Pcg32::new(seed, MAP_STREAM);
+
+    let tile_size = DEFAULT_TILE_SIZE;
+    // Big enough for a lane plus room to build either side of it
+    let width = config.width.max(tile_size * 4);
+    let height = config.height.max(tile_size * 4);
+    let dimensions = BoundingBox::new(Point::new(0, 0), Point::new(width, height));
+    let mut map = GameMap::new(&format!("Generated {}", seed), dimensions);
+
+    // Only tiles whose centers are on the map are used
+    let columns = width / tile_size;
+    let rows = height / tile_size;
+    let lanes = config.lanes.max(1).min(rows / 2);
+    let winding = config.winding.min(100);
+
+    // Spawns and goals go on the lower left corner of their tile, which is
+    // where the pathfinding grid samples it
+    let corner = |x: u32, y: u32| Point::new(x * tile_size, y * tile_size);
+
+    let goal_row = rows / 2;
+    let mut road = vec![false; (columns * rows) as usize];
+    let mut lay_road = |x: u32, y: u32| {
+        road[(y * columns + x) as usize] = true;
+    };
+
+    for lane in 0..lanes {
+        let mut row = (lane + 1) * rows / (lanes + 1);
+        map.spawns.push(corner(0, row));
+
+        for column in 0..columns {
+            lay_road(column, row);
+            if column + 1 < columns && rng.gen_range(0, 100) < winding {
+                // Turn up or down for a few tiles, staying off the edges
+                let length = rng.gen_range(1, 4);
+                let up = rng.gen::<bool>();
+                for _ in 0..length {
+                    let next = if up { row + 1 } else { row.saturating_sub(1) };
+                    if next < 1 || next > rows - 2 {
+                        break;
+                    }
+                    row = next;
+                    lay_road(column, row);
+                }
+            }
+        }
+
+        // Lanes all finish at the goal
+        while row != goal_row {
+            row = if row < goal_row { row + 1 } else { row - 1 };
+            lay_road(columns - 1, row);
+        }
+    }
+    map.goals.push(corner(columns - 1, goal_row));
+
+    let density = config.obstacle_density.min(100);
+    for y in 0..rows {
+        for x in 0..columns {
+            if road[(y * columns + x) as usize] {
+                map.tiles.set_terrain(x, y, Terrain::Road);
+                map.tiles.set_buildable(x, y, Buildability::Never);
+            } else if rng.gen_range(0, 100) < density {
+                let obstacle = if rng.gen_range(0, 4) == 0 {
+                    Terrain::Water
+                } else {
+                    Terrain::Cliff
+                };
+                map.tiles.set_terrain(x, y, obstacle);
+            }
+        }
+    }
+
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::archetypes::test_archetypes;
+    use crate::game::generator::*;
+    use crate::game::map::MapFormat;
+    use crate::game::validation::validate_map;
+
+    #[test]
+    fn the_same_seed_gives_the_same_map() {
+        let config = MapGenConfig {
+            lanes: 3,
+            ..MapGenConfig::default()
+        };
+
+        let map = generate_map(42, &config);
+        let again = generate_map(42, &config);
+        let other = generate_map(43, &config);
+
+        assert_eq!(map.spawns.len(), 3);
+        assert_eq!(
+            map.to_bytes(MapFormat::Binary).unwrap(),
+            again.to_bytes(MapFormat::Binary).unwrap()
+        );
+        assert_ne!(map.tiles, other.tiles);
+    }
+
+    #[test]
+    fn generated_maps_are_valid() {
+        let archetypes = test_archetypes();
+
+        for seed in 0..20 {
+            let config = MapGenConfig {
+                width: 60 + seed as u32 * 10,
+                height: 80,
+                lanes: 1 + seed as u32 % 4,
+                winding: 60,
+                obstacle_density: 50,
+            };
+            let map = generate_map(seed, &config);
+
+            let report = validate_map(&map, None, &archetypes);
+            assert!(report.problems.is_empty(), "seed {}: {:?}", seed, report);
+        }
+    }
+}
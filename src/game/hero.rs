@@ -0,0 +1,363 @@
+//! The hero: a unit the player moves around the map by picking destinations.
+//! It walks there around any obstacles, attacks enemies which come within
+//! reach, gets stronger as it scores kills and comes back a while after dying.
+use super::clock::TICKS_PER_SECOND;
+use super::collision::CollisionShape;
+use super::combat::{CombatEvent, CombatLog, DamageType};
+use super::entities::{EntityId, GameEntity, GameEntityType, Hero, Sight};
+use super::pathfinding;
+use super::targeting;
+use super::GameState;
+use crate::geometry::Point;
+
+pub const HERO_RESPAWN_TICKS: u32 = 10 * TICKS_PER_SECOND;
+pub const HERO_RADIUS: u32 = 1;
+pub const HERO_SIGHT_RANGE: u32 = 24;
+/// Experience for every kill, on top of the enemy's bounty.
+pub const EXPERIENCE_PER_KILL: u32 = 10;
+
+/// A dead hero waiting to respawn. Its level and experience are kept.
+#[derive(Debug, Clone)]
+pub struct FallenHero {
+    pub hero: Hero,
+    /// Ticks until the hero comes back.
+    pub respawn_in: u32,
+}
+
+impl Hero {
+    pub fn new(spawn_point: Point) -> Hero {
+        Hero {
+            level: 1,
+            experience: 0,
+            max_health: 100,
+            speed: 2,
+            attack_range: 8,
+            damage: 3,
+            attack_interval: TICKS_PER_SECOND / 2,
+            cooldown: 0,
+            spawn_point,
+            path: Vec::new(),
+        }
+    }
+
+    pub fn experience_to_next_level(&self) -> u32 {
+        self.level * 100
+    }
+
+    /// Adds experience, levelling up as many times as it's enough for.
+    /// Returns the number of levels gained.
+    pub fn gain_experience(&mut self, amount: u32) -> u32 {
+        self.experience += amount;
+
+        let mut levels = 0;
+        while self.experience >= self.experience_to_next_level() {
+            self.experience -= self.experience_to_next_level();
+            self.level += 1;
+            self.max_health += 10;
+            self.damage += 1;
+            levels += 1;
+        }
+
+        levels
+    }
+}
+
+/// Builds a hero entity at full health.
+fn hero_entity(hero: Hero) -> GameEntity {
+    let mut entity = GameEntity::new(0, GameEntityType::Player, hero.spawn_point);
+    entity.shape = Some(CollisionShape::Circle {
+        radius: HERO_RADIUS,
+    });
+    entity.health = Some(hero.max_health);
+    entity.sight = Some(Sight {
+        range: HERO_SIGHT_RANGE,
+        detects_stealth: false,
+    });
+    entity.hero = Some(hero);
+
+    entity
+}
+
+pub fn spawn_hero(state: &mut GameState, location: Point) -> EntityId {
+    state.add_entity(hero_entity(Hero::new(location)))
+}
+
+/// The hero's current ID. This changes every time the hero respawns.
+pub fn hero_id(state: &GameState) -> Option<EntityId> {
+    state
+        .entities
+        .iter()
+        .map(|entity| entity.borrow())
+        .find(|entity| entity.hero.is_some())
+        .map(|entity| entity.id)
+}
+
+/// Sends the hero towards `destination`. Returns false if `id` isn't a hero or
+/// there is no way to get there, in which case the hero stays put.
+pub fn order_move(state: &GameState, id: EntityId, destination: Point) -> bool {
+    let entity = match state.entity(id) {
+        Some(entity) => entity,
+        None => return false,
+    };
+    let from = entity.borrow().location;
+    if entity.borrow().hero.is_none() {
+        return false;
+    }
+
+    let path = pathfinding::find_path(state, state.bounds, from, destination, HERO_RADIUS);
+    let mut entity = entity.borrow_mut();
+    let hero = entity.hero.as_mut().unwrap();
+    match path {
+        Some(path) => {
+            hero.path = path;
+            true
+        }
+        None => {
+            hero.path.clear();
+            false
+        }
+    }
+}
+
+/// One step of at most `speed` units from `from` towards `to`.
+pub fn step_towards(from: Point, to: Point, speed: u32) -> Point {
+    let dx = f64::from(*to.x()) - f64::from(*from.x());
+    let dy = f64::from(*to.y()) - f64::from(*from.y());
+    let distance = (dx * dx + dy * dy).sqrt();
+    if distance <= f64::from(speed) {
+        return to;
+    }
+
+    let scale = f64::from(speed) / distance;
+    Point::new(
+        (f64::from(*from.x()) + dx * scale).round() as u32,
+        (f64::from(*from.y()) + dy * scale).round() as u32,
+    )
+}
+
+/// Respawns, moves and attacks with every hero.
+pub fn update_heroes(state: &mut GameState, log: &mut CombatLog) {
+    respawn_heroes(state);
+
+    let heroes: Vec<EntityId> = state
+        .entities
+        .iter()
+        .map(|entity| entity.borrow())
+        .filter(|entity| entity.hero.is_some())
+        .map(|entity| entity.id)
+        .collect();
+
+    for id in heroes {
+        if state.commands.is_despawning(id) {
+            continue;
+        }
+        let entity = match state.entity(id) {
+            Some(entity) => entity,
+            None => continue,
+        };
+
+        if entity.borrow().health == Some(0) {
+            let hero = entity.borrow().hero.clone().unwrap();
+            info!("Hero died, respawning in {} ticks", HERO_RESPAWN_TICKS);
+            state.fallen_heroes.push(FallenHero {
+                hero,
+                respawn_in: HERO_RESPAWN_TICKS,
+            });
+            state.commands.despawn(id);
+            continue;
+        }
+
+        let moved_to = {
+            let mut entity = entity.borrow_mut();
+            let location = entity.location;
+            let hero = entity.hero.as_mut().unwrap();
+            if hero.path.is_empty() {
+                None
+            } else {
+                let speed = state.tiles.terrain_at(location).ground_speed(hero.speed);
+                let next = step_towards(location, hero.path[0], speed);
+                if next == hero.path[0] {
+                    hero.path.remove(0);
+                }
+                Some(next)
+            }
+        };
+        if let Some(location) = moved_to {
+            state.set_entity_location(id, location);
+        }
+
+        attack(state, log, id);
+    }
+}
+
+fn respawn_heroes(state: &mut GameState) {
+    let mut ready = Vec::new();
+    for fallen in state.fallen_heroes.iter_mut() {
+        fallen.respawn_in = fallen.respawn_in.saturating_sub(1);
+        if fallen.respawn_in == 0 {
+            ready.push(fallen.hero.clone());
+        }
+    }
+    state.fallen_heroes.retain(|fallen| fallen.respawn_in > 0);
+
+    for mut hero in ready {
+        hero.path.clear();
+        hero.cooldown = 0;
+        state.add_entity(hero_entity(hero));
+    }
+}
+
+/// Hits the closest enemy in reach, if the hero is ready to attack.
+fn attack(state: &mut GameState, log: &mut CombatLog, id: EntityId) {
+    let entity = state.entity(id).unwrap();
+    let (owner, location, range, damage) = {
+        let mut entity = entity.borrow_mut();
+        let owner = entity.owner;
+        let location = entity.location;
+        let hero = entity.hero.as_mut().unwrap();
+        if hero.cooldown > 0 {
+            hero.cooldown -= 1;
+            return;
+        }
+        (owner, location, hero.attack_range, hero.damage)
+    };
+
+    let target = match targeting::find_target(state, id, owner, location, range) {
+        Some(handle) => match handle.upgrade() {
+            Some(target) => target,
+            None => return,
+        },
+        None => return,
+    };
+    if state.commands.is_despawning(target.borrow().id) {
+        return;
+    }
+
+    let (target_id, killed, bounty) = {
+        let mut target = target.borrow_mut();
+        // Entities without health can't be hurt
+        let health = match target.health {
+            Some(health) => health,
+            None => return,
+        };
+        let amount = damage.min(health);
+        target.health = Some(health - amount);
+
+        log.record(
+            state.tick,
+            state.wave,
+            CombatEvent::DamageDealt {
+                source: id,
+                target: target.id,
+                amount,
+                damage_type: DamageType::Physical,
+            },
+        );
+
+        let bounty = target.enemy.as_ref().map_or(0, |enemy| enemy.bounty);
+        (target.id, health - amount == 0, bounty)
+    };
+
+    let mut entity = entity.borrow_mut();
+    let hero = entity.hero.as_mut().unwrap();
+    hero.cooldown = hero.attack_interval;
+
+    if killed {
+        log.record(
+            state.tick,
+            state.wave,
+            CombatEvent::Kill {
+                source: id,
+                target: target_id,
+            },
+        );
+        state.commands.despawn(target_id);
+        state.gold += bounty;
+
+        let levels = hero.gain_experience(EXPERIENCE_PER_KILL + bounty);
+        if levels > 0 {
+            info!("Hero reached level {}", hero.level);
+            entity.health = Some(hero.max_health);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::combat::CombatLog;
+    use crate::game::entities::Enemy;
+    use crate::game::hero::*;
+
+    #[test]
+    fn levelling_up() {
+        let mut hero = Hero::new(Point::new(0, 0));
+        assert_eq!(hero.gain_experience(50), 0);
+        assert_eq!(hero.gain_experience(350), 2);
+        assert_eq!(hero.level, 3);
+        assert_eq!(hero.experience, 100);
+        assert_eq!(hero.max_health, 120);
+    }
+
+    #[test]
+    fn heroes_walk_fight_and_respawn() {
+        let mut state = GameState::new(Vec::new());
+        let mut log = CombatLog::new();
+        let hero = spawn_hero(&mut state, Point::new(10, 10));
+
+        assert!(order_move(&state, hero, Point::new(30, 10)));
+        for _ in 0..20 {
+            update_heroes(&mut state, &mut log);
+        }
+        assert_eq!(
+            state.entity(hero).unwrap().borrow().location,
+            Point::new(30, 10)
+        );
+
+        let mut enemy = GameEntity::new(0, GameEntityType::Enemy, Point::new(34, 10));
+        enemy.health = Some(3);
+        enemy.enemy = Some(Enemy {
+            speed: 1,
+            bounty: 5,
+            lives_cost: 1,
+            damage: 0,
+            cooldown: 0,
+            stealth: false,
+            path: Vec::new(),
+            statuses: Vec::new(),
+        });
+        let enemy = state.add_entity(enemy);
+        let gold = state.gold;
+        update_heroes(&mut state, &mut log);
+        state.apply_commands();
+        assert!(state.entity(enemy).is_none());
+        assert_eq!(state.gold, gold + 5);
+        assert_eq!(
+            state
+                .entity(hero)
+                .unwrap()
+                .borrow()
+                .hero
+                .as_ref()
+                .unwrap()
+                .experience,
+            EXPERIENCE_PER_KILL + 5
+        );
+
+        state.entity(hero).unwrap().borrow_mut().health = Some(0);
+        update_heroes(&mut state, &mut log);
+        state.apply_commands();
+        assert!(hero_id(&state).is_none());
+
+        for _ in 0..HERO_RESPAWN_TICKS {
+            update_heroes(&mut state, &mut log);
+        }
+        let respawned = state.entity(hero_id(&state).unwrap()).unwrap();
+        let respawned = respawned.borrow();
+        assert_eq!(respawned.location, Point::new(10, 10));
+        assert_eq!(respawned.health, Some(100));
+        assert_eq!(
+            respawned.hero.as_ref().unwrap().experience,
+            EXPERIENCE_PER_KILL + 5
+        );
+    }
+}
@@ -0,0 +1,340 @@
+//! Undo and redo for the build phase between waves.
+//!
+//! Tower placements and sales made while no wave is running are recorded as
+//! reversible actions. Undoing a placement refunds the tower's full cost,
+//! rather than the usual sell price. Starting the next wave commits them, so
+//! nothing from before it can be undone.
+use std::error::Error;
+use std::fmt;
+
+use super::entities::EntityId;
+use super::map::GameMap;
+use super::placement;
+use super::synergy;
+use super::GameState;
+use crate::geometry::Point;
+
+#[derive(Debug)]
+pub struct HistoryError {
+    details: String,
+}
+
+impl HistoryError {
+    fn new(msg: &str) -> HistoryError {
+        HistoryError {
+            details: msg.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for HistoryError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuildAction {
+    PlaceTower {
+        archetype: String,
+        location: Point,
+        tower: EntityId,
+        cost: u32,
+    },
+    SellTower {
+        archetype: String,
+        location: Point,
+        tower: EntityId,
+        refund: u32,
+    },
+}
+
+impl BuildAction {
+    fn tower_mut(&mut self) -> &mut EntityId {
+        match self {
+            BuildAction::PlaceTower { tower, .. } => tower,
+            BuildAction::SellTower { tower, .. } => tower,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct BuildHistory {
+    undo: Vec<BuildAction>,
+    redo: Vec<BuildAction>,
+}
+
+impl BuildHistory {
+    pub fn new() -> BuildHistory {
+        BuildHistory::default()
+    }
+
+    /// Adds an action which has just been done. Anything which was undone
+    /// can no longer be redone.
+    pub fn record(&mut self, action: BuildAction) {
+        self.undo.push(action);
+        self.redo.clear();
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Makes everything done so far permanent.
+    pub fn commit(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    /// Reverts the most recent action. If that fails the action is kept, so
+    /// it can be tried again. Sold towers are only bought back if there's
+    /// still room for them.
+    pub fn undo(&mut self, state: &mut GameState, map: &GameMap) -> Result<(), HistoryError> {
+        let action = match self.undo.pop() {
+            Some(action) => action,
+            None => return Err(HistoryError::new("Nothing to undo")),
+        };
+
+        let result = match action {
+            BuildAction::PlaceTower { tower, cost, .. } => {
+                if state.entity(tower).is_none() {
+                    Err(HistoryError::new("The tower is gone"))
+                } else {
+                    state.gold += cost;
+                    state.remove_entity(tower);
+                    synergy::update_synergies(state);
+                    Ok(None)
+                }
+            }
+            BuildAction::SellTower {
+                ref archetype,
+                location,
+                refund,
+                ..
+            } => {
+                if state.gold < refund {
+                    Err(HistoryError::new(&format!(
+                        "Not enough gold to buy back {} ({} needed, {} available)",
+                        archetype, refund, state.gold
+                    )))
+                } else {
+                    match state.archetypes.tower(archetype) {
+                        Some(tower) => match placement::check_site(state, map, tower, location) {
+                            Ok(()) => {
+                                let entity = tower.instantiate(location);
+                                state.gold -= refund;
+                                let id = state.add_entity(entity);
+                                synergy::update_synergies(state);
+                                Ok(Some(id))
+                            }
+                            Err(e) => Err(HistoryError::new(&e.to_string())),
+                        },
+                        None => Err(HistoryError::new(&format!(
+                            "'{}' is no longer a tower",
+                            archetype
+                        ))),
+                    }
+                }
+            }
+        };
+
+        self.finish(action, result, true)
+    }
+
+    /// Does the most recently undone action again.
+    pub fn redo(&mut self, state: &mut GameState, map: &GameMap) -> Result<(), HistoryError> {
+        let mut action = match self.redo.pop() {
+            Some(action) => action,
+            None => return Err(HistoryError::new("Nothing to redo")),
+        };
+
+        let result = match action {
+            BuildAction::PlaceTower {
+                ref archetype,
+                location,
+                ref mut cost,
+                ..
+            } => {
+                // The archetype may have been reloaded since, so remember
+                // what it costs now for undoing it again
+                let gold = state.gold;
+                placement::place_tower(state, map, archetype, location)
+                    .map(|id| {
+                        *cost = gold - state.gold;
+                        Some(id)
+                    })
+                    .map_err(|e| HistoryError::new(&e.to_string()))
+            }
+            BuildAction::SellTower { tower, .. } => placement::sell_tower(state, tower)
+                .map(|_| None)
+                .map_err(|e| HistoryError::new(&e.to_string())),
+        };
+
+        self.finish(action, result, false)
+    }
+
+    /// Moves an action between the stacks once it has been undone or redone.
+    /// Towers which were rebuilt get a new ID, which every other action
+    /// referring to them is updated with.
+    fn finish(
+        &mut self,
+        mut action: BuildAction,
+        result: Result<Option<EntityId>, HistoryError>,
+        undoing: bool,
+    ) -> Result<(), HistoryError> {
+        let (from, to) = if undoing {
+            (&mut self.undo, &mut self.redo)
+        } else {
+            (&mut self.redo, &mut self.undo)
+        };
+
+        match result {
+            Ok(rebuilt) => {
+                if let Some(new_id) = rebuilt {
+                    let old_id = *action.tower_mut();
+                    for other in from.iter_mut().chain(to.iter_mut()) {
+                        if *other.tower_mut() == old_id {
+                            *other.tower_mut() = new_id;
+                        }
+                    }
+                    *action.tower_mut() = new_id;
+                }
+                to.push(action);
+                Ok(())
+            }
+            Err(e) => {
+                from.push(action);
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::archetypes::test_archetypes;
+    use crate::game::history::*;
+    use crate::game::map::DEFAULT_MAP_DIMENSIONS;
+
+    fn setup() -> (GameState, GameMap) {
+        let mut state = GameState::new(Vec::new());
+        state.archetypes = test_archetypes();
+        let map = GameMap::new("test", DEFAULT_MAP_DIMENSIONS);
+
+        (state, map)
+    }
+
+    fn place(state: &mut GameState, map: &GameMap, history: &mut BuildHistory) -> EntityId {
+        let location = Point::new(10, 10);
+        let tower = placement::place_tower(state, map, "arrow", location).unwrap();
+        history.record(BuildAction::PlaceTower {
+            archetype: "arrow".to_string(),
+            location,
+            tower,
+            cost: 40,
+        });
+        tower
+    }
+
+    #[test]
+    fn undoing_a_placement_refunds_it_in_full() {
+        let (mut state, map) = setup();
+        let mut history = BuildHistory::new();
+        let gold = state.gold;
+
+        let tower = place(&mut state, &map, &mut history);
+        history.undo(&mut state, &map).unwrap();
+        assert_eq!(state.gold, gold);
+        assert!(state.entity(tower).is_none());
+        assert!(history.undo(&mut state, &map).is_err());
+
+        history.redo(&mut state, &map).unwrap();
+        assert_eq!(state.gold, gold - 40);
+        assert_eq!(state.entities.len(), 1);
+        assert!(!history.can_redo());
+    }
+
+    #[test]
+    fn redoing_a_placement_records_its_current_cost() {
+        let (mut state, map) = setup();
+        let mut history = BuildHistory::new();
+        let gold = state.gold;
+
+        place(&mut state, &map, &mut history);
+        history.undo(&mut state, &map).unwrap();
+        state.archetypes.update_towers(|tower| tower.cost = 55);
+
+        history.redo(&mut state, &map).unwrap();
+        assert_eq!(state.gold, gold - 55);
+        history.undo(&mut state, &map).unwrap();
+        assert_eq!(state.gold, gold);
+    }
+
+    #[test]
+    fn undo_follows_rebuilt_towers() {
+        let (mut state, map) = setup();
+        let mut history = BuildHistory::new();
+        let gold = state.gold;
+
+        let tower = place(&mut state, &map, &mut history);
+        let refund = placement::sell_tower(&mut state, tower).unwrap();
+        history.record(BuildAction::SellTower {
+            archetype: "arrow".to_string(),
+            location: Point::new(10, 10),
+            tower,
+            refund,
+        });
+
+        // Buying it back gives it a new ID, which undoing the placement
+        // has to use
+        history.undo(&mut state, &map).unwrap();
+        assert_eq!(state.entities.len(), 1);
+        assert!(state.entity(tower).is_none());
+        history.undo(&mut state, &map).unwrap();
+        assert!(state.entities.is_empty());
+        assert_eq!(state.gold, gold);
+
+        history.redo(&mut state, &map).unwrap();
+        history.redo(&mut state, &map).unwrap();
+        assert!(state.entities.is_empty());
+        assert_eq!(state.gold, gold - 40 + refund);
+
+        history.commit();
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn sold_towers_are_not_bought_back_on_top_of_others() {
+        let (mut state, map) = setup();
+        let mut history = BuildHistory::new();
+
+        let tower = place(&mut state, &map, &mut history);
+        let refund = placement::sell_tower(&mut state, tower).unwrap();
+        history.record(BuildAction::SellTower {
+            archetype: "arrow".to_string(),
+            location: Point::new(10, 10),
+            tower,
+            refund,
+        });
+
+        // Built outside the history, e.g. during the last wave
+        let other = placement::place_tower(&mut state, &map, "arrow", Point::new(10, 10)).unwrap();
+        assert!(history.undo(&mut state, &map).is_err());
+        assert_eq!(state.entities.len(), 1);
+        assert!(history.can_undo());
+
+        placement::sell_tower(&mut state, other).unwrap();
+        history.undo(&mut state, &map).unwrap();
+        assert_eq!(state.entities.len(), 1);
+    }
+}
@@ -1,4 +1,19 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde_json::Value;
+
 use super::super::geometry::{BoundingBox, Point};
+use super::entities::Zone;
+use super::files::write_atomically;
+use super::terrain::Terrain;
+use super::tiles::{
+    Buildability, Decoration, GridShape, PathHint, TileMap, TileRuns, DEFAULT_TILE_SIZE,
+};
+use crate::bincode::{deserialize, serialize};
 use crate::serde_derive::{Deserialize, Serialize};
 
 pub const DEFAULT_MAP_NAME: &str = "Default Map";
@@ -6,8 +21,484 @@ pub const DEFAULT_MAP: &str = "data/map.bin";
 pub const DEFAULT_MAP_DIMENSIONS: BoundingBox =
     BoundingBox::new(Point::new(0, 0), Point::new(100, 100));
 
+/// Every binary map file starts with this, followed by the format version.
+pub const MAP_MAGIC: &[u8; 4] = b"RTDM";
+
+/// Version of the map format written by this version of the game. Version 1
+/// was the original headerless bincode, with only a name and dimensions.
+pub const MAP_VERSION: u32 = 4;
+
+type Migration = fn(&mut Value) -> Result<(), MapError>;
+
+/// `MIGRATIONS[n]` upgrades a version `n + 1` map to version `n + 2`.
+const MIGRATIONS: &[Migration] = &[add_map_contents, add_spawns_and_goals, add_grid_shape];
+
+/// Why a map couldn't be loaded.
+#[derive(Debug)]
+pub enum MapError {
+    Io(io::Error),
+    /// Not a map file in either format.
+    NotAMap,
+    /// Written by a newer version of the game.
+    UnsupportedVersion {
+        version: u32,
+        newest: u32,
+    },
+    /// A map file which can't be decoded.
+    Corrupt(String),
+}
+
+impl MapError {
+    fn corrupt<E: fmt::Display>(e: E) -> MapError {
+        MapError::Corrupt(e.to_string())
+    }
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapError::Io(e) => write!(f, "{}", e),
+            MapError::NotAMap => write!(f, "not a map file"),
+            MapError::UnsupportedVersion { version, newest } => write!(
+                f,
+                "unsupported map version {} (newest supported is {})",
+                version, newest
+            ),
+            MapError::Corrupt(details) => write!(f, "corrupt map: {}", details),
+        }
+    }
+}
+
+impl Error for MapError {
+    fn description(&self) -> &str {
+        "unable to load map"
+    }
+}
+
+impl From<io::Error> for MapError {
+    fn from(e: io::Error) -> MapError {
+        MapError::Io(e)
+    }
+}
+
+/// The layout of version 1 maps.
 #[derive(Debug, Deserialize, Serialize)]
+struct MapV1 {
+    name: String,
+    dimensions: BoundingBox,
+}
+
+/// Version 1 maps had nothing on them but their size.
+fn add_map_contents(map: &mut Value) -> Result<(), MapError> {
+    let dimensions: BoundingBox =
+        serde_json::from_value(map["dimensions"].clone()).map_err(MapError::corrupt)?;
+    let tiles = TileMap::new(dimensions, DEFAULT_TILE_SIZE);
+
+    map["zones"] = Value::Array(Vec::new());
+    map["script"] = Value::Null;
+    map["sight_blockers"] = Value::Array(Vec::new());
+    map["decorations"] = Value::Array(Vec::new());
+    map["tiles"] = serde_json::to_value(tiles).map_err(MapError::corrupt)?;
+
+    Ok(())
+}
+
+/// The layout of tiles before version 4, when they were always square.
+#[derive(Debug, Deserialize, Serialize)]
+struct SquareTiles {
+    origin: Point,
+    tile_size: u32,
+    width: u32,
+    height: u32,
+    terrain: TileRuns<Terrain>,
+    buildable: TileRuns<Buildability>,
+    decoration: TileRuns<Decoration>,
+    path_hints: TileRuns<PathHint>,
+}
+
+/// The layout of version 2 maps.
+#[derive(Debug, Deserialize, Serialize)]
+struct MapV2 {
+    name: String,
+    dimensions: BoundingBox,
+    zones: Vec<Zone>,
+    script: Option<String>,
+    sight_blockers: Vec<BoundingBox>,
+    decorations: Vec<String>,
+    tiles: SquareTiles,
+}
+
+/// Version 2 maps left enemy routes up to their waves.
+fn add_spawns_and_goals(map: &mut Value) -> Result<(), MapError> {
+    map["spawns"] = Value::Array(Vec::new());
+    map["goals"] = Value::Array(Vec::new());
+
+    Ok(())
+}
+
+/// The layout of version 3 maps.
+#[derive(Debug, Deserialize, Serialize)]
+struct MapV3 {
+    name: String,
+    dimensions: BoundingBox,
+    zones: Vec<Zone>,
+    script: Option<String>,
+    sight_blockers: Vec<BoundingBox>,
+    decorations: Vec<String>,
+    tiles: SquareTiles,
+    spawns: Vec<Point>,
+    goals: Vec<Point>,
+}
+
+/// Maps before version 4 were all square.
+fn add_grid_shape(map: &mut Value) -> Result<(), MapError> {
+    map["tiles"]["shape"] = serde_json::to_value(GridShape::Square).map_err(MapError::corrupt)?;
+
+    Ok(())
+}
+
+fn check_version(version: u64) -> Result<u32, MapError> {
+    if version == 0 || version > u64::from(MAP_VERSION) {
+        return Err(MapError::UnsupportedVersion {
+            version: version.min(u64::from(u32::MAX)) as u32,
+            newest: MAP_VERSION,
+        });
+    }
+
+    Ok(version as u32)
+}
+
+/// Brings an older map up to date.
+fn migrate(mut map: Value, version: u32) -> Result<GameMap, MapError> {
+    for migration in MIGRATIONS[version as usize - 1..].iter() {
+        migration(&mut map)?;
+    }
+
+    serde_json::from_value(map).map_err(MapError::corrupt)
+}
+
+/// Written at the top of JSON maps.
+#[derive(Serialize)]
+struct VersionedMap<'a> {
+    version: u32,
+    #[serde(flatten)]
+    map: &'a GameMap,
+}
+
+/// How a map file is encoded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MapFormat {
+    /// Compact bincode, which the game ships with.
+    Binary,
+    /// Human-readable JSON, for maps kept in version control.
+    Json,
+}
+
+impl MapFormat {
+    /// Picks the format from the file extension, or from the data itself if
+    /// the extension is unknown.
+    pub fn detect(path: &str, data: &[u8]) -> MapFormat {
+        match MapFormat::from_extension(path) {
+            Some(format) => format,
+            None => MapFormat::sniff(data),
+        }
+    }
+
+    /// The format for a `.json` or `.bin` file.
+    pub fn from_extension(path: &str) -> Option<MapFormat> {
+        let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "json" => Some(MapFormat::Json),
+            "bin" => Some(MapFormat::Binary),
+            _ => None,
+        }
+    }
+
+    /// JSON maps are objects, so start with a brace. Binary ones start with
+    /// `MAP_MAGIC`, or the length of the map's name before it had a header,
+    /// which can't be a brace for any sensible name.
+    pub fn sniff(data: &[u8]) -> MapFormat {
+        match data.iter().find(|b| !b.is_ascii_whitespace()) {
+            Some(b'{') => MapFormat::Json,
+            _ => MapFormat::Binary,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct GameMap {
     pub name: String,
     pub dimensions: BoundingBox,
+    pub zones: Vec<Zone>,
+    /// Path to the map's event script, if it has one.
+    pub script: Option<String>,
+    /// Terrain, such as walls and cliffs, which can't be seen through.
+    pub sight_blockers: Vec<BoundingBox>,
+    /// Model names for tile decorations. Decoration `n` uses the model at
+    /// index `n - 1`.
+    pub decorations: Vec<String>,
+    pub tiles: TileMap,
+    /// Where enemies enter the map.
+    pub spawns: Vec<Point>,
+    /// Where enemies are heading. Each one must be reachable from every
+    /// spawn.
+    pub goals: Vec<Point>,
+}
+
+impl GameMap {
+    /// An empty map with nothing on it.
+    pub fn new(name: &str, dimensions: BoundingBox) -> GameMap {
+        GameMap {
+            name: name.to_string(),
+            dimensions,
+            zones: Vec::new(),
+            script: None,
+            sight_blockers: Vec::new(),
+            decorations: Vec::new(),
+            tiles: TileMap::new(dimensions, DEFAULT_TILE_SIZE),
+            spawns: Vec::new(),
+            goals: Vec::new(),
+        }
+    }
+
+    /// Loads a map file in either format, upgrading it if it's from an
+    /// older version of the game.
+    pub fn load(path: &str) -> Result<GameMap, MapError> {
+        let data = fs::read(path)?;
+        GameMap::from_bytes(&data, MapFormat::detect(path, &data))
+    }
+
+    /// Saves the map, as JSON if `path` ends in `.json` and as bincode
+    /// otherwise.
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let format = MapFormat::from_extension(path).unwrap_or(MapFormat::Binary);
+        let data = self.to_bytes(format)?;
+        write_atomically(path, data)?;
+
+        Ok(())
+    }
+
+    pub fn from_bytes(data: &[u8], format: MapFormat) -> Result<GameMap, MapError> {
+        match format {
+            MapFormat::Binary => GameMap::from_binary(data),
+            MapFormat::Json => GameMap::from_json(data),
+        }
+    }
+
+    fn from_binary(data: &[u8]) -> Result<GameMap, MapError> {
+        let header = MAP_MAGIC.len() + 4;
+        let (version, body) = if data.starts_with(MAP_MAGIC) {
+            if data.len() < header {
+                return Err(MapError::Corrupt("truncated header".to_string()));
+            }
+            let version: u32 =
+                deserialize(&data[MAP_MAGIC.len()..header]).map_err(MapError::corrupt)?;
+            (check_version(u64::from(version))?, &data[header..])
+        } else {
+            (1, data)
+        };
+
+        if version == MAP_VERSION {
+            return deserialize(body).map_err(MapError::corrupt);
+        }
+
+        // Older layouts are decoded with their own structs, then upgraded
+        // the same way as JSON maps
+        let map = match version {
+            1 => {
+                let map: MapV1 = deserialize(body).map_err(|_| MapError::NotAMap)?;
+                serde_json::to_value(map).map_err(MapError::corrupt)?
+            }
+            2 => {
+                let map: MapV2 = deserialize(body).map_err(MapError::corrupt)?;
+                serde_json::to_value(map).map_err(MapError::corrupt)?
+            }
+            3 => {
+                let map: MapV3 = deserialize(body).map_err(MapError::corrupt)?;
+                serde_json::to_value(map).map_err(MapError::corrupt)?
+            }
+            _ => {
+                return Err(MapError::UnsupportedVersion {
+                    version,
+                    newest: MAP_VERSION,
+                })
+            }
+        };
+        migrate(map, version)
+    }
+
+    /// JSON maps were introduced with version 2, so maps without a version
+    /// are taken to be from then.
+    fn from_json(data: &[u8]) -> Result<GameMap, MapError> {
+        let mut map: Value = serde_json::from_slice(data).map_err(MapError::corrupt)?;
+        let version = match map.as_object_mut() {
+            Some(fields) => fields.remove("version"),
+            None => return Err(MapError::NotAMap),
+        };
+        let version = match version {
+            Some(version) => match version.as_u64() {
+                Some(version) => check_version(version)?,
+                None => return Err(MapError::Corrupt("invalid version".to_string())),
+            },
+            None => 2,
+        };
+
+        migrate(map, version)
+    }
+
+    pub fn to_bytes(&self, format: MapFormat) -> Result<Vec<u8>, Box<dyn Error>> {
+        Ok(match format {
+            MapFormat::Binary => {
+                let mut data = MAP_MAGIC.to_vec();
+                data.extend(serialize(&MAP_VERSION)?);
+                data.extend(serialize(self)?);
+                data
+            }
+            MapFormat::Json => serde_json::to_vec_pretty(&VersionedMap {
+                version: MAP_VERSION,
+                map: self,
+            })?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::map::*;
+
+    fn test_map() -> GameMap {
+        let mut map = GameMap::new("Test Map", DEFAULT_MAP_DIMENSIONS);
+        map.zones.push(Zone::new(
+            "gate",
+            BoundingBox::new(Point::new(10, 10), Point::new(20, 20)),
+        ));
+        map.tiles.fill_terrain(
+            BoundingBox::new(Point::new(0, 40), Point::new(100, 60)),
+            Terrain::Road,
+        );
+        map
+    }
+
+    #[test]
+    fn maps_round_trip_through_both_formats() {
+        let map = test_map();
+
+        for format in [MapFormat::Binary, MapFormat::Json].iter() {
+            let data = map.to_bytes(*format).unwrap();
+            assert_eq!(MapFormat::sniff(&data), *format);
+
+            let loaded = GameMap::from_bytes(&data, *format).unwrap();
+            assert_eq!(loaded.name, map.name);
+            assert_eq!(loaded.dimensions, map.dimensions);
+            assert_eq!(
+                loaded.zones[0].get_bounding_box(),
+                map.zones[0].get_bounding_box()
+            );
+            assert_eq!(loaded.tiles, map.tiles);
+        }
+    }
+
+    #[test]
+    fn formats_are_detected_by_extension_first() {
+        assert_eq!(MapFormat::detect("maps/a.JSON", b"\0"), MapFormat::Json);
+        assert_eq!(MapFormat::detect("maps/a.bin", b"{"), MapFormat::Binary);
+        assert_eq!(MapFormat::detect("maps/a.map", b"  {"), MapFormat::Json);
+        assert_eq!(MapFormat::detect("maps/a", b"\x09\0"), MapFormat::Binary);
+    }
+
+    fn square_tiles(tiles: TileMap) -> SquareTiles {
+        let mut tiles = serde_json::to_value(tiles).unwrap();
+        tiles.as_object_mut().unwrap().remove("shape");
+        serde_json::from_value(tiles).unwrap()
+    }
+
+    #[test]
+    fn version_one_maps_are_upgraded() {
+        let old = serialize(&MapV1 {
+            name: "Old Map".to_string(),
+            dimensions: DEFAULT_MAP_DIMENSIONS,
+        })
+        .unwrap();
+
+        let map = GameMap::from_bytes(&old, MapFormat::Binary).unwrap();
+        assert_eq!(map.name, "Old Map");
+        assert_eq!(
+            map.tiles,
+            TileMap::new(DEFAULT_MAP_DIMENSIONS, DEFAULT_TILE_SIZE)
+        );
+
+        let mut v2 = MAP_MAGIC.to_vec();
+        v2.extend(serialize(&2u32).unwrap());
+        v2.extend(
+            serialize(&MapV2 {
+                name: "Old Map".to_string(),
+                dimensions: DEFAULT_MAP_DIMENSIONS,
+                zones: Vec::new(),
+                script: None,
+                sight_blockers: Vec::new(),
+                decorations: vec!["rock".to_string()],
+                tiles: square_tiles(TileMap::new(DEFAULT_MAP_DIMENSIONS, 8)),
+            })
+            .unwrap(),
+        );
+        let map = GameMap::from_bytes(&v2, MapFormat::Binary).unwrap();
+        assert_eq!(map.decorations, vec!["rock".to_string()]);
+        assert_eq!(map.tiles.tile_size(), 8);
+        assert_eq!(map.tiles.shape(), GridShape::Square);
+        assert!(map.goals.is_empty());
+
+        let json = br#"{ "name": "Old Map", "dimensions": [[0, 0], [100, 100]], "version": 1 }"#;
+        let map = GameMap::from_bytes(json, MapFormat::Json).unwrap();
+        assert_eq!(map.dimensions, DEFAULT_MAP_DIMENSIONS);
+        assert!(map.zones.is_empty());
+    }
+
+    #[test]
+    fn unreadable_maps_are_typed_errors() {
+        let mut newer = MAP_MAGIC.to_vec();
+        newer.extend(serialize(&(MAP_VERSION + 1)).unwrap());
+        match GameMap::from_bytes(&newer, MapFormat::Binary) {
+            Err(MapError::UnsupportedVersion { version, .. }) => {
+                assert_eq!(version, MAP_VERSION + 1)
+            }
+            other => panic!("expected an unsupported version, got {:?}", other),
+        }
+
+        let mut truncated = test_map().to_bytes(MapFormat::Binary).unwrap();
+        truncated.truncate(truncated.len() / 2);
+        match GameMap::from_bytes(&truncated, MapFormat::Binary) {
+            Err(MapError::Corrupt(_)) => {}
+            other => panic!("expected a corrupt map, got {:?}", other),
+        }
+
+        match GameMap::from_bytes(b"[1, 2]", MapFormat::Json) {
+            Err(MapError::NotAMap) => {}
+            other => panic!("expected not a map, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn corrupt_tile_grids_are_rejected() {
+        let corruptions: [fn(&mut Value); 4] = [
+            |tiles| tiles["tile_size"] = 0.into(),
+            |tiles| tiles["terrain"][0][0] = 1.into(),
+            |tiles| tiles["decoration"][0][0] = u32::MAX.into(),
+            |tiles| tiles["height"] = u32::MAX.into(),
+        ];
+
+        for corrupt in corruptions.iter() {
+            let mut map = serde_json::to_value(VersionedMap {
+                version: MAP_VERSION,
+                map: &test_map(),
+            })
+            .unwrap();
+            corrupt(&mut map["tiles"]);
+
+            let json = serde_json::to_vec(&map).unwrap();
+            match GameMap::from_bytes(&json, MapFormat::Json) {
+                Err(MapError::Corrupt(_)) => {}
+                other => panic!("expected a corrupt map, got {:?}", other),
+            }
+        }
+    }
 }
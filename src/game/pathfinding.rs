@@ -0,0 +1,385 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use super::tiles::{GridShape, TileMap};
+use super::GameState;
+use crate::geometry::{BoundingBox, Point, Polygon};
+
+/// Size of the cells paths are planned over on square maps, in world units.
+/// Hex maps are planned over their tiles.
+pub const PATH_CELL_SIZE: u32 = 4;
+
+const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+
+type Cell = (u32, u32);
+
+/// Which cells can be walked through and how hard they are to cross, worked
+/// out once per search.
+struct NavGrid<'a> {
+    /// The map's tiles, if they're hexagons.
+    hexes: Option<&'a TileMap>,
+    origin: Point,
+    width: u32,
+    height: u32,
+    blocked: Vec<bool>,
+    /// Terrain movement cost of each cell, as a percentage.
+    cost: Vec<u32>,
+    /// The lowest cost of any cell, which keeps the heuristic admissible.
+    cheapest: u32,
+}
+
+impl<'a> NavGrid<'a> {
+    /// Cells are blocked by terrain ground units can't cross, structures
+    /// (grown by `clearance`, so the walker doesn't clip them) and closed
+    /// zones. On hex maps, so are tiles outside `bounds`.
+    fn new(state: &'a GameState, bounds: BoundingBox, clearance: u32) -> NavGrid<'a> {
+        let hexes = Some(&state.tiles).filter(|tiles| tiles.shape() == GridShape::Hex);
+        let (origin, width, height) = match hexes {
+            Some(tiles) => (tiles.origin(), tiles.width(), tiles.height()),
+            None => {
+                let origin = bounds.lower_left();
+                let width = (bounds.upper_right().x() - origin.x()) / PATH_CELL_SIZE + 1;
+                let height = (bounds.upper_right().y() - origin.y()) / PATH_CELL_SIZE + 1;
+                (origin, width, height)
+            }
+        };
+        let mut grid = NavGrid {
+            hexes,
+            origin,
+            width,
+            height,
+            blocked: vec![false; (width * height) as usize],
+            cost: vec![100; (width * height) as usize],
+            cheapest: 100,
+        };
+
+        for y in 0..height {
+            for x in 0..width {
+                let i = grid.index((x, y));
+                match state.tiles.movement_cost_at(grid.center((x, y))) {
+                    Some(cost) => grid.cost[i] = cost,
+                    None => grid.blocked[i] = true,
+                }
+            }
+        }
+        grid.cheapest = grid.cost.iter().cloned().min().unwrap_or(100);
+
+        if hexes.is_some() {
+            let lower_left = bounds.lower_left();
+            let upper_right = bounds.upper_right();
+            grid.block_where(|center| {
+                center.x() < lower_left.x()
+                    || center.y() < lower_left.y()
+                    || center.x() > upper_right.x()
+                    || center.y() > upper_right.y()
+            });
+        }
+
+        for zone in state.zones.iter().filter(|zone| !zone.open) {
+            let zone_box = zone.get_bounding_box();
+            grid.block_where(|center| zone_box.contains(center));
+        }
+
+        for entity in state.entities.iter() {
+            let entity = entity.borrow();
+            if entity.tower.is_none() {
+                continue;
+            }
+            let radius = entity.shape.map_or(0, |shape| {
+                let (w, h) = shape.half_extents();
+                w.max(h)
+            });
+            let reach = u64::from(radius + clearance);
+            let location = entity.location;
+            grid.block_where(|center| center.distance_squared(location) <= reach * reach);
+        }
+
+        grid
+    }
+
+    fn block_where<F: Fn(Point) -> bool>(&mut self, blocked: F) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if blocked(self.center((x, y))) {
+                    let i = self.index((x, y));
+                    self.blocked[i] = true;
+                }
+            }
+        }
+    }
+
+    fn index(&self, cell: Cell) -> usize {
+        (cell.1 * self.width + cell.0) as usize
+    }
+
+    fn center(&self, cell: Cell) -> Point {
+        if let Some(tiles) = self.hexes {
+            return tiles.center(cell.0, cell.1);
+        }
+
+        Point::new(
+            self.origin.x() + cell.0 * PATH_CELL_SIZE,
+            self.origin.y() + cell.1 * PATH_CELL_SIZE,
+        )
+    }
+
+    fn cell_at(&self, point: Point) -> Option<Cell> {
+        if let Some(tiles) = self.hexes {
+            return tiles.cell_at(point);
+        }
+
+        if point.x() < self.origin.x() || point.y() < self.origin.y() {
+            return None;
+        }
+        let half = PATH_CELL_SIZE / 2;
+        let x = (point.x() - self.origin.x() + half) / PATH_CELL_SIZE;
+        let y = (point.y() - self.origin.y() + half) / PATH_CELL_SIZE;
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some((x, y))
+    }
+
+    fn is_blocked(&self, cell: Cell) -> bool {
+        self.blocked[self.index(cell)]
+    }
+
+    fn neighbours(&self, cell: Cell) -> Vec<(Cell, u32)> {
+        // Every hexagon is the same distance from all six of its neighbors
+        if let Some(tiles) = self.hexes {
+            return tiles
+                .neighbors(cell.0, cell.1)
+                .into_iter()
+                .filter(|&next| !self.is_blocked(next))
+                .map(|next| (next, STRAIGHT_COST * self.cost[self.index(next)]))
+                .collect();
+        }
+
+        let mut neighbours = Vec::with_capacity(8);
+        for dy in -1i64..=1 {
+            for dx in -1i64..=1 {
+                if dx == 0 && dy == 0 {
+                    continue;
+                }
+                let x = i64::from(cell.0) + dx;
+                let y = i64::from(cell.1) + dy;
+                if x < 0 || y < 0 || x >= i64::from(self.width) || y >= i64::from(self.height) {
+                    continue;
+                }
+                let next = (x as u32, y as u32);
+                if self.is_blocked(next) {
+                    continue;
+                }
+
+                if dx != 0 && dy != 0 {
+                    // Don't cut corners between two blocked cells
+                    let side_a = (x as u32, cell.1);
+                    let side_b = (cell.0, y as u32);
+                    if self.is_blocked(side_a) || self.is_blocked(side_b) {
+                        continue;
+                    }
+                    neighbours.push((next, DIAGONAL_COST * self.cost[self.index(next)]));
+                } else {
+                    neighbours.push((next, STRAIGHT_COST * self.cost[self.index(next)]));
+                }
+            }
+        }
+
+        neighbours
+    }
+}
+
+/// Octile distance (or hex distance on hex maps) over the cheapest terrain
+/// on the grid, which never overestimates.
+fn heuristic(grid: &NavGrid, a: Cell, b: Cell) -> u32 {
+    if let Some(tiles) = grid.hexes {
+        let steps = tiles.hex(a.0, a.1).distance(tiles.hex(b.0, b.1));
+        return STRAIGHT_COST * steps * grid.cheapest;
+    }
+
+    let dx = a.0.max(b.0) - a.0.min(b.0);
+    let dy = a.1.max(b.1) - a.1.min(b.1);
+    (STRAIGHT_COST * dx.max(dy) + (DIAGONAL_COST - STRAIGHT_COST) * dx.min(dy)) * grid.cheapest
+}
+
+/// Plans a route from `from` to `to` around structures, closed zones and
+/// impassable terrain using A*, preferring terrain which is quick to cross.
+/// Returns the waypoints to walk through, ending at `to`, or `None` if the
+/// destination can't be reached.
+pub fn find_path(
+    state: &GameState,
+    bounds: BoundingBox,
+    from: Point,
+    to: Point,
+    clearance: u32,
+) -> Option<Vec<Point>> {
+    let grid = NavGrid::new(state, bounds, clearance);
+    let start = grid.cell_at(from)?;
+    let goal = grid.cell_at(to)?;
+    if grid.is_blocked(goal) {
+        return None;
+    }
+
+    let mut open = BinaryHeap::new();
+    let mut came_from: HashMap<Cell, Cell> = HashMap::new();
+    let mut cost: HashMap<Cell, u32> = HashMap::new();
+
+    cost.insert(start, 0);
+    // Ties are broken on the cell so searches are deterministic
+    open.push(Reverse((heuristic(&grid, start, goal), start)));
+
+    while let Some(Reverse((_, cell))) = open.pop() {
+        if cell == goal {
+            let mut path = vec![to];
+            let mut current = cell;
+            while let Some(&previous) = came_from.get(&current) {
+                if previous != start {
+                    path.push(grid.center(previous));
+                }
+                current = previous;
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        let cell_cost = cost[&cell];
+        for (next, step) in grid.neighbours(cell) {
+            let next_cost = cell_cost + step;
+            let better = match cost.get(&next) {
+                Some(&known) => next_cost < known,
+                None => true,
+            };
+            if better {
+                cost.insert(next, next_cost);
+                came_from.insert(next, cell);
+                open.push(Reverse((next_cost + heuristic(&grid, next, goal), next)));
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::collision::CollisionShape;
+    use crate::game::combat::DamageType;
+    use crate::game::entities::{GameEntity, GameEntityType, Tower, Zone};
+    use crate::game::pathfinding::*;
+    use crate::game::terrain::Terrain;
+    use crate::game::tiles::{GridShape, TileMap};
+
+    fn bounds() -> BoundingBox {
+        BoundingBox::new(Point::new(0, 4), Point::new(40, 40))
+    }
+
+    fn add_wall(state: &mut GameState, x: u32, y: u32) {
+        let mut wall = GameEntity::new(0, GameEntityType::Structure, Point::new(x, y));
+        wall.shape = Some(CollisionShape::Circle { radius: 1 });
+        wall.tower = Some(Tower {
+            range: 1,
+            damage: 0,
+            damage_type: DamageType::Physical,
+            fire_interval: 1,
+            cooldown: 0,
+            effects: Vec::new(),
+            abilities: Vec::new(),
+            target: None,
+            buffs: Vec::new(),
+        });
+        state.add_entity(wall);
+    }
+
+    #[test]
+    fn paths_go_around_structures() {
+        let mut state = GameState::new(Vec::new());
+        let from = Point::new(0, 20);
+        let to = Point::new(40, 20);
+
+        let straight = find_path(&state, bounds(), from, to, 0).unwrap();
+        assert_eq!(straight.len(), 10);
+        assert_eq!(*straight.last().unwrap(), to);
+
+        // A wall across the middle with a gap at the top
+        for y in 0..9 {
+            add_wall(&mut state, 20, y * PATH_CELL_SIZE);
+        }
+        let around = find_path(&state, bounds(), from, to, 0).unwrap();
+        assert_eq!(*around.last().unwrap(), to);
+        assert!(around.iter().any(|p| *p.x() == 20));
+        assert!(around.iter().all(|p| *p.x() != 20 || *p.y() > 32));
+    }
+
+    #[test]
+    fn closed_zones_block_paths() {
+        let mut gate = Zone::new(
+            "gate",
+            BoundingBox::new(Point::new(16, 0), Point::new(24, 41)),
+        );
+        gate.open = false;
+        let mut state = GameState::new(vec![gate]);
+
+        assert!(find_path(&state, bounds(), Point::new(0, 20), Point::new(40, 20), 0).is_none());
+
+        state.zones[0].open = true;
+        assert!(find_path(&state, bounds(), Point::new(0, 20), Point::new(40, 20), 0).is_some());
+    }
+
+    #[test]
+    fn paths_prefer_quick_terrain() {
+        let mut state = GameState::new(Vec::new());
+        let from = Point::new(0, 8);
+        let to = Point::new(40, 8);
+
+        // Mud on the direct route, a road a little way off it and water
+        // cutting off everything further out
+        state.tiles.fill_terrain(
+            BoundingBox::new(Point::new(4, 4), Point::new(36, 12)),
+            Terrain::Mud,
+        );
+        state.tiles.fill_terrain(
+            BoundingBox::new(Point::new(0, 16), Point::new(41, 20)),
+            Terrain::Road,
+        );
+        state.tiles.fill_terrain(
+            BoundingBox::new(Point::new(0, 20), Point::new(41, 41)),
+            Terrain::Water,
+        );
+
+        let path = find_path(&state, bounds(), from, to, 0).unwrap();
+        assert!(path
+            .iter()
+            .any(|p| state.tiles.terrain_at(*p) == Terrain::Road));
+        assert!(path
+            .iter()
+            .all(|p| state.tiles.terrain_at(*p) != Terrain::Water));
+
+        assert!(find_path(&state, bounds(), from, Point::new(20, 32), 0).is_none());
+    }
+
+    #[test]
+    fn paths_on_hex_maps_step_between_neighbors() {
+        let mut state = GameState::new(Vec::new());
+        let bounds = BoundingBox::new(Point::new(0, 0), Point::new(60, 60));
+        state.tiles = TileMap::with_shape(bounds, 6, GridShape::Hex);
+
+        // A wall of cliffs with a gap at the top
+        for y in 0..state.tiles.height() - 2 {
+            state.tiles.set_terrain(5, y, Terrain::Cliff);
+        }
+
+        let from = state.tiles.center(1, 2);
+        let to = state.tiles.center(8, 2);
+        let path = find_path(&state, bounds, from, to, 0).unwrap();
+
+        let mut previous = state.tiles.hex_at(from);
+        for point in path.iter() {
+            let hex = state.tiles.hex_at(*point);
+            assert_eq!(previous.distance(hex), 1);
+            assert_ne!(state.tiles.terrain_at(*point), Terrain::Cliff);
+            previous = hex;
+        }
+        assert_eq!(*path.last().unwrap(), to);
+    }
+}
@@ -0,0 +1,196 @@
+use std::error::Error;
+use std::fmt;
+
+use super::archetypes::TowerArchetype;
+use super::entities::{EntityId, GameEntityType};
+use super::map::GameMap;
+use super::synergy;
+use super::tiles::{Buildability, GridShape, TileMap};
+use super::GameState;
+use crate::geometry::{Point, Polygon};
+
+/// Share of a tower's cost given back when it is sold.
+pub const SELL_REFUND_PERCENT: u32 = 75;
+
+#[derive(Debug)]
+pub struct PlacementError {
+    details: String,
+}
+
+impl PlacementError {
+    fn new(msg: &str) -> PlacementError {
+        PlacementError {
+            details: msg.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for PlacementError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for PlacementError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+/// Checks whether the tile at `location` allows `tower` to be built on it,
+/// whatever else is in the way.
+pub fn check_tile(
+    tower: &TowerArchetype,
+    tiles: &TileMap,
+    location: Point,
+) -> Result<(), PlacementError> {
+    let tile = tiles.tile_at(location);
+    let terrain = tile.terrain;
+    if tile.buildable == Buildability::Never {
+        return Err(PlacementError::new("Nothing can be built here"));
+    }
+    if tower.terrain.is_empty() {
+        if !tiles.is_buildable_at(location) {
+            return Err(PlacementError::new(&format!(
+                "Can't build on {:?}",
+                terrain
+            )));
+        }
+    } else if !tower.terrain.contains(&terrain) {
+        return Err(PlacementError::new(&format!(
+            "{} can only be built on {:?}",
+            tower.name, tower.terrain
+        )));
+    }
+
+    Ok(())
+}
+
+/// Where a tower asked for at `location` is actually built. Towers on hex
+/// maps sit in the middle of their hexagon, one to a hexagon.
+pub fn snap_to_grid(map: &GameMap, location: Point) -> Point {
+    if map.tiles.shape() != GridShape::Hex {
+        return location;
+    }
+
+    match map.tiles.cell_at(location) {
+        Some((x, y)) => map.tiles.center(x, y),
+        None => location,
+    }
+}
+
+/// Checks whether the named tower could be built at `location` right now.
+pub fn validate_placement(
+    state: &GameState,
+    map: &GameMap,
+    archetype: &str,
+    location: Point,
+) -> Result<(), PlacementError> {
+    let tower = match state.archetypes.tower(archetype) {
+        Some(tower) => tower,
+        None => {
+            return Err(PlacementError::new(&format!(
+                "'{}' is not a tower",
+                archetype
+            )))
+        }
+    };
+
+    if state.gold < tower.cost {
+        return Err(PlacementError::new(&format!(
+            "Not enough gold to build {} ({} needed, {} available)",
+            archetype, tower.cost, state.gold
+        )));
+    }
+
+    check_site(state, map, tower, location)
+}
+
+/// Checks whether `tower` fits at `location`, whatever it costs: it has to
+/// be on the map, on suitable tiles and clear of other structures.
+pub fn check_site(
+    state: &GameState,
+    map: &GameMap,
+    tower: &TowerArchetype,
+    location: Point,
+) -> Result<(), PlacementError> {
+    let location = snap_to_grid(map, location);
+    if !map.dimensions.contains(location) {
+        return Err(PlacementError::new("Location is outside of the map"));
+    }
+
+    check_tile(tower, &map.tiles, location)?;
+
+    for entity in state.entities.iter() {
+        let entity = entity.borrow();
+        if entity.entity_type != GameEntityType::Structure {
+            continue;
+        }
+
+        let other_radius = entity.shape.map_or(0, |shape| {
+            let (w, h) = shape.half_extents();
+            w.max(h)
+        });
+        let min_distance = u64::from(tower.radius + other_radius);
+        if entity.location == location
+            || entity.location.distance_squared(location) < min_distance * min_distance
+        {
+            return Err(PlacementError::new("Location overlaps another structure"));
+        }
+    }
+
+    Ok(())
+}
+
+/// Builds the named tower at `location`, paying for it out of the player's
+/// gold.
+pub fn place_tower(
+    state: &mut GameState,
+    map: &GameMap,
+    archetype: &str,
+    location: Point,
+) -> Result<EntityId, PlacementError> {
+    validate_placement(state, map, archetype, location)?;
+    let location = snap_to_grid(map, location);
+
+    let tower = state
+        .archetypes
+        .tower(archetype)
+        .expect("Validated tower archetype missing")
+        .clone();
+
+    state.gold -= tower.cost;
+    let id = state.add_entity(tower.instantiate(location));
+
+    // Update buffs straight away so tooltips are right even while paused
+    synergy::update_synergies(state);
+
+    Ok(id)
+}
+
+/// Removes a tower, refunding part of its cost. Returns the refund.
+pub fn sell_tower(state: &mut GameState, id: EntityId) -> Result<u32, PlacementError> {
+    let cost = {
+        let entity = match state.entity(id) {
+            Some(entity) => entity,
+            None => return Err(PlacementError::new("No such tower")),
+        };
+        let entity = entity.borrow();
+        if entity.tower.is_none() {
+            return Err(PlacementError::new("Only towers can be sold"));
+        }
+
+        entity
+            .archetype
+            .as_ref()
+            .and_then(|name| state.archetypes.tower(name))
+            .map_or(0, |archetype| archetype.cost)
+    };
+
+    let refund = cost * SELL_REFUND_PERCENT / 100;
+    state.gold += refund;
+    state.remove_entity(id);
+    synergy::update_synergies(state);
+
+    Ok(refund)
+}
@@ -0,0 +1,337 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use serde_json::Value;
+
+use super::campaign::CampaignProgress;
+use super::files::write_atomically;
+use super::ActiveGame;
+use crate::serde_derive::{Deserialize, Serialize};
+
+pub const DEFAULT_PROFILE: &str = "data/profile.json";
+
+/// Bump this whenever the profile layout changes in a way `#[serde(default)]`
+/// can't cover, and add a migration from the previous version to `MIGRATIONS`.
+pub const PROFILE_VERSION: u64 = 1;
+
+/// `MIGRATIONS[n]` upgrades a version `n + 1` profile to version `n + 2`.
+const MIGRATIONS: &[fn(&mut Value)] = &[];
+
+#[derive(Debug)]
+pub struct ProfileError {
+    details: String,
+}
+
+impl ProfileError {
+    fn new(msg: &str) -> ProfileError {
+        ProfileError {
+            details: msg.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for ProfileError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+/// How a single game went, as far as the profile is concerned.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct GameResult {
+    pub map_name: String,
+    pub difficulty: Difficulty,
+    pub cleared: bool,
+    pub score: u32,
+    pub waves_survived: u32,
+    pub kills: u32,
+    pub damage_dealt: u64,
+    pub lives_lost: u32,
+}
+
+impl GameResult {
+    pub fn from_game(game: &ActiveGame, difficulty: Difficulty) -> GameResult {
+        let stats = game.combat_stats();
+        let kills: u32 = stats.per_wave.values().map(|wave| wave.kills).sum();
+        let damage_dealt: u64 = stats.per_wave.values().map(|wave| wave.damage_dealt).sum();
+        let lives_lost: u32 = stats.per_wave.values().map(|wave| wave.lives_lost).sum();
+
+        let cleared = game.is_cleared();
+        let waves_survived = if cleared {
+            game.state.wave
+        } else {
+            game.state.wave.saturating_sub(1)
+        };
+
+        GameResult {
+            map_name: game.map.name.clone(),
+            difficulty,
+            cleared,
+            score: score(kills, waves_survived, lives_lost),
+            waves_survived,
+            kills,
+            damage_dealt,
+            lives_lost,
+        }
+    }
+}
+
+fn score(kills: u32, waves_survived: u32, lives_lost: u32) -> u32 {
+    kills
+        .saturating_mul(10)
+        .saturating_add(waves_survived.saturating_mul(100))
+        .saturating_sub(lives_lost.saturating_mul(50))
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MapRecord {
+    pub times_played: u32,
+    pub best_score: u32,
+    pub cleared: BTreeSet<Difficulty>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ProfileStats {
+    pub games_played: u32,
+    pub games_won: u32,
+    pub kills: u64,
+    pub damage_dealt: u64,
+    pub lives_lost: u64,
+}
+
+pub struct Achievement {
+    pub id: &'static str,
+    pub name: &'static str,
+    unlocked: fn(&PlayerProfile, &GameResult) -> bool,
+}
+
+/// Every achievement, checked after each game against the updated profile.
+pub const ACHIEVEMENTS: &[Achievement] = &[
+    Achievement {
+        id: "first_blood",
+        name: "First Blood",
+        unlocked: |profile, _| profile.stats.kills >= 1,
+    },
+    Achievement {
+        id: "exterminator",
+        name: "Exterminator",
+        unlocked: |profile, _| profile.stats.kills >= 1000,
+    },
+    Achievement {
+        id: "first_victory",
+        name: "First Victory",
+        unlocked: |profile, _| profile.stats.games_won >= 1,
+    },
+    Achievement {
+        id: "flawless",
+        name: "Flawless",
+        unlocked: |_, result| result.cleared && result.lives_lost == 0,
+    },
+    Achievement {
+        id: "hardened",
+        name: "Hardened",
+        unlocked: |_, result| result.cleared && result.difficulty == Difficulty::Hard,
+    },
+    Achievement {
+        id: "veteran",
+        name: "Veteran",
+        unlocked: |profile, _| profile.stats.games_played >= 10,
+    },
+];
+
+/// Player progress which is kept between games.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PlayerProfile {
+    pub version: u64,
+    pub name: String,
+    pub stats: ProfileStats,
+    /// Keyed by map name.
+    pub maps: BTreeMap<String, MapRecord>,
+    /// IDs of unlocked achievements.
+    pub achievements: BTreeSet<String>,
+    pub campaign: CampaignProgress,
+}
+
+impl Default for PlayerProfile {
+    fn default() -> PlayerProfile {
+        PlayerProfile {
+            version: PROFILE_VERSION,
+            name: "Player".to_string(),
+            stats: ProfileStats::default(),
+            maps: BTreeMap::new(),
+            achievements: BTreeSet::new(),
+            campaign: CampaignProgress::default(),
+        }
+    }
+}
+
+impl PlayerProfile {
+    pub fn new() -> PlayerProfile {
+        PlayerProfile::default()
+    }
+
+    /// Loads the profile at `path`, or starts a fresh one if there isn't one
+    /// yet. A profile which exists but can't be read is an error, so that it
+    /// doesn't get overwritten.
+    pub fn load_or_default(path: &str) -> Result<PlayerProfile, Box<dyn Error>> {
+        if !Path::new(path).exists() {
+            return Ok(PlayerProfile::new());
+        }
+
+        let json = fs::read_to_string(path)?;
+        Ok(PlayerProfile::from_json(&json)?)
+    }
+
+    /// Parses a profile written by this or any earlier version of the game.
+    pub fn from_json(json: &str) -> Result<PlayerProfile, ProfileError> {
+        let mut value: Value = serde_json::from_str(json)
+            .map_err(|e| ProfileError::new(&format!("invalid profile: {}", e)))?;
+        if !value.is_object() {
+            return Err(ProfileError::new("invalid profile: expected an object"));
+        }
+
+        let version = value.get("version").and_then(Value::as_u64).unwrap_or(1);
+        if version == 0 || version > PROFILE_VERSION {
+            return Err(ProfileError::new(&format!(
+                "unsupported profile version {} (newest supported is {})",
+                version, PROFILE_VERSION
+            )));
+        }
+
+        for migration in MIGRATIONS.iter().skip(version as usize - 1) {
+            migration(&mut value);
+        }
+        value["version"] = Value::from(PROFILE_VERSION);
+
+        serde_json::from_value(value)
+            .map_err(|e| ProfileError::new(&format!("invalid profile: {}", e)))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let json = serde_json::to_string_pretty(self)?;
+        write_atomically(path, json)?;
+
+        Ok(())
+    }
+
+    pub fn has_cleared(&self, map_name: &str, difficulty: Difficulty) -> bool {
+        self.maps
+            .get(map_name)
+            .map(|record| record.cleared.contains(&difficulty))
+            .unwrap_or(false)
+    }
+
+    pub fn best_score(&self, map_name: &str) -> Option<u32> {
+        self.maps.get(map_name).map(|record| record.best_score)
+    }
+
+    pub fn has_achievement(&self, id: &str) -> bool {
+        self.achievements.contains(id)
+    }
+
+    /// Adds a finished game to the profile. Returns any achievements which
+    /// were unlocked by it.
+    pub fn record_game(&mut self, result: &GameResult) -> Vec<&'static Achievement> {
+        self.stats.games_played += 1;
+        if result.cleared {
+            self.stats.games_won += 1;
+        }
+        self.stats.kills += u64::from(result.kills);
+        self.stats.damage_dealt += result.damage_dealt;
+        self.stats.lives_lost += u64::from(result.lives_lost);
+
+        let record = self.maps.entry(result.map_name.clone()).or_default();
+        record.times_played += 1;
+        record.best_score = record.best_score.max(result.score);
+        if result.cleared {
+            record.cleared.insert(result.difficulty);
+        }
+
+        let mut unlocked = Vec::new();
+        for achievement in ACHIEVEMENTS.iter() {
+            if !self.has_achievement(achievement.id) && (achievement.unlocked)(self, result) {
+                self.achievements.insert(achievement.id.to_string());
+                unlocked.push(achievement);
+            }
+        }
+
+        unlocked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::profile::*;
+
+    fn result(cleared: bool, kills: u32) -> GameResult {
+        GameResult {
+            map_name: "Test Map".to_string(),
+            difficulty: Difficulty::Hard,
+            cleared,
+            score: score(kills, 3, 0),
+            waves_survived: 3,
+            kills,
+            damage_dealt: u64::from(kills) * 10,
+            lives_lost: 0,
+        }
+    }
+
+    #[test]
+    fn games_update_stats_and_achievements() {
+        let mut profile = PlayerProfile::new();
+
+        let unlocked = profile.record_game(&result(false, 5));
+        let ids: Vec<&str> = unlocked.iter().map(|a| a.id).collect();
+        assert_eq!(ids, vec!["first_blood"]);
+        assert!(!profile.has_cleared("Test Map", Difficulty::Hard));
+
+        let unlocked = profile.record_game(&result(true, 2));
+        let ids: Vec<&str> = unlocked.iter().map(|a| a.id).collect();
+        assert_eq!(ids, vec!["first_victory", "flawless", "hardened"]);
+        assert!(profile.has_cleared("Test Map", Difficulty::Hard));
+        assert!(!profile.has_cleared("Test Map", Difficulty::Easy));
+
+        assert_eq!(profile.stats.games_played, 2);
+        assert_eq!(profile.stats.kills, 7);
+        assert_eq!(profile.best_score("Test Map"), Some(350));
+    }
+
+    #[test]
+    fn profiles_roundtrip_and_tolerate_missing_fields() {
+        let mut profile = PlayerProfile::new();
+        profile.record_game(&result(true, 1));
+
+        let json = serde_json::to_string(&profile).unwrap();
+        assert_eq!(PlayerProfile::from_json(&json).unwrap(), profile);
+
+        let old = PlayerProfile::from_json(r#"{ "name": "Old" }"#).unwrap();
+        assert_eq!(old.name, "Old");
+        assert_eq!(old.version, PROFILE_VERSION);
+        assert_eq!(old.stats.games_played, 0);
+    }
+
+    #[test]
+    fn newer_profiles_are_rejected() {
+        let json = format!(r#"{{ "version": {} }}"#, PROFILE_VERSION + 1);
+        assert!(PlayerProfile::from_json(&json).is_err());
+    }
+}
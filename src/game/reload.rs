@@ -0,0 +1,130 @@
+use std::fs;
+use std::time::{Duration, SystemTime};
+
+/// How often watched files are checked for changes.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The kinds of gameplay data which can be reloaded while a game is running.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DataFile {
+    Archetypes,
+    Waves,
+    Map,
+}
+
+#[derive(Debug)]
+struct WatchedFile {
+    kind: DataFile,
+    path: String,
+    modified: Option<SystemTime>,
+}
+
+/// Watches data files for changes by polling their modification times, so
+/// balance can be tuned without restarting the game.
+#[derive(Debug)]
+pub struct DataWatcher {
+    files: Vec<WatchedFile>,
+    poll_interval: Duration,
+    since_poll: Duration,
+    /// Whether reloaded archetypes are also applied to entities which have
+    /// already spawned, rather than only to future spawns.
+    pub update_existing: bool,
+}
+
+impl DataWatcher {
+    pub fn new() -> DataWatcher {
+        DataWatcher {
+            files: Vec::new(),
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            since_poll: Duration::from_secs(0),
+            update_existing: false,
+        }
+    }
+
+    pub fn set_poll_interval(&mut self, interval: Duration) {
+        self.poll_interval = interval;
+    }
+
+    pub fn watch(&mut self, kind: DataFile, path: &str) {
+        self.files.push(WatchedFile {
+            kind,
+            path: path.to_string(),
+            modified: modified_time(path),
+        });
+    }
+
+    pub fn path(&self, kind: DataFile) -> Option<&str> {
+        self.files
+            .iter()
+            .find(|file| file.kind == kind)
+            .map(|file| file.path.as_str())
+    }
+
+    /// Returns the files which changed since they were last checked. Files are
+    /// only checked once per poll interval, however often this is called.
+    pub fn poll(&mut self, elapsed: Duration) -> Vec<DataFile> {
+        self.since_poll += elapsed;
+        if self.since_poll < self.poll_interval {
+            return Vec::new();
+        }
+        self.since_poll = Duration::from_secs(0);
+
+        let mut changed = Vec::new();
+        for file in self.files.iter_mut() {
+            let modified = modified_time(&file.path);
+            // A file which is missing (e.g. mid-save) is left alone until it
+            // comes back.
+            if modified.is_some() && modified != file.modified {
+                file.modified = modified;
+                changed.push(file.kind);
+            }
+        }
+
+        changed
+    }
+}
+
+impl Default for DataWatcher {
+    fn default() -> DataWatcher {
+        DataWatcher::new()
+    }
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::reload::*;
+    use std::env;
+
+    #[test]
+    fn changes_are_reported_once_per_interval() {
+        let path = env::temp_dir().join(format!("rtd_reload_test_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, "{}").unwrap();
+
+        let mut watcher = DataWatcher::new();
+        watcher.set_poll_interval(Duration::from_millis(100));
+        watcher.watch(DataFile::Archetypes, path);
+
+        assert!(watcher.poll(Duration::from_millis(200)).is_empty());
+
+        // Make sure the new modification time differs on coarse filesystems
+        let file = fs::OpenOptions::new().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+
+        assert!(watcher.poll(Duration::from_millis(50)).is_empty());
+        assert_eq!(
+            watcher.poll(Duration::from_millis(50)),
+            vec![DataFile::Archetypes]
+        );
+        assert!(watcher.poll(Duration::from_millis(200)).is_empty());
+
+        fs::remove_file(path).unwrap();
+    }
+}
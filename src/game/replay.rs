@@ -0,0 +1,112 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{Read, Write};
+
+use super::clock::ClockCommand;
+use super::entities::EntityId;
+use crate::bincode::{deserialize, serialize};
+use crate::geometry::Point;
+use crate::serde_derive::{Deserialize, Serialize};
+
+/// Commands are the only way player input reaches the simulation, which
+/// means recording them is enough to replay a game.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub enum GameCommand {
+    Clock(ClockCommand),
+    PlaceTower {
+        archetype: String,
+        location: Point,
+    },
+    SellTower {
+        tower: EntityId,
+    },
+    MoveHero {
+        destination: Point,
+    },
+    /// Starts the next wave, which also commits the build phase.
+    StartWave,
+    Undo,
+    Redo,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ReplayEntry {
+    pub tick: u32,
+    pub command: GameCommand,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct Replay {
+    pub map_name: String,
+    /// The game's random seed, needed to play it back identically.
+    pub seed: u64,
+    entries: Vec<ReplayEntry>,
+}
+
+impl Replay {
+    pub fn new(map_name: &str) -> Replay {
+        Replay {
+            map_name: map_name.to_string(),
+            seed: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, tick: u32, command: GameCommand) {
+        self.entries.push(ReplayEntry { tick, command });
+    }
+
+    pub fn entries(&self) -> &[ReplayEntry] {
+        &self.entries
+    }
+
+    /// All commands which were issued on `tick`, in the order they were issued.
+    pub fn commands_at(&self, tick: u32) -> impl Iterator<Item = &GameCommand> {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.tick == tick)
+            .map(|entry| &entry.command)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let mut file = File::create(path)?;
+        let encoded: Vec<u8> = serialize(self)?;
+        file.write_all(&encoded)?;
+
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> Result<Replay, Box<dyn Error>> {
+        let mut file = File::open(path)?;
+        let mut data = Vec::<u8>::new();
+        file.read_to_end(&mut data)?;
+
+        Ok(deserialize(&data)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::clock::{ClockCommand, GameSpeed};
+    use crate::game::replay::*;
+
+    #[test]
+    fn replay_roundtrip() {
+        let mut replay = Replay::new("test");
+        replay.seed = 1234;
+        replay.record(0, GameCommand::Clock(ClockCommand::Pause));
+        replay.record(0, GameCommand::Clock(ClockCommand::Step));
+        replay.record(
+            5,
+            GameCommand::Clock(ClockCommand::SetSpeed(GameSpeed::Double)),
+        );
+
+        let decoded: Replay = deserialize(&serialize(&replay).unwrap()).unwrap();
+
+        assert_eq!(decoded.map_name, "test");
+        assert_eq!(decoded.seed, 1234);
+        assert_eq!(decoded.entries(), replay.entries());
+        assert_eq!(decoded.commands_at(0).count(), 2);
+        assert_eq!(decoded.commands_at(1).count(), 0);
+    }
+}
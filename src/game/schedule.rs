@@ -0,0 +1,411 @@
+//! Runs the simulation's systems for each tick.
+//!
+//! Every system declares which components it reads and writes. The scheduler
+//! groups systems into stages: systems in the same stage don't conflict, so
+//! they can run at the same time on a thread pool. A system never moves ahead
+//! of an earlier one it conflicts with.
+//!
+//! Parallel systems only get shared access to the game state. Their spawns,
+//! despawns and combat events go into a `SystemOutput` of their own, which
+//! are merged in the order the systems were declared once the stage is done.
+//! That way a tick plays out exactly the same however many threads it runs on.
+//! Moves and fog of war go through it too, so a system which only defers its
+//! writes can share a stage with earlier systems reading what it changes.
+use std::error::Error;
+
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use super::collision;
+use super::combat::{self, CombatEvent, CombatLog};
+use super::command_buffer::CommandBuffer;
+use super::enemies;
+use super::entities::EntityId;
+use super::hero;
+use super::synergy;
+use super::targeting;
+use super::towers;
+use super::visibility::{self, FogOfWar};
+use super::GameState;
+use crate::geometry::Point;
+
+/// The parts of the game state a system can access.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Component {
+    Location,
+    Shape,
+    Health,
+    Tower,
+    Enemy,
+    Projectile,
+    Hero,
+    Sight,
+    /// The spatial index over entity locations.
+    Spatial,
+    /// Each player's fog of war.
+    Visibility,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Access {
+    pub reads: &'static [Component],
+    pub writes: &'static [Component],
+    /// Written through the system's `SystemOutput`, so only once its stage
+    /// is done.
+    pub deferred: &'static [Component],
+}
+
+impl Access {
+    /// Whether this system has to run in a later stage than `earlier`, which
+    /// was declared before it. That's the case when either one changes
+    /// something the other uses while the stage is running, or when this
+    /// system would miss changes `earlier` defers. Writes this system defers
+    /// land after the stage, just as if it had run after `earlier`.
+    pub fn conflicts_with(&self, earlier: &Access) -> bool {
+        let touches = |access: &Access, component: &Component| {
+            access.reads.contains(component) || access.writes.contains(component)
+        };
+
+        self.writes.iter().any(|c| touches(earlier, c))
+            || earlier.writes.iter().any(|c| touches(self, c))
+            || earlier
+                .deferred
+                .iter()
+                .any(|c| touches(self, c) || self.deferred.contains(c))
+    }
+}
+
+/// Side effects of a parallel system, applied after its stage has finished.
+#[derive(Debug, Default)]
+pub struct SystemOutput {
+    pub commands: CommandBuffer,
+    /// Recorded in the combat log for the current tick and wave.
+    pub events: Vec<CombatEvent>,
+    /// Bounties earned, added to the player's gold.
+    pub gold: u32,
+    /// New entity locations, set in order.
+    pub moves: Vec<(EntityId, Point)>,
+    /// Replaces the game's fog of war.
+    pub fog: Option<FogOfWar>,
+}
+
+impl SystemOutput {
+    pub fn new() -> SystemOutput {
+        SystemOutput::default()
+    }
+
+    /// Makes the system's changes to the game state.
+    pub fn apply(self, state: &mut GameState, log: &mut CombatLog) {
+        state.commands.append(self.commands);
+        state.gold += self.gold;
+        for event in self.events {
+            log.record(state.tick, state.wave, event);
+        }
+        for (id, location) in self.moves {
+            state.set_entity_location(id, location);
+        }
+        if let Some(fog) = self.fog {
+            state.fog = Some(fog);
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum SystemFn {
+    /// Runs alongside other systems with read-only access to the game state.
+    /// Components are changed through the entities' locks.
+    Parallel(fn(&GameState, &mut SystemOutput)),
+    /// Needs the whole game state to itself, so always runs alone.
+    Exclusive(fn(&mut GameState, &mut CombatLog)),
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct System {
+    pub name: &'static str,
+    pub access: Access,
+    pub run: SystemFn,
+}
+
+impl System {
+    fn is_exclusive(&self) -> bool {
+        match self.run {
+            SystemFn::Exclusive(_) => true,
+            SystemFn::Parallel(_) => false,
+        }
+    }
+
+    fn conflicts_with(&self, earlier: &System) -> bool {
+        self.is_exclusive() || earlier.is_exclusive() || self.access.conflicts_with(&earlier.access)
+    }
+}
+
+#[derive(Debug)]
+pub struct Scheduler {
+    systems: Vec<System>,
+    /// Indexes into `systems`, in the order the stages run.
+    stages: Vec<Vec<usize>>,
+    /// Only set when running on more than one thread.
+    pool: Option<ThreadPool>,
+}
+
+impl Scheduler {
+    /// Builds a single threaded scheduler for `systems`, which are given in
+    /// the order they should run.
+    pub fn new(systems: Vec<System>) -> Scheduler {
+        let mut stages: Vec<Vec<usize>> = Vec::new();
+
+        for (i, system) in systems.iter().enumerate() {
+            // The earliest stage after the last one this system conflicts with
+            let earliest = stages
+                .iter()
+                .rposition(|stage| {
+                    stage
+                        .iter()
+                        .any(|&other| system.conflicts_with(&systems[other]))
+                })
+                .map_or(0, |last_conflict| last_conflict + 1);
+
+            if earliest < stages.len() {
+                stages[earliest].push(i);
+            } else {
+                stages.push(vec![i]);
+            }
+        }
+
+        Scheduler {
+            systems,
+            stages,
+            pool: None,
+        }
+    }
+
+    /// Sets how many threads stages are run on. With one thread (or zero)
+    /// everything runs on the calling thread.
+    pub fn set_threads(&mut self, threads: usize) -> Result<(), Box<dyn Error>> {
+        self.pool = if threads > 1 {
+            Some(ThreadPoolBuilder::new().num_threads(threads).build()?)
+        } else {
+            None
+        };
+
+        Ok(())
+    }
+
+    pub fn threads(&self) -> usize {
+        self.pool
+            .as_ref()
+            .map_or(1, |pool| pool.current_num_threads())
+    }
+
+    /// System names grouped by stage, in the order they run.
+    pub fn stages(&self) -> Vec<Vec<&'static str>> {
+        self.stages
+            .iter()
+            .map(|stage| stage.iter().map(|&i| self.systems[i].name).collect())
+            .collect()
+    }
+
+    /// Runs every system once.
+    pub fn run(&self, state: &mut GameState, log: &mut CombatLog) {
+        for stage in self.stages.iter() {
+            let mut parallel = Vec::with_capacity(stage.len());
+            for &i in stage.iter() {
+                match self.systems[i].run {
+                    SystemFn::Exclusive(run) => run(state, log),
+                    SystemFn::Parallel(run) => parallel.push(run),
+                }
+            }
+            if parallel.is_empty() {
+                continue;
+            }
+
+            let shared: &GameState = state;
+            let run_system = |run: &fn(&GameState, &mut SystemOutput)| {
+                let mut output = SystemOutput::new();
+                run(shared, &mut output);
+                output
+            };
+            let outputs: Vec<SystemOutput> = match self.pool {
+                Some(ref pool) if parallel.len() > 1 => {
+                    pool.install(|| parallel.par_iter().map(run_system).collect())
+                }
+                _ => parallel.iter().map(run_system).collect(),
+            };
+
+            for output in outputs {
+                output.apply(state, log);
+            }
+        }
+    }
+}
+
+fn run_synergies(state: &mut GameState, _log: &mut CombatLog) {
+    synergy::update_synergies(state);
+}
+
+fn run_targeting(state: &GameState, _output: &mut SystemOutput) {
+    targeting::update_targets(state);
+}
+
+fn run_projectiles(state: &GameState, output: &mut SystemOutput) {
+    let collisions = collision::detect_collisions(state);
+    combat::apply_collisions(state, output, &collisions);
+}
+
+/// The systems run every tick, in order.
+pub fn simulation_systems() -> Vec<System> {
+    use self::Component::*;
+
+    vec![
+        System {
+            name: "synergies",
+            access: Access {
+                reads: &[Location, Spatial],
+                writes: &[Tower],
+                deferred: &[],
+            },
+            run: SystemFn::Exclusive(run_synergies),
+        },
+        System {
+            name: "heroes",
+            access: Access {
+                reads: &[Enemy, Visibility],
+                writes: &[Hero, Location, Health, Spatial],
+                deferred: &[],
+            },
+            run: SystemFn::Exclusive(hero::update_heroes),
+        },
+        System {
+            name: "enemies",
+            access: Access {
+                reads: &[Hero],
+                writes: &[Enemy, Location, Health, Spatial],
+                deferred: &[],
+            },
+            run: SystemFn::Exclusive(enemies::update_enemies),
+        },
+        System {
+            name: "visibility",
+            access: Access {
+                reads: &[Location, Sight],
+                writes: &[],
+                deferred: &[Visibility],
+            },
+            run: SystemFn::Parallel(visibility::update_visibility),
+        },
+        System {
+            name: "projectile movement",
+            access: Access {
+                reads: &[Location, Projectile],
+                writes: &[],
+                deferred: &[Location, Spatial],
+            },
+            run: SystemFn::Parallel(towers::move_projectiles),
+        },
+        System {
+            name: "targeting",
+            access: Access {
+                reads: &[Location, Enemy, Spatial, Visibility],
+                writes: &[Tower],
+                deferred: &[],
+            },
+            run: SystemFn::Parallel(run_targeting),
+        },
+        System {
+            name: "towers",
+            access: Access {
+                reads: &[Location],
+                writes: &[Tower],
+                deferred: &[],
+            },
+            run: SystemFn::Parallel(towers::fire_towers),
+        },
+        System {
+            name: "projectiles",
+            access: Access {
+                reads: &[Location, Shape, Spatial, Hero],
+                writes: &[Projectile, Health, Enemy],
+                deferred: &[],
+            },
+            run: SystemFn::Parallel(run_projectiles),
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::schedule::*;
+
+    fn noop(_state: &GameState, _output: &mut SystemOutput) {}
+
+    fn system(
+        name: &'static str,
+        reads: &'static [Component],
+        writes: &'static [Component],
+    ) -> System {
+        System {
+            name,
+            access: Access {
+                reads,
+                writes,
+                deferred: &[],
+            },
+            run: SystemFn::Parallel(noop),
+        }
+    }
+
+    #[test]
+    fn conflicting_systems_keep_their_order() {
+        use crate::game::schedule::Component::*;
+
+        let scheduler = Scheduler::new(vec![
+            system("move", &[], &[Location]),
+            system("target", &[Location], &[Tower]),
+            system("heal", &[], &[Health]),
+            system("fire", &[Tower], &[Projectile]),
+            system("regen", &[Location], &[Health]),
+        ]);
+
+        assert_eq!(
+            scheduler.stages(),
+            vec![vec!["move", "heal"], vec!["target", "regen"], vec!["fire"]]
+        );
+    }
+
+    #[test]
+    fn deferred_writes_only_wait_for_earlier_systems() {
+        use crate::game::schedule::Component::*;
+
+        let mut move_later = system("move", &[], &[]);
+        move_later.access.deferred = &[Location];
+        let scheduler = Scheduler::new(vec![
+            system("target", &[Location], &[Tower]),
+            move_later,
+            system("fire", &[Location], &[Projectile]),
+        ]);
+
+        assert_eq!(
+            scheduler.stages(),
+            vec![vec!["target", "move"], vec!["fire"]]
+        );
+    }
+
+    #[test]
+    fn exclusive_systems_run_alone() {
+        let mut systems = simulation_systems();
+        systems.push(system("idle", &[], &[]));
+        let scheduler = Scheduler::new(systems);
+
+        assert_eq!(
+            scheduler.stages(),
+            vec![
+                vec!["synergies"],
+                vec!["heroes"],
+                vec!["enemies"],
+                vec!["visibility", "projectile movement", "idle"],
+                vec!["targeting"],
+                vec!["towers", "projectiles"]
+            ]
+        );
+    }
+}
@@ -0,0 +1,455 @@
+//! Map event scripting.
+//!
+//! Maps can ship a [Rhai](https://rhai.rs) script which is run inside the
+//! simulation tick. Scripts never touch the game state directly: they get a
+//! read-only snapshot of the world and can only affect the game by posting
+//! messages onto the `GameState` message bus, which keeps them sandboxed and
+//! deterministic.
+//!
+//! A script can define any of the following hooks:
+//!
+//! ```text
+//! fn on_tick(tick) { }
+//! fn on_wave_start(wave) {
+//!     if wave == 5 {
+//!         spawn_entity("grunt", 50, 50);
+//!         close_zone("north_gate");
+//!     }
+//! }
+//! fn on_timer(name) { }
+//! ```
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::rc::Rc;
+
+use rhai::{Array, CallFnOptions, Dynamic, Engine, Scope, AST, INT};
+
+use super::entities::{EntityId, GameEntityType};
+use super::{GameMessage, GameState};
+use crate::geometry::Point;
+
+/// Upper bound on the work a single hook invocation may do, so a runaway
+/// script can't stall the simulation.
+const MAX_SCRIPT_OPERATIONS: u64 = 100_000;
+const MAX_SCRIPT_CALL_LEVELS: usize = 32;
+
+#[derive(Debug)]
+pub struct ScriptError {
+    details: String,
+}
+
+impl ScriptError {
+    fn new(msg: &str) -> ScriptError {
+        ScriptError {
+            details: msg.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for ScriptError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+#[derive(Debug, Clone)]
+struct EntitySnapshot {
+    id: EntityId,
+    entity_type: GameEntityType,
+    location: Point,
+}
+
+/// The view of the game exposed to scripts, refreshed before each hook runs.
+#[derive(Debug, Default)]
+struct ScriptWorld {
+    tick: u32,
+    wave: u32,
+    entities: Vec<EntitySnapshot>,
+    zones: Vec<(String, bool)>,
+    /// Keyed by the tick the timer fires on, then the timer name, so timers
+    /// always fire in the same order.
+    timers: BTreeMap<(u32, String), ()>,
+    outbox: Vec<GameMessage>,
+}
+
+impl ScriptWorld {
+    fn sync(&mut self, state: &GameState) {
+        self.tick = state.tick;
+        self.wave = state.wave;
+        self.entities = state
+            .entities
+            .iter()
+            .map(|entity| {
+                let entity = entity.borrow();
+                EntitySnapshot {
+                    id: entity.id,
+                    entity_type: entity.entity_type,
+                    location: entity.location,
+                }
+            })
+            .collect();
+        self.zones = state
+            .zones
+            .iter()
+            .map(|zone| (zone.name.clone(), zone.open))
+            .collect();
+    }
+}
+
+fn parse_entity_type(name: &str) -> Option<GameEntityType> {
+    match name {
+        "player" => Some(GameEntityType::Player),
+        "enemy" => Some(GameEntityType::Enemy),
+        "structure" => Some(GameEntityType::Structure),
+        "zone" => Some(GameEntityType::Zone),
+        "projectile" => Some(GameEntityType::Projectile),
+        _ => None,
+    }
+}
+
+fn to_coordinate(value: INT) -> u32 {
+    if value < 0 {
+        0
+    } else if value > INT::from(u32::MAX) {
+        u32::MAX
+    } else {
+        value as u32
+    }
+}
+
+#[derive(Debug)]
+pub struct ScriptRuntime {
+    engine: Engine,
+    ast: AST,
+    world: Rc<RefCell<ScriptWorld>>,
+}
+
+impl ScriptRuntime {
+    pub fn from_file(path: &str) -> Result<ScriptRuntime, Box<dyn Error>> {
+        let source = fs::read_to_string(path)?;
+        ScriptRuntime::new(&source)
+    }
+
+    pub fn new(source: &str) -> Result<ScriptRuntime, Box<dyn Error>> {
+        let world = Rc::new(RefCell::new(ScriptWorld::default()));
+        let engine = build_engine(&world);
+
+        let ast = engine
+            .compile(source)
+            .map_err(|e| ScriptError::new(&format!("Unable to compile script: {}", e)))?;
+
+        // Top-level statements are only evaluated once, at load time.
+        engine
+            .run_ast_with_scope(&mut Scope::new(), &ast)
+            .map_err(|e| ScriptError::new(&format!("Unable to run script: {}", e)))?;
+
+        Ok(ScriptRuntime { engine, ast, world })
+    }
+
+    pub fn on_tick(&mut self, state: &mut GameState) {
+        let tick = INT::from(state.tick);
+        self.call_hook(state, "on_tick", tick);
+        self.fire_timers(state);
+    }
+
+    pub fn on_wave_start(&mut self, state: &mut GameState) {
+        let wave = INT::from(state.wave);
+        self.call_hook(state, "on_wave_start", wave);
+    }
+
+    fn fire_timers(&mut self, state: &mut GameState) {
+        loop {
+            let next = {
+                let world = self.world.borrow();
+                world.timers.keys().next().cloned()
+            };
+
+            match next {
+                Some((fire_at, name)) if fire_at <= state.tick => {
+                    self.world
+                        .borrow_mut()
+                        .timers
+                        .remove(&(fire_at, name.clone()));
+                    self.call_hook(state, "on_timer", name);
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn has_hook(&self, name: &str) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|f| f.name == name && f.params.len() == 1)
+    }
+
+    fn call_hook<A: Into<Dynamic>>(&mut self, state: &mut GameState, name: &str, arg: A) {
+        if !self.has_hook(name) {
+            return;
+        }
+
+        self.world.borrow_mut().sync(state);
+
+        let options = CallFnOptions::new().eval_ast(false);
+        let result = self.engine.call_fn_with_options::<Dynamic>(
+            options,
+            &mut Scope::new(),
+            &self.ast,
+            name,
+            (arg.into(),),
+        );
+
+        if let Err(e) = result {
+            // A broken script shouldn't take the whole game down with it.
+            error!("Map script error in {}: {}", name, e);
+        }
+
+        for message in self.world.borrow_mut().outbox.drain(..) {
+            state.post_message(message);
+        }
+    }
+}
+
+fn build_engine(world: &Rc<RefCell<ScriptWorld>>) -> Engine {
+    let mut engine = Engine::new();
+
+    engine.set_max_operations(MAX_SCRIPT_OPERATIONS);
+    engine.set_max_call_levels(MAX_SCRIPT_CALL_LEVELS);
+    engine.disable_symbol("eval");
+    engine.on_print(|s| info!("[script] {}", s));
+    engine.on_debug(|s, _, _| debug!("[script] {}", s));
+
+    let w = world.clone();
+    engine.register_fn("tick", move || INT::from(w.borrow().tick));
+    let w = world.clone();
+    engine.register_fn("wave", move || INT::from(w.borrow().wave));
+
+    // Entity queries
+    let w = world.clone();
+    engine.register_fn("entities", move |kind: &str| -> Array {
+        let entity_type = parse_entity_type(kind);
+        w.borrow()
+            .entities
+            .iter()
+            .filter(|e| Some(e.entity_type) == entity_type)
+            .map(|e| Dynamic::from(INT::from(e.id)))
+            .collect()
+    });
+    let w = world.clone();
+    engine.register_fn("position", move |id: INT| -> Array {
+        match w.borrow().entities.iter().find(|e| INT::from(e.id) == id) {
+            Some(e) => vec![
+                Dynamic::from(INT::from(*e.location.x())),
+                Dynamic::from(INT::from(*e.location.y())),
+            ],
+            None => Array::new(),
+        }
+    });
+
+    // Zones
+    let w = world.clone();
+    engine.register_fn("zone_open", move |name: &str| {
+        w.borrow()
+            .zones
+            .iter()
+            .any(|(zone, open)| zone == name && *open)
+    });
+    let w = world.clone();
+    engine.register_fn("open_zone", move |name: &str| {
+        w.borrow_mut().outbox.push(GameMessage::SetZoneOpen {
+            zone: name.to_string(),
+            open: true,
+        });
+    });
+    let w = world.clone();
+    engine.register_fn("close_zone", move |name: &str| {
+        w.borrow_mut().outbox.push(GameMessage::SetZoneOpen {
+            zone: name.to_string(),
+            open: false,
+        });
+    });
+
+    // Timers
+    let w = world.clone();
+    engine.register_fn("set_timer", move |name: &str, ticks: INT| {
+        let mut world = w.borrow_mut();
+        // Timers fire on a later tick at the soonest, so one which keeps
+        // setting itself again can't stop the tick from ending
+        let fire_at = world.tick.saturating_add(to_coordinate(ticks).max(1));
+        world.timers.retain(|(_, timer), _| timer != name);
+        world.timers.insert((fire_at, name.to_string()), ());
+    });
+    let w = world.clone();
+    engine.register_fn("cancel_timer", move |name: &str| {
+        w.borrow_mut().timers.retain(|(_, timer), _| timer != name);
+    });
+
+    // Message bus
+    let w = world.clone();
+    engine.register_fn("spawn_entity", move |archetype: &str, x: INT, y: INT| {
+        w.borrow_mut().outbox.push(GameMessage::Spawn {
+            archetype: archetype.to_string(),
+            location: Point::new(to_coordinate(x), to_coordinate(y)),
+        });
+    });
+    let w = world.clone();
+    engine.register_fn("move_entity", move |id: INT, x: INT, y: INT| {
+        w.borrow_mut().outbox.push(GameMessage::Move {
+            target: to_coordinate(id),
+            destination: Point::new(to_coordinate(x), to_coordinate(y)),
+        });
+    });
+    let w = world.clone();
+    engine.register_fn("trigger_ability", move |id: INT| {
+        w.borrow_mut().outbox.push(GameMessage::TriggerAbility {
+            target: to_coordinate(id),
+        });
+    });
+    let w = world.clone();
+    engine.register_fn("interact", move |source: INT, target: INT| {
+        w.borrow_mut().outbox.push(GameMessage::Interact {
+            source: to_coordinate(source),
+            target: to_coordinate(target),
+        });
+    });
+
+    engine
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::archetypes::test_archetypes;
+    use crate::game::entities::{GameEntityType, Zone};
+    use crate::game::scripting::*;
+    use crate::geometry::BoundingBox;
+
+    fn test_state() -> GameState {
+        let mut state = GameState::new(vec![Zone::new(
+            "gate",
+            BoundingBox::new(Point::new(0, 0), Point::new(10, 10)),
+        )]);
+        state.archetypes = test_archetypes();
+
+        state
+    }
+
+    #[test]
+    fn wave_hook_posts_messages() {
+        let mut scripts = ScriptRuntime::new(
+            r#"
+            fn on_wave_start(wave) {
+                if wave == 5 {
+                    spawn_entity("grunt", 50, 50);
+                    close_zone("gate");
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let mut state = test_state();
+
+        state.wave = 4;
+        scripts.on_wave_start(&mut state);
+        assert!(state.messages.is_empty());
+
+        state.wave = 5;
+        scripts.on_wave_start(&mut state);
+        assert_eq!(state.messages.len(), 2);
+
+        state.process_messages();
+        assert_eq!(state.entities.len(), 1);
+        assert_eq!(
+            state.entities[0].borrow().entity_type,
+            GameEntityType::Enemy
+        );
+        assert!(!state.zone("gate").unwrap().open);
+    }
+
+    #[test]
+    fn timers_fire_in_order() {
+        let mut scripts = ScriptRuntime::new(
+            r#"
+            fn on_tick(tick) {
+                if tick == 1 {
+                    set_timer("b", 2);
+                    set_timer("a", 2);
+                }
+            }
+            fn on_timer(name) {
+                if name == "a" { spawn_entity("grunt", 1, 1); }
+                if name == "b" { spawn_entity("cannon", 2, 2); }
+            }
+            "#,
+        )
+        .unwrap();
+        let mut state = test_state();
+
+        for _ in 0..3 {
+            state.tick += 1;
+            scripts.on_tick(&mut state);
+        }
+
+        assert_eq!(
+            state.messages,
+            vec![
+                GameMessage::Spawn {
+                    archetype: "grunt".to_string(),
+                    location: Point::new(1, 1),
+                },
+                GameMessage::Spawn {
+                    archetype: "cannon".to_string(),
+                    location: Point::new(2, 2),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn timers_set_for_now_fire_next_tick() {
+        let mut scripts = ScriptRuntime::new(
+            r#"
+            fn on_tick(tick) {
+                if tick == 1 { set_timer("again", 0); }
+            }
+            fn on_timer(name) {
+                spawn_entity("grunt", 1, 1);
+                set_timer(name, 0);
+            }
+            "#,
+        )
+        .unwrap();
+        let mut state = test_state();
+
+        for _ in 0..3 {
+            state.tick += 1;
+            scripts.on_tick(&mut state);
+        }
+
+        // Once on each of ticks 2 and 3
+        assert_eq!(state.messages.len(), 2);
+    }
+
+    #[test]
+    fn runaway_scripts_are_stopped() {
+        let mut scripts = ScriptRuntime::new("fn on_tick(tick) { loop { } }").unwrap();
+        let mut state = test_state();
+
+        // Should hit the operation limit and log rather than hang
+        scripts.on_tick(&mut state);
+        assert!(state.messages.is_empty());
+    }
+
+    #[test]
+    fn invalid_script_fails_to_load() {
+        assert!(ScriptRuntime::new("fn on_tick(tick) {").is_err());
+    }
+}
@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+
+use super::entities::EntityId;
+use crate::geometry::{BoundingBox, Point, Polygon};
+
+/// Default width and height of a grid cell, in world units. Roughly the
+/// range of a short-ranged tower, so most radius queries only touch a
+/// handful of cells.
+pub const DEFAULT_CELL_SIZE: u32 = 16;
+
+type Cell = (u32, u32);
+
+/// Uniform grid spatial index over entity positions.
+///
+/// Query results are always sorted (by ID, or by distance then ID for
+/// nearest queries) so that anything using them stays deterministic.
+#[derive(Debug)]
+pub struct SpatialGrid {
+    cell_size: u32,
+    cells: HashMap<Cell, Vec<EntityId>>,
+    positions: HashMap<EntityId, Point>,
+}
+
+impl Default for SpatialGrid {
+    fn default() -> SpatialGrid {
+        SpatialGrid::new(DEFAULT_CELL_SIZE)
+    }
+}
+
+impl SpatialGrid {
+    pub fn new(cell_size: u32) -> SpatialGrid {
+        assert!(cell_size > 0, "Spatial grid cell size must be non-zero");
+
+        SpatialGrid {
+            cell_size,
+            cells: HashMap::new(),
+            positions: HashMap::new(),
+        }
+    }
+
+    fn cell_of(&self, point: Point) -> Cell {
+        (point.x() / self.cell_size, point.y() / self.cell_size)
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn position(&self, id: EntityId) -> Option<Point> {
+        self.positions.get(&id).cloned()
+    }
+
+    /// Inserts the entity, or moves it if it is already in the index.
+    pub fn insert(&mut self, id: EntityId, point: Point) {
+        if let Some(old) = self.positions.insert(id, point) {
+            let old_cell = self.cell_of(old);
+            if old_cell == self.cell_of(point) {
+                return;
+            }
+            self.remove_from_cell(id, old_cell);
+        }
+
+        let cell = self.cell_of(point);
+        self.cells.entry(cell).or_default().push(id);
+    }
+
+    pub fn update(&mut self, id: EntityId, point: Point) {
+        self.insert(id, point);
+    }
+
+    pub fn remove(&mut self, id: EntityId) -> Option<Point> {
+        let point = self.positions.remove(&id)?;
+        let cell = self.cell_of(point);
+        self.remove_from_cell(id, cell);
+
+        Some(point)
+    }
+
+    fn remove_from_cell(&mut self, id: EntityId, cell: Cell) {
+        if let Some(ids) = self.cells.get_mut(&cell) {
+            ids.retain(|&other| other != id);
+            if ids.is_empty() {
+                self.cells.remove(&cell);
+            }
+        }
+    }
+
+    /// Calls `f` for every entity in cells overlapping the given range of cells.
+    fn for_each_in_cells<F: FnMut(EntityId, Point)>(&self, min: Cell, max: Cell, mut f: F) {
+        for cx in min.0..=max.0 {
+            for cy in min.1..=max.1 {
+                if let Some(ids) = self.cells.get(&(cx, cy)) {
+                    for &id in ids {
+                        f(id, self.positions[&id]);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Calls `f` for every entity in the square ring of cells exactly `ring`
+    /// cells away from `center`.
+    fn for_each_in_ring<F: FnMut(EntityId, Point)>(&self, center: Cell, ring: u32, mut f: F) {
+        let (cx, cy) = (i64::from(center.0), i64::from(center.1));
+        let ring = i64::from(ring);
+
+        let mut visit = |x: i64, y: i64| {
+            if x < 0 || y < 0 || x > i64::from(u32::MAX) || y > i64::from(u32::MAX) {
+                return;
+            }
+            if let Some(ids) = self.cells.get(&(x as u32, y as u32)) {
+                for &id in ids {
+                    f(id, self.positions[&id]);
+                }
+            }
+        };
+
+        if ring == 0 {
+            visit(cx, cy);
+            return;
+        }
+
+        for x in (cx - ring)..=(cx + ring) {
+            visit(x, cy - ring);
+            visit(x, cy + ring);
+        }
+        for y in (cy - ring + 1)..(cy + ring) {
+            visit(cx - ring, y);
+            visit(cx + ring, y);
+        }
+    }
+
+    /// All entities within `radius` of `center` (inclusive).
+    pub fn query_radius(&self, center: Point, radius: u32) -> Vec<EntityId> {
+        let min = self.cell_of(Point::new(
+            center.x().saturating_sub(radius),
+            center.y().saturating_sub(radius),
+        ));
+        let max = self.cell_of(Point::new(
+            center.x().saturating_add(radius),
+            center.y().saturating_add(radius),
+        ));
+        let radius_squared = u64::from(radius) * u64::from(radius);
+
+        let mut found = Vec::new();
+        self.for_each_in_cells(min, max, |id, point| {
+            if point.distance_squared(center) <= radius_squared {
+                found.push(id);
+            }
+        });
+        found.sort();
+
+        found
+    }
+
+    /// All entities inside `bbox`, using the same edge rules as
+    /// `BoundingBox::contains`.
+    pub fn query_box(&self, bbox: BoundingBox) -> Vec<EntityId> {
+        let min = self.cell_of(bbox.lower_left());
+        let max = self.cell_of(bbox.upper_right());
+
+        let mut found = Vec::new();
+        self.for_each_in_cells(min, max, |id, point| {
+            if bbox.contains(point) {
+                found.push(id);
+            }
+        });
+        found.sort();
+
+        found
+    }
+
+    /// The `k` entities closest to `center`, closest first. Ties are broken
+    /// by entity ID.
+    pub fn nearest_k(&self, center: Point, k: usize) -> Vec<EntityId> {
+        if k == 0 || self.is_empty() {
+            return Vec::new();
+        }
+
+        let (cx, cy) = self.cell_of(center);
+        // No occupied cell is further away than this many rings
+        let max_ring = self
+            .cells
+            .keys()
+            .map(|&(x, y)| (x.max(cx) - x.min(cx)).max(y.max(cy) - y.min(cy)))
+            .max()
+            .unwrap_or(0);
+
+        let mut candidates: Vec<(u64, EntityId)> = Vec::new();
+        for ring in 0..=max_ring {
+            self.for_each_in_ring((cx, cy), ring, |id, point| {
+                candidates.push((point.distance_squared(center), id));
+            });
+
+            if candidates.len() >= k {
+                candidates.sort();
+                // Anything in further rings is at least this far away
+                let ring_bound = u64::from(ring) * u64::from(self.cell_size);
+                if candidates[k - 1].0 <= ring_bound * ring_bound {
+                    break;
+                }
+            }
+        }
+
+        candidates.sort();
+        candidates.truncate(k);
+
+        candidates.into_iter().map(|(_, id)| id).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::spatial::*;
+
+    #[test]
+    fn insert_move_remove() {
+        let mut grid = SpatialGrid::new(10);
+        grid.insert(1, Point::new(5, 5));
+        grid.insert(2, Point::new(25, 25));
+
+        assert_eq!(grid.query_radius(Point::new(0, 0), 10), vec![1]);
+
+        grid.update(1, Point::new(50, 50));
+        assert!(grid.query_radius(Point::new(0, 0), 10).is_empty());
+        assert_eq!(grid.query_radius(Point::new(50, 50), 0), vec![1]);
+
+        assert_eq!(grid.remove(2), Some(Point::new(25, 25)));
+        assert_eq!(grid.remove(2), None);
+        assert_eq!(grid.len(), 1);
+    }
+
+    #[test]
+    fn radius_and_box_queries() {
+        let mut grid = SpatialGrid::new(4);
+        for i in 0..20 {
+            grid.insert(i, Point::new(i * 2, 10));
+        }
+
+        // x in 6..=14
+        assert_eq!(
+            grid.query_radius(Point::new(10, 10), 4),
+            vec![3, 4, 5, 6, 7]
+        );
+
+        let bbox = BoundingBox::new(Point::new(6, 0), Point::new(12, 20));
+        assert_eq!(grid.query_box(bbox), vec![4, 5]);
+    }
+
+    #[test]
+    fn nearest_matches_brute_force() {
+        let mut grid = SpatialGrid::new(8);
+        let mut points = Vec::new();
+        for i in 0..200u32 {
+            let point = Point::new((i * 37) % 150, (i * 91) % 130);
+            grid.insert(i, point);
+            points.push((i, point));
+        }
+
+        let center = Point::new(70, 40);
+        let mut expected: Vec<(u64, EntityId)> = points
+            .iter()
+            .map(|&(id, point)| (point.distance_squared(center), id))
+            .collect();
+        expected.sort();
+        let expected: Vec<EntityId> = expected.into_iter().take(7).map(|(_, id)| id).collect();
+
+        assert_eq!(grid.nearest_k(center, 7), expected);
+        assert_eq!(grid.nearest_k(center, 500).len(), 200);
+    }
+}
@@ -0,0 +1,243 @@
+//! Tower synergies: towers whose archetype declares an aura boost the stats
+//! of other towers around them, e.g. a radar extending the range of its
+//! neighbours.
+//!
+//! Buffs are stored on each `Tower` next to its base stats and recomputed from
+//! scratch whenever the set of towers changes, so they never go stale when a
+//! tower is sold.
+use super::entities::{EntityId, Tower};
+use super::GameState;
+use crate::serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum Stat {
+    Range,
+    Damage,
+    /// How often the tower fires. Its breakdown is in ticks between shots, so
+    /// boosting it shortens the fire interval.
+    FireRate,
+}
+
+/// Declared on a tower archetype to boost towers within `radius` of it.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Aura {
+    pub stat: Stat,
+    /// Added to the boosted stat, as a percentage of its base value.
+    pub percent: u32,
+    pub radius: u32,
+    /// Tower archetypes which are boosted. If empty, every tower is.
+    #[serde(default)]
+    pub targets: Vec<String>,
+}
+
+impl Aura {
+    pub fn applies_to(&self, archetype: Option<&str>) -> bool {
+        if self.targets.is_empty() {
+            return true;
+        }
+        match archetype {
+            Some(archetype) => self.targets.iter().any(|target| target == archetype),
+            None => false,
+        }
+    }
+}
+
+/// A single aura's contribution to a tower.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Buff {
+    pub source: EntityId,
+    pub stat: Stat,
+    pub percent: u32,
+}
+
+/// How a tower's effective stat is made up, for tooltips.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatBreakdown {
+    pub base: u32,
+    pub buffs: Vec<Buff>,
+    pub effective: u32,
+}
+
+impl Tower {
+    fn base_stat(&self, stat: Stat) -> u32 {
+        match stat {
+            Stat::Range => self.range,
+            Stat::Damage => self.damage,
+            Stat::FireRate => self.fire_interval,
+        }
+    }
+
+    fn bonus_percent(&self, stat: Stat) -> u32 {
+        self.buffs
+            .iter()
+            .filter(|buff| buff.stat == stat)
+            .map(|buff| buff.percent)
+            .sum()
+    }
+
+    pub fn effective_range(&self) -> u32 {
+        self.breakdown(Stat::Range).effective
+    }
+
+    pub fn effective_damage(&self) -> u32 {
+        self.breakdown(Stat::Damage).effective
+    }
+
+    /// Ticks between shots once fire rate buffs are taken into account.
+    pub fn effective_fire_interval(&self) -> u32 {
+        self.breakdown(Stat::FireRate).effective
+    }
+
+    pub fn breakdown(&self, stat: Stat) -> StatBreakdown {
+        let base = self.base_stat(stat);
+        let multiplier = u64::from(100 + self.bonus_percent(stat));
+
+        let effective = match stat {
+            // Firing faster means a shorter interval, but never less than a tick
+            Stat::FireRate => (u64::from(base) * 100 / multiplier).max(1),
+            _ => u64::from(base) * multiplier / 100,
+        };
+
+        StatBreakdown {
+            base,
+            buffs: self
+                .buffs
+                .iter()
+                .filter(|buff| buff.stat == stat)
+                .cloned()
+                .collect(),
+            effective: effective.min(u64::from(u32::MAX)) as u32,
+        }
+    }
+}
+
+/// Recomputes every tower's buffs if any towers have been added, removed or
+/// changed since the last time.
+pub fn update_synergies(state: &mut GameState) {
+    if state.take_synergies_dirty() {
+        recompute_synergies(state);
+    }
+}
+
+pub fn recompute_synergies(state: &GameState) {
+    for entity in state.entities.iter() {
+        if let Some(tower) = entity.borrow_mut().tower.as_mut() {
+            tower.buffs.clear();
+        }
+    }
+
+    for source in state.entities.iter() {
+        let (source_id, location, auras) = {
+            let source = source.borrow();
+            if source.tower.is_none() {
+                continue;
+            }
+            let archetype = match source
+                .archetype
+                .as_ref()
+                .and_then(|name| state.archetypes.tower(name))
+            {
+                Some(archetype) => archetype,
+                None => continue,
+            };
+            if archetype.auras.is_empty() {
+                continue;
+            }
+            (source.id, source.location, archetype.auras.clone())
+        };
+
+        for aura in auras.iter() {
+            for id in state.spatial.query_radius(location, aura.radius) {
+                // Towers don't buff themselves
+                if id == source_id {
+                    continue;
+                }
+
+                let target = match state.entity(id) {
+                    Some(target) => target,
+                    None => continue,
+                };
+                let mut target = target.borrow_mut();
+                let archetype = target.archetype.clone();
+                if let Some(tower) = target.tower.as_mut() {
+                    if aura.applies_to(archetype.as_deref()) {
+                        tower.buffs.push(Buff {
+                            source: source_id,
+                            stat: aura.stat,
+                            percent: aura.percent,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::archetypes::test_archetypes;
+    use crate::game::synergy::*;
+    use crate::geometry::Point;
+
+    fn state() -> GameState {
+        let mut state = GameState::new(Vec::new());
+        state.archetypes = test_archetypes();
+
+        state
+    }
+
+    fn add(state: &mut GameState, name: &str, x: u32) -> EntityId {
+        let entity = state
+            .archetypes
+            .instantiate(name, Point::new(x, 0))
+            .unwrap();
+        state.add_entity(entity)
+    }
+
+    fn breakdown(state: &GameState, id: EntityId, stat: Stat) -> StatBreakdown {
+        let entity = state.entity(id).unwrap();
+        let entity = entity.borrow();
+        entity.tower.as_ref().unwrap().breakdown(stat)
+    }
+
+    #[test]
+    fn auras_buff_nearby_towers() {
+        let mut state = state();
+        let arrow = add(&mut state, "arrow", 10);
+        let frost = add(&mut state, "frost", 14);
+        let far = add(&mut state, "arrow", 50);
+        let radar = add(&mut state, "radar", 12);
+
+        update_synergies(&mut state);
+
+        let range = breakdown(&state, arrow, Stat::Range);
+        assert_eq!(range.base, 20);
+        assert_eq!(range.effective, 30);
+        assert_eq!(range.buffs[0].source, radar);
+        assert_eq!(breakdown(&state, arrow, Stat::FireRate).effective, 7);
+
+        // Only arrows get the fire rate boost
+        assert_eq!(breakdown(&state, frost, Stat::Range).effective, 30);
+        assert_eq!(breakdown(&state, frost, Stat::FireRate).effective, 30);
+
+        assert!(breakdown(&state, far, Stat::Range).buffs.is_empty());
+        assert!(breakdown(&state, radar, Stat::Range).buffs.is_empty());
+
+        // Selling the radar takes its buffs away
+        state.remove_entity(radar);
+        update_synergies(&mut state);
+        assert_eq!(breakdown(&state, arrow, Stat::Range).effective, 20);
+    }
+
+    #[test]
+    fn auras_stack() {
+        let mut state = state();
+        let arrow = add(&mut state, "arrow", 10);
+        add(&mut state, "radar", 8);
+        add(&mut state, "radar", 12);
+
+        update_synergies(&mut state);
+        assert_eq!(breakdown(&state, arrow, Stat::Range).effective, 40);
+        assert_eq!(breakdown(&state, arrow, Stat::Range).buffs.len(), 2);
+    }
+}
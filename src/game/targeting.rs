@@ -0,0 +1,153 @@
+use super::entities::{EntityHandle, EntityId, GameEntity, GameEntityType, PlayerId};
+use super::GameState;
+use crate::geometry::Point;
+
+/// Keeps every tower's target up to date. Towers hold on to their target for
+/// as long as it is alive, in range and visible to them, and otherwise switch
+/// to the closest such enemy (or nothing).
+pub fn update_targets(state: &GameState) {
+    for entity in state.entities.iter() {
+        // Only lock towers for writing, other systems may be reading the rest
+        if entity.borrow().tower.is_none() {
+            continue;
+        }
+        let mut entity = entity.borrow_mut();
+        let id = entity.id;
+        let owner = entity.owner;
+        let location = entity.location;
+
+        let tower = match entity.tower.as_mut() {
+            Some(tower) => tower,
+            None => continue,
+        };
+
+        let range = tower.effective_range();
+        let range_squared = u64::from(range) * u64::from(range);
+        let keep = match tower.target {
+            Some(ref handle) if handle.id() != id => match handle.upgrade() {
+                Some(target) => {
+                    let target = target.borrow();
+                    target.location.distance_squared(location) <= range_squared
+                        && can_see(state, owner, &target)
+                }
+                // Target was despawned since the last tick
+                None => false,
+            },
+            _ => false,
+        };
+
+        if !keep {
+            tower.target = find_target(state, id, owner, location, range);
+        }
+    }
+}
+
+/// Whether `player` can see `entity`, which is always the case without fog of
+/// war.
+fn can_see(state: &GameState, player: PlayerId, entity: &GameEntity) -> bool {
+    match state.fog {
+        Some(ref fog) => fog.can_target(player, entity),
+        None => true,
+    }
+}
+
+/// The closest enemy within `range` of `location` which `player` can see.
+/// Ties go to the lowest ID.
+pub fn find_target(
+    state: &GameState,
+    searcher: EntityId,
+    player: PlayerId,
+    location: Point,
+    range: u32,
+) -> Option<EntityHandle> {
+    let mut best: Option<(u64, EntityHandle)> = None;
+
+    for id in state.spatial.query_radius(location, range) {
+        if id == searcher {
+            continue;
+        }
+
+        let candidate = match state.entity(id) {
+            Some(candidate) => candidate,
+            None => continue,
+        };
+        let distance = {
+            let candidate = candidate.borrow();
+            if candidate.entity_type != GameEntityType::Enemy || !can_see(state, player, &candidate)
+            {
+                continue;
+            }
+            candidate.location.distance_squared(location)
+        };
+
+        let closer = match best {
+            Some((best_distance, _)) => distance < best_distance,
+            None => true,
+        };
+        if closer {
+            best = Some((distance, EntityHandle::new(&candidate)));
+        }
+    }
+
+    best.map(|(_, handle)| handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::combat::DamageType;
+    use crate::game::entities::{GameEntity, GameEntityType, Tower};
+    use crate::game::targeting::*;
+
+    #[test]
+    fn towers_retarget_when_target_dies() {
+        let mut state = GameState::new(Vec::new());
+
+        let near = state.spawn_entity(GameEntityType::Enemy, Point::new(12, 10));
+        let far = state.spawn_entity(GameEntityType::Enemy, Point::new(15, 10));
+        state.spawn_entity(GameEntityType::Enemy, Point::new(90, 90));
+
+        let mut tower = GameEntity::new(0, GameEntityType::Structure, Point::new(10, 10));
+        tower.tower = Some(Tower {
+            range: 10,
+            damage: 1,
+            damage_type: DamageType::Physical,
+            fire_interval: 1,
+            cooldown: 0,
+            effects: Vec::new(),
+            abilities: Vec::new(),
+            target: None,
+            buffs: Vec::new(),
+        });
+        let tower = state.add_entity(tower);
+
+        let target_of = |state: &GameState| {
+            let tower = state.entity(tower).unwrap();
+            let tower = tower.borrow();
+            tower
+                .tower
+                .as_ref()
+                .unwrap()
+                .target
+                .as_ref()
+                .map(|t| t.id())
+        };
+
+        update_targets(&state);
+        assert_eq!(target_of(&state), Some(near));
+
+        let handle = state.handle(near).unwrap();
+        state.commands.despawn(near);
+
+        // Despawns are deferred until the sync point
+        assert!(handle.is_alive());
+        state.apply_commands();
+        assert!(!handle.is_alive());
+
+        update_targets(&state);
+        assert_eq!(target_of(&state), Some(far));
+
+        state.set_entity_location(far, Point::new(50, 50));
+        update_targets(&state);
+        assert_eq!(target_of(&state), None);
+    }
+}
@@ -0,0 +1,64 @@
+//! What the ground is like across the map. Terrain slows down (or stops)
+//! ground units, decides where towers can be built and can block sight.
+//! Each map tile has a terrain type; see `tiles`.
+use crate::serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum Terrain {
+    #[default]
+    Grass,
+    Road,
+    Water,
+    Cliff,
+    Mud,
+}
+
+impl Terrain {
+    /// How much it costs ground units to cross, as a percentage of crossing
+    /// grass. `None` if they can't cross it at all.
+    pub fn movement_cost(self) -> Option<u32> {
+        match self {
+            Terrain::Grass => Some(100),
+            Terrain::Road => Some(50),
+            Terrain::Mud => Some(200),
+            Terrain::Water | Terrain::Cliff => None,
+        }
+    }
+
+    /// Speed of a ground unit on this terrain, given its speed on grass.
+    /// Units always make some progress on terrain they can cross.
+    pub fn ground_speed(self, speed: u32) -> u32 {
+        match self.movement_cost() {
+            Some(cost) => (speed * 100 / cost).max(1),
+            None => 0,
+        }
+    }
+
+    /// Whether towers can normally be built here. Tiles can override this,
+    /// and towers which list the terrain they need ignore it.
+    pub fn is_buildable(self) -> bool {
+        match self {
+            Terrain::Grass | Terrain::Mud => true,
+            Terrain::Road | Terrain::Water | Terrain::Cliff => false,
+        }
+    }
+
+    pub fn blocks_sight(self) -> bool {
+        self == Terrain::Cliff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::terrain::*;
+
+    #[test]
+    fn terrain_properties() {
+        assert_eq!(Terrain::Road.ground_speed(2), 4);
+        assert_eq!(Terrain::Mud.ground_speed(1), 1);
+        assert_eq!(Terrain::Water.ground_speed(2), 0);
+        assert!(Terrain::Mud.is_buildable());
+        assert!(!Terrain::Water.is_buildable());
+        assert!(Terrain::Cliff.blocks_sight());
+    }
+}
@@ -0,0 +1,829 @@
+//! Imports maps made with the Tiled editor (<https://www.mapeditor.org/>),
+//! saved as JSON (`.tmj`).
+//!
+//! Tiles get their layers from the custom properties of their tileset tiles:
+//! `terrain` (a terrain name, or the tile's class), `buildable` (a bool),
+//! `decoration` (a model name) and `path_hint` (`prefer` or `avoid`). Tile
+//! layers are applied in order, and a property on a later layer's tile
+//! replaces the same property from an earlier one.
+//!
+//! Objects are read by their class: `spawn` and `goal` are points, while
+//! `zone` and `sight_blocker` are rectangles. Zones take their name from the
+//! object and can be closed with an `open` property. The map itself can set
+//! its `name`, `script` and `tile_size` in world units.
+//!
+//! Anything which can't be represented, such as isometric maps or compressed
+//! layers, is an error rather than being quietly dropped.
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use serde_json::Value;
+
+use super::entities::Zone;
+use super::map::GameMap;
+use super::terrain::Terrain;
+use super::tiles::{Buildability, Decoration, GridShape, PathHint, TileMap, DEFAULT_TILE_SIZE};
+use crate::geometry::{BoundingBox, Point};
+use crate::serde_derive::Deserialize;
+
+/// Tiled keeps whether a tile is flipped or rotated in the top bits of its ID.
+const FLIP_FLAGS: u32 = 0xf000_0000;
+
+#[derive(Debug)]
+pub struct TiledError {
+    details: String,
+}
+
+impl TiledError {
+    fn new(msg: &str) -> TiledError {
+        TiledError {
+            details: msg.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for TiledError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for TiledError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TiledMap {
+    #[serde(default)]
+    orientation: String,
+    width: u32,
+    height: u32,
+    tilewidth: u32,
+    tileheight: u32,
+    #[serde(default)]
+    infinite: bool,
+    #[serde(default)]
+    hexsidelength: u32,
+    #[serde(default)]
+    staggeraxis: String,
+    #[serde(default)]
+    staggerindex: String,
+    #[serde(default)]
+    layers: Vec<Layer>,
+    #[serde(default)]
+    tilesets: Vec<Tileset>,
+    #[serde(default)]
+    properties: Vec<Property>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Layer {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    width: u32,
+    #[serde(default)]
+    height: u32,
+    /// Tile IDs, or a base64 string if the layer is encoded.
+    #[serde(default)]
+    data: Value,
+    #[serde(default)]
+    encoding: String,
+    #[serde(default)]
+    objects: Vec<Object>,
+    /// Layers inside a group layer.
+    #[serde(default)]
+    layers: Vec<Layer>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Object {
+    #[serde(default)]
+    id: u32,
+    #[serde(default)]
+    name: String,
+    /// Called `class` by some versions of Tiled.
+    #[serde(default, rename = "type")]
+    kind: String,
+    #[serde(default)]
+    class: String,
+    x: f64,
+    y: f64,
+    #[serde(default)]
+    width: f64,
+    #[serde(default)]
+    height: f64,
+    #[serde(default)]
+    ellipse: bool,
+    #[serde(default)]
+    polygon: Option<Value>,
+    #[serde(default)]
+    polyline: Option<Value>,
+    #[serde(default)]
+    text: Option<Value>,
+    #[serde(default)]
+    gid: Option<u32>,
+    #[serde(default)]
+    properties: Vec<Property>,
+}
+
+impl Object {
+    fn class(&self) -> &str {
+        if self.kind.is_empty() {
+            &self.class
+        } else {
+            &self.kind
+        }
+    }
+
+    fn describe(&self) -> String {
+        if self.name.is_empty() {
+            format!("object {}", self.id)
+        } else {
+            format!("object {} '{}'", self.id, self.name)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Tileset {
+    /// Missing from tilesets in their own file.
+    #[serde(default)]
+    firstgid: u32,
+    /// Set if the tileset is in its own file.
+    #[serde(default)]
+    source: Option<String>,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    tiles: Vec<TileDefinition>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TileDefinition {
+    id: u32,
+    #[serde(default, rename = "type")]
+    kind: String,
+    #[serde(default)]
+    class: String,
+    #[serde(default)]
+    properties: Vec<Property>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Property {
+    name: String,
+    value: Value,
+}
+
+fn property<'a>(properties: &'a [Property], name: &str) -> Option<&'a Value> {
+    properties
+        .iter()
+        .find(|property| property.name == name)
+        .map(|property| &property.value)
+}
+
+fn string_property<'a>(
+    properties: &'a [Property],
+    name: &str,
+    owner: &str,
+) -> Result<Option<&'a str>, TiledError> {
+    match property(properties, name) {
+        Some(value) => match value.as_str() {
+            Some(value) => Ok(Some(value)),
+            None => Err(TiledError::new(&format!(
+                "{}: '{}' should be a string",
+                owner, name
+            ))),
+        },
+        None => Ok(None),
+    }
+}
+
+fn bool_property(
+    properties: &[Property],
+    name: &str,
+    owner: &str,
+) -> Result<Option<bool>, TiledError> {
+    match property(properties, name) {
+        Some(value) => match value.as_bool() {
+            Some(value) => Ok(Some(value)),
+            None => Err(TiledError::new(&format!(
+                "{}: '{}' should be a bool",
+                owner, name
+            ))),
+        },
+        None => Ok(None),
+    }
+}
+
+/// The layers a tileset tile sets. Layers it has no property for are left
+/// as they are.
+#[derive(Debug, Default, Clone)]
+struct TileProperties {
+    terrain: Option<Terrain>,
+    buildable: Option<Buildability>,
+    decoration: Option<Decoration>,
+    path_hint: Option<PathHint>,
+}
+
+fn parse_terrain(name: &str) -> Option<Terrain> {
+    match name.to_lowercase().as_str() {
+        "grass" => Some(Terrain::Grass),
+        "road" => Some(Terrain::Road),
+        "water" => Some(Terrain::Water),
+        "cliff" => Some(Terrain::Cliff),
+        "mud" => Some(Terrain::Mud),
+        _ => None,
+    }
+}
+
+/// Where the tiles of a Tiled map go on ours, and where its pixels go in
+/// the world.
+struct Layout {
+    width: u32,
+    height: u32,
+    shape: GridShape,
+    tile_size: u32,
+    /// Rows added below the map so hexagon rows are pushed the same way
+    /// once the map is turned the right way up.
+    padding: u32,
+    tiled_tile_width: f64,
+    tiled_tile_height: f64,
+    /// Distance between rows in Tiled's pixels and in world units.
+    tiled_row_height: f64,
+    row_height: f64,
+}
+
+impl Layout {
+    fn new(tiled: &TiledMap, tile_size: u32) -> Result<Layout, TiledError> {
+        if tiled.infinite {
+            return Err(TiledError::new(
+                "infinite maps aren't supported, give the map a fixed size",
+            ));
+        }
+        if tiled.width == 0 || tiled.height == 0 || tiled.tilewidth == 0 || tiled.tileheight == 0 {
+            return Err(TiledError::new("the map has no tiles"));
+        }
+
+        let (shape, padding, tiled_row_height, row_height) = match tiled.orientation.as_str() {
+            "orthogonal" | "" => (
+                GridShape::Square,
+                0,
+                f64::from(tiled.tileheight),
+                f64::from(tile_size),
+            ),
+            "hexagonal" => {
+                if tiled.staggeraxis != "y" {
+                    return Err(TiledError::new(
+                        "only hexagonal maps with a stagger axis of y are supported",
+                    ));
+                }
+                // Tiled counts rows from the top and pushes either odd or
+                // even ones, while ours count from the bottom and push odd
+                // ones, so the bottom row has to be odd or even to match
+                let odd_height = tiled.height % 2 == 1;
+                let bottom_row_pushed = match tiled.staggerindex.as_str() {
+                    "odd" => !odd_height,
+                    "even" => odd_height,
+                    other => {
+                        return Err(TiledError::new(&format!(
+                            "unknown stagger index '{}'",
+                            other
+                        )))
+                    }
+                };
+                (
+                    GridShape::Hex,
+                    if bottom_row_pushed { 1 } else { 0 },
+                    f64::from(tiled.tileheight + tiled.hexsidelength) / 2.0,
+                    f64::from(tile_size) * 3f64.sqrt() / 2.0,
+                )
+            }
+            other => {
+                return Err(TiledError::new(&format!(
+                    "{} maps aren't supported, only orthogonal and hexagonal ones",
+                    other
+                )))
+            }
+        };
+
+        Ok(Layout {
+            width: tiled.width,
+            height: tiled.height,
+            shape,
+            tile_size,
+            padding,
+            tiled_tile_width: f64::from(tiled.tilewidth),
+            tiled_tile_height: f64::from(tiled.tileheight),
+            tiled_row_height,
+            row_height,
+        })
+    }
+
+    fn dimensions(&self) -> BoundingBox {
+        let rows = f64::from(self.height + self.padding);
+        let mut width = self.width * self.tile_size;
+        if self.shape == GridShape::Hex {
+            width += self.tile_size / 2;
+        }
+        BoundingBox::new(
+            Point::new(0, 0),
+            Point::new(width, (rows * self.row_height).round() as u32),
+        )
+    }
+
+    /// Our column and row for one of Tiled's tiles.
+    fn cell(&self, column: u32, row: u32) -> (u32, u32) {
+        (column, self.height - 1 - row + self.padding)
+    }
+
+    /// The world location of a point in Tiled's pixels, which count down
+    /// from the top left of the map.
+    fn point(&self, x: f64, y: f64) -> Point {
+        let half = f64::from(self.tile_size / 2);
+        let top_row = f64::from(self.height - 1 + self.padding) * self.row_height + half;
+        let world_x = x * f64::from(self.tile_size) / self.tiled_tile_width;
+        let world_y =
+            top_row - (y - self.tiled_tile_height / 2.0) * self.row_height / self.tiled_row_height;
+
+        Point::new(
+            world_x.max(0.0).round() as u32,
+            world_y.max(0.0).round() as u32,
+        )
+    }
+}
+
+/// Imports a Tiled map. Tilesets saved in their own files are looked for
+/// next to it.
+pub fn import(path: &str) -> Result<GameMap, Box<dyn Error>> {
+    let json = fs::read_to_string(path)?;
+    let path = Path::new(path);
+    let name = path
+        .file_stem()
+        .map_or("Imported Map".into(), |stem| stem.to_string_lossy());
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+    from_json(&json, &name, dir).map_err(|e| {
+        Box::new(TiledError::new(&format!("{}: {}", path.display(), e))) as Box<dyn Error>
+    })
+}
+
+/// Converts a Tiled map, named `name` unless it has a `name` property.
+/// Tilesets in their own files are read from `dir`.
+pub fn from_json(json: &str, name: &str, dir: &Path) -> Result<GameMap, TiledError> {
+    let tiled: TiledMap = serde_json::from_str(json)
+        .map_err(|e| TiledError::new(&format!("invalid Tiled map: {}", e)))?;
+
+    let tile_size = match property(&tiled.properties, "tile_size") {
+        Some(value) => match value.as_u64() {
+            Some(size) if size > 0 && size <= u64::from(u16::MAX) => size as u32,
+            _ => return Err(TiledError::new("'tile_size' should be a positive int")),
+        },
+        None => DEFAULT_TILE_SIZE,
+    };
+    let layout = Layout::new(&tiled, tile_size)?;
+
+    let name = string_property(&tiled.properties, "name", "map")?.unwrap_or(name);
+    let mut map = GameMap::new(name, layout.dimensions());
+    map.tiles = TileMap::with_shape(map.dimensions, tile_size, layout.shape);
+    map.script = string_property(&tiled.properties, "script", "map")?.map(String::from);
+
+    let tilesets = load_tilesets(&tiled, dir, &mut map.decorations)?;
+    for layer in tiled.layers.iter() {
+        import_layer(layer, &layout, &tilesets, &mut map)?;
+    }
+
+    Ok(map)
+}
+
+/// Every tileset's first tile ID, with the properties of its tiles.
+type Tilesets = Vec<(u32, HashMap<u32, TileProperties>)>;
+
+fn load_tilesets(
+    tiled: &TiledMap,
+    dir: &Path,
+    decorations: &mut Vec<String>,
+) -> Result<Tilesets, TiledError> {
+    let mut tilesets = Vec::new();
+
+    for tileset in tiled.tilesets.iter() {
+        let external;
+        let tileset = match tileset.source {
+            Some(ref source) => {
+                if !source.ends_with(".tsj") && !source.ends_with(".json") {
+                    return Err(TiledError::new(&format!(
+                        "tileset '{}' isn't JSON, export it from Tiled as a .tsj file",
+                        source
+                    )));
+                }
+                let json = fs::read_to_string(dir.join(source)).map_err(|e| {
+                    TiledError::new(&format!("can't read tileset '{}': {}", source, e))
+                })?;
+                let mut loaded: Tileset = serde_json::from_str(&json).map_err(|e| {
+                    TiledError::new(&format!("invalid tileset '{}': {}", source, e))
+                })?;
+                loaded.firstgid = tileset.firstgid;
+                external = loaded;
+                &external
+            }
+            None => tileset,
+        };
+
+        let mut tiles = HashMap::new();
+        for tile in tileset.tiles.iter() {
+            let owner = format!("tileset '{}' tile {}", tileset.name, tile.id);
+            let mut found = TileProperties::default();
+
+            let class = if tile.kind.is_empty() {
+                &tile.class
+            } else {
+                &tile.kind
+            };
+            found.terrain = match string_property(&tile.properties, "terrain", &owner)? {
+                Some(terrain) => match parse_terrain(terrain) {
+                    Some(terrain) => Some(terrain),
+                    None => {
+                        return Err(TiledError::new(&format!(
+                            "{}: unknown terrain '{}'",
+                            owner, terrain
+                        )))
+                    }
+                },
+                None => parse_terrain(class),
+            };
+            found.buildable =
+                bool_property(&tile.properties, "buildable", &owner)?.map(|buildable| {
+                    if buildable {
+                        Buildability::Always
+                    } else {
+                        Buildability::Never
+                    }
+                });
+            if let Some(model) = string_property(&tile.properties, "decoration", &owner)? {
+                let index = match decorations.iter().position(|d| d == model) {
+                    Some(i) => i,
+                    None => {
+                        decorations.push(model.to_string());
+                        decorations.len() - 1
+                    }
+                };
+                // Decorations count from 1, leaving 0 for none
+                found.decoration = Some(index as Decoration + 1);
+            }
+            found.path_hint = match string_property(&tile.properties, "path_hint", &owner)? {
+                Some(hint) => match hint.to_lowercase().as_str() {
+                    "none" => Some(PathHint::None),
+                    "prefer" => Some(PathHint::Prefer),
+                    "avoid" => Some(PathHint::Avoid),
+                    _ => {
+                        return Err(TiledError::new(&format!(
+                            "{}: unknown path hint '{}'",
+                            owner, hint
+                        )))
+                    }
+                },
+                None => None,
+            };
+
+            tiles.insert(tile.id, found);
+        }
+        tilesets.push((tileset.firstgid, tiles));
+    }
+
+    // Looking tiles up relies on the tilesets being in order
+    tilesets.sort_by_key(|(first, _)| *first);
+    Ok(tilesets)
+}
+
+fn import_layer(
+    layer: &Layer,
+    layout: &Layout,
+    tilesets: &Tilesets,
+    map: &mut GameMap,
+) -> Result<(), TiledError> {
+    match layer.kind.as_str() {
+        "tilelayer" => import_tiles(layer, layout, tilesets, map),
+        "objectgroup" => {
+            for object in layer.objects.iter() {
+                import_object(object, layout, map)?;
+            }
+            Ok(())
+        }
+        "group" => {
+            for inner in layer.layers.iter() {
+                import_layer(inner, layout, tilesets, map)?;
+            }
+            Ok(())
+        }
+        other => Err(TiledError::new(&format!(
+            "layer '{}': {} layers aren't supported",
+            layer.name, other
+        ))),
+    }
+}
+
+fn import_tiles(
+    layer: &Layer,
+    layout: &Layout,
+    tilesets: &Tilesets,
+    map: &mut GameMap,
+) -> Result<(), TiledError> {
+    let owner = format!("layer '{}'", layer.name);
+    if !layer.encoding.is_empty() && layer.encoding != "csv" {
+        return Err(TiledError::new(&format!(
+            "{}: {} tile data isn't supported, set the layer format to CSV",
+            owner, layer.encoding
+        )));
+    }
+    if layer.width != layout.width || layer.height != layout.height {
+        return Err(TiledError::new(&format!(
+            "{}: isn't the same size as the map",
+            owner
+        )));
+    }
+    let ids: Vec<u32> = serde_json::from_value(layer.data.clone())
+        .map_err(|e| TiledError::new(&format!("{}: invalid tile data: {}", owner, e)))?;
+    if ids.len() != (layout.width * layout.height) as usize {
+        return Err(TiledError::new(&format!(
+            "{}: has {} tiles, expected {}",
+            owner,
+            ids.len(),
+            layout.width * layout.height
+        )));
+    }
+
+    for (i, id) in ids.iter().enumerate() {
+        let id = id & !FLIP_FLAGS;
+        if id == 0 {
+            continue;
+        }
+
+        let tileset = tilesets.iter().rev().find(|(first, _)| *first <= id);
+        let properties = match tileset {
+            Some((first, tiles)) => match tiles.get(&(id - first)) {
+                Some(properties) => properties,
+                None => continue,
+            },
+            None => {
+                return Err(TiledError::new(&format!(
+                    "{}: tile {} isn't in any tileset",
+                    owner, id
+                )))
+            }
+        };
+
+        let i = i as u32;
+        let (x, y) = layout.cell(i % layout.width, i / layout.width);
+        let tiles = &mut map.tiles;
+        if let Some(terrain) = properties.terrain {
+            tiles.set_terrain(x, y, terrain);
+        }
+        if let Some(buildable) = properties.buildable {
+            tiles.set_buildable(x, y, buildable);
+        }
+        if let Some(decoration) = properties.decoration {
+            tiles.set_decoration(x, y, decoration);
+        }
+        if let Some(hint) = properties.path_hint {
+            tiles.set_path_hint(x, y, hint);
+        }
+    }
+
+    Ok(())
+}
+
+fn import_object(object: &Object, layout: &Layout, map: &mut GameMap) -> Result<(), TiledError> {
+    let owner = object.describe();
+    let unsupported = |what: &str| {
+        Err(TiledError::new(&format!(
+            "{}: {} aren't supported",
+            owner, what
+        )))
+    };
+    if object.gid.is_some() {
+        return unsupported("tile objects");
+    }
+    if object.text.is_some() {
+        return unsupported("text objects");
+    }
+
+    let shaped = object.ellipse || object.polygon.is_some() || object.polyline.is_some();
+    let sized = object.width > 0.0 && object.height > 0.0;
+    // Objects are placed by their top left corner, y counting downwards
+    let area = || {
+        let top_left = layout.point(object.x, object.y);
+        let bottom_right = layout.point(object.x + object.width, object.y + object.height);
+        BoundingBox::new(
+            Point::new(*top_left.x(), *bottom_right.y()),
+            Point::new(*bottom_right.x(), *top_left.y()),
+        )
+    };
+    let location = || {
+        if sized {
+            layout.point(
+                object.x + object.width / 2.0,
+                object.y + object.height / 2.0,
+            )
+        } else {
+            layout.point(object.x, object.y)
+        }
+    };
+
+    match object.class().to_lowercase().as_str() {
+        "spawn" => map.spawns.push(snap(&map.tiles, location())),
+        "goal" => map.goals.push(snap(&map.tiles, location())),
+        "zone" | "sight_blocker" if shaped || !sized => {
+            return Err(TiledError::new(&format!(
+                "{}: {}s have to be rectangles",
+                owner,
+                object.class()
+            )))
+        }
+        "zone" => {
+            let name = if object.name.is_empty() {
+                format!("zone {}", object.id)
+            } else {
+                object.name.clone()
+            };
+            let mut zone = Zone::new(&name, area());
+            if let Some(open) = bool_property(&object.properties, "open", &owner)? {
+                zone.open = open;
+            }
+            map.zones.push(zone);
+        }
+        "sight_blocker" => map.sight_blockers.push(area()),
+        "" => {
+            return Err(TiledError::new(&format!(
+                "{}: has no class, expected spawn, goal, zone or sight_blocker",
+                owner
+            )))
+        }
+        other => {
+            return Err(TiledError::new(&format!(
+                "{}: unknown class '{}', expected spawn, goal, zone or sight_blocker",
+                owner, other
+            )))
+        }
+    }
+
+    Ok(())
+}
+
+/// Spawns and goals go where the pathfinding grid samples their tile: the
+/// lower left corner of squares and the middle of hexagons.
+fn snap(tiles: &TileMap, point: Point) -> Point {
+    match tiles.cell_at(point) {
+        Some((x, y)) if tiles.shape() == GridShape::Square => {
+            let origin = tiles.origin();
+            Point::new(
+                origin.x() + x * tiles.tile_size(),
+                origin.y() + y * tiles.tile_size(),
+            )
+        }
+        Some((x, y)) => tiles.center(x, y),
+        None => point,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::tiled::*;
+
+    /// A 4x3 map of 16 pixel tiles, with a road along the middle row.
+    fn tiled_json(orientation: &str, objects: &str) -> String {
+        format!(
+            r#"{{
+                "type": "map",
+                "orientation": "{}",
+                "staggeraxis": "y",
+                "staggerindex": "even",
+                "hexsidelength": 8,
+                "width": 4,
+                "height": 3,
+                "tilewidth": 16,
+                "tileheight": 16,
+                "infinite": false,
+                "properties": [{{ "name": "name", "type": "string", "value": "River Crossing" }}],
+                "tilesets": [{{
+                    "firstgid": 1,
+                    "name": "ground",
+                    "tiles": [
+                        {{ "id": 0, "type": "Road" }},
+                        {{ "id": 1, "properties": [
+                            {{ "name": "terrain", "type": "string", "value": "water" }},
+                            {{ "name": "decoration", "type": "string", "value": "lily" }}
+                        ] }},
+                        {{ "id": 2, "properties": [{{ "name": "buildable", "type": "bool", "value": true }}] }}
+                    ]
+                }}],
+                "layers": [
+                    {{ "type": "tilelayer", "name": "ground", "width": 4, "height": 3,
+                       "data": [0, 2, 0, 0, 1, 1, 1, 1, 0, 0, 0, 2147483650] }},
+                    {{ "type": "group", "name": "markers", "layers": [
+                        {{ "type": "tilelayer", "name": "build", "width": 4, "height": 3,
+                           "data": [3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] }},
+                        {{ "type": "objectgroup", "name": "objects", "objects": [{}] }}
+                    ] }}
+                ]
+            }}"#,
+            orientation, objects
+        )
+    }
+
+    const OBJECTS: &str = r#"
+        { "id": 1, "type": "spawn", "x": 2, "y": 24, "point": true },
+        { "id": 2, "class": "goal", "x": 56, "y": 16, "width": 8, "height": 16 },
+        { "id": 3, "name": "lake", "type": "zone", "x": 16, "y": 0, "width": 16, "height": 16,
+          "properties": [{ "name": "open", "type": "bool", "value": false }] }
+    "#;
+
+    fn import(orientation: &str, objects: &str) -> Result<GameMap, TiledError> {
+        from_json(&tiled_json(orientation, objects), "test", Path::new("."))
+    }
+
+    #[test]
+    fn tiles_and_objects_are_imported() {
+        let map = import("orthogonal", OBJECTS).unwrap();
+        let tiles = &map.tiles;
+
+        assert_eq!(map.name, "River Crossing");
+        assert_eq!(map.dimensions.upper_right(), Point::new(16, 12));
+
+        // Tiled's top row is our highest one
+        assert_eq!(tiles.tile(1, 2).unwrap().terrain, Terrain::Water);
+        assert_eq!(map.decorations, vec!["lily"]);
+        assert_eq!(tiles.tile(1, 2).unwrap().decoration, 1);
+        assert_eq!(tiles.tile(0, 2).unwrap().buildable, Buildability::Always);
+        assert!((0..4).all(|x| tiles.tile(x, 1).unwrap().terrain == Terrain::Road));
+        // Flipped tiles are still the same tile
+        assert_eq!(tiles.tile(3, 0).unwrap().terrain, Terrain::Water);
+        assert_eq!(tiles.tile(0, 0).unwrap().terrain, Terrain::Grass);
+
+        assert_eq!(map.spawns, vec![Point::new(0, 4)]);
+        assert_eq!(map.goals, vec![Point::new(12, 4)]);
+        let lake = &map.zones[0];
+        assert_eq!(lake.name, "lake");
+        assert!(!lake.open);
+        assert_eq!(lake.get_bounding_box().lower_left(), Point::new(4, 8));
+        assert_eq!(lake.get_bounding_box().upper_right(), Point::new(8, 12));
+    }
+
+    #[test]
+    fn hexagonal_maps_keep_their_rows_pushed_the_same_way() {
+        let map = import("hexagonal", OBJECTS).unwrap();
+        let tiles = &map.tiles;
+
+        assert_eq!(tiles.shape(), GridShape::Hex);
+        // Tiled pushes the top and bottom rows right, so an extra row goes
+        // below them to put them on odd rows of ours
+        assert!((0..4).all(|x| tiles.tile(x, 2).unwrap().terrain == Terrain::Road));
+        assert_eq!(tiles.tile(1, 3).unwrap().terrain, Terrain::Water);
+
+        let spawn = map.spawns[0];
+        assert_eq!(tiles.cell_at(spawn), Some((0, 2)));
+    }
+
+    #[test]
+    fn unsupported_features_are_errors() {
+        let error = |orientation: &str, objects: &str| {
+            import(orientation, objects).unwrap_err().to_string()
+        };
+
+        assert_eq!(
+            error("isometric", ""),
+            "isometric maps aren't supported, only orthogonal and hexagonal ones"
+        );
+        assert_eq!(
+            error(
+                "orthogonal",
+                r#"{ "id": 7, "type": "tower", "x": 0, "y": 0 }"#
+            ),
+            "object 7: unknown class 'tower', expected spawn, goal, zone or sight_blocker"
+        );
+        assert_eq!(
+            error(
+                "orthogonal",
+                r#"{ "id": 8, "name": "pond", "type": "zone", "x": 0, "y": 0, "width": 8, "height": 8, "ellipse": true }"#
+            ),
+            "object 8 'pond': zones have to be rectangles"
+        );
+
+        let compressed = tiled_json("orthogonal", "").replace(
+            r#""name": "ground", "width""#,
+            r#""name": "ground", "encoding": "base64", "compression": "zlib", "width""#,
+        );
+        let error = from_json(&compressed, "test", Path::new(".")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "layer 'ground': base64 tile data isn't supported, set the layer format to CSV"
+        );
+    }
+}
@@ -0,0 +1,554 @@
+//! The map's tile grid.
+//!
+//! Every tile has several layers: its terrain, whether towers may be built
+//! on it, a decoration for the renderer and a hint for pathfinding. Tiles are
+//! addressed by column and row, counting from the lower left of the map.
+//!
+//! Tiles are either squares or pointy-topped hexagons. Hexagon rows are
+//! packed closer together than square ones, and odd rows are pushed half a
+//! tile to the right.
+//!
+//! Layers are stored run-length encoded in map files, since most maps are
+//! large stretches of the same tile.
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, SerializeSeq, Serializer};
+
+use super::terrain::Terrain;
+use crate::geometry::{BoundingBox, Hex, Point, Polygon};
+use crate::serde_derive::{Deserialize, Serialize};
+
+/// Size of tiles, in world units, unless a map chooses otherwise.
+pub const DEFAULT_TILE_SIZE: u32 = 4;
+
+/// Decoration shown on tiles which have none.
+pub const NO_DECORATION: Decoration = 0;
+
+/// Index into the map's decoration models, counting from 1.
+pub type Decoration = u16;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum GridShape {
+    #[default]
+    Square,
+    Hex,
+}
+
+/// Overrides whether towers can be built on a tile.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum Buildability {
+    /// Buildable if the terrain is.
+    #[default]
+    Terrain,
+    /// Buildable whatever the terrain, unless a tower needs specific terrain.
+    Always,
+    Never,
+}
+
+/// Nudges ground units' routes, without changing where they can go.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum PathHint {
+    #[default]
+    None,
+    /// Halves the cost of crossing the tile.
+    Prefer,
+    /// Doubles the cost of crossing the tile.
+    Avoid,
+}
+
+impl PathHint {
+    /// Applies the hint to a movement cost.
+    pub fn adjust(self, cost: u32) -> u32 {
+        match self {
+            PathHint::None => cost,
+            PathHint::Prefer => (cost / 2).max(1),
+            PathHint::Avoid => cost * 2,
+        }
+    }
+}
+
+/// Every layer of a single tile.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Tile {
+    pub terrain: Terrain,
+    pub buildable: Buildability,
+    pub decoration: Decoration,
+    pub path_hint: PathHint,
+}
+
+/// One value per tile, row by row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileLayer<T> {
+    cells: Vec<T>,
+}
+
+impl<T: Copy> TileLayer<T> {
+    fn new(len: usize, value: T) -> TileLayer<T> {
+        TileLayer {
+            cells: vec![value; len],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<T> {
+        self.cells.get(index).cloned()
+    }
+
+    fn set(&mut self, index: usize, value: T) {
+        if let Some(cell) = self.cells.get_mut(index) {
+            *cell = value;
+        }
+    }
+}
+
+impl<T: Serialize + PartialEq> Serialize for TileLayer<T> {
+    /// Written as `(count, value)` runs.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut runs: Vec<(u32, &T)> = Vec::new();
+        for cell in self.cells.iter() {
+            match runs.last_mut() {
+                Some(run) if run.1 == cell => run.0 += 1,
+                _ => runs.push((1, cell)),
+            }
+        }
+
+        let mut seq = serializer.serialize_seq(Some(runs.len()))?;
+        for run in runs.iter() {
+            seq.serialize_element(run)?;
+        }
+        seq.end()
+    }
+}
+
+/// A layer as written in map files, before its runs are expanded.
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct TileRuns<T>(Vec<(u32, T)>);
+
+impl<T: Copy> TileRuns<T> {
+    /// Expands the runs, which must cover exactly `len` tiles.
+    fn expand(self, name: &str, len: usize) -> Result<TileLayer<T>, String> {
+        let mut cells = Vec::new();
+        for (count, value) in self.0 {
+            if count as usize > len - cells.len() {
+                return Err(format!("{} layer has more than {} tiles", name, len));
+            }
+            cells.extend((0..count).map(|_| value));
+        }
+        if cells.len() != len {
+            return Err(format!(
+                "{} layer has {} tiles instead of {}",
+                name,
+                cells.len(),
+                len
+            ));
+        }
+        Ok(TileLayer { cells })
+    }
+}
+
+/// A tile map as written in map files, before it's been checked.
+#[derive(Deserialize)]
+struct TileMapData {
+    origin: Point,
+    tile_size: u32,
+    width: u32,
+    height: u32,
+    terrain: TileRuns<Terrain>,
+    buildable: TileRuns<Buildability>,
+    decoration: TileRuns<Decoration>,
+    path_hints: TileRuns<PathHint>,
+    shape: GridShape,
+}
+
+impl TileMapData {
+    fn check(self) -> Result<TileMap, String> {
+        if self.tile_size == 0 {
+            return Err("tile size is 0".to_string());
+        }
+        let len = self
+            .width
+            .checked_mul(self.height)
+            .ok_or_else(|| format!("{}x{} tiles is too many", self.width, self.height))?
+            as usize;
+
+        Ok(TileMap {
+            origin: self.origin,
+            tile_size: self.tile_size,
+            width: self.width,
+            height: self.height,
+            terrain: self.terrain.expand("terrain", len)?,
+            buildable: self.buildable.expand("buildable", len)?,
+            decoration: self.decoration.expand("decoration", len)?,
+            path_hints: self.path_hints.expand("path hint", len)?,
+            shape: self.shape,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for TileMap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        TileMapData::deserialize(deserializer)?
+            .check()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Every layer of every tile of a map.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TileMap {
+    origin: Point,
+    /// For hexagons, the distance between their flat sides.
+    tile_size: u32,
+    width: u32,
+    height: u32,
+    pub terrain: TileLayer<Terrain>,
+    pub buildable: TileLayer<Buildability>,
+    pub decoration: TileLayer<Decoration>,
+    pub path_hints: TileLayer<PathHint>,
+    shape: GridShape,
+}
+
+impl TileMap {
+    /// Covers `bounds` with default square tiles, `tile_size` world units
+    /// across.
+    pub fn new(bounds: BoundingBox, tile_size: u32) -> TileMap {
+        TileMap::with_shape(bounds, tile_size, GridShape::Square)
+    }
+
+    pub fn with_shape(bounds: BoundingBox, tile_size: u32, shape: GridShape) -> TileMap {
+        let tile_size = tile_size.max(1);
+        let origin = bounds.lower_left();
+        let span_x = bounds.upper_right().x() - origin.x();
+        let span_y = bounds.upper_right().y() - origin.y();
+        let width = span_x / tile_size + 1;
+        let height = match shape {
+            GridShape::Square => span_y / tile_size + 1,
+            GridShape::Hex => (f64::from(span_y) / hex_row_height(tile_size)) as u32 + 1,
+        };
+        let len = (width * height) as usize;
+
+        TileMap {
+            origin,
+            tile_size,
+            width,
+            height,
+            terrain: TileLayer::new(len, Terrain::default()),
+            buildable: TileLayer::new(len, Buildability::default()),
+            decoration: TileLayer::new(len, NO_DECORATION),
+            path_hints: TileLayer::new(len, PathHint::default()),
+            shape,
+        }
+    }
+
+    pub fn origin(&self) -> Point {
+        self.origin
+    }
+
+    pub fn shape(&self) -> GridShape {
+        self.shape
+    }
+
+    /// Width and height of each tile, in world units.
+    pub fn tile_size(&self) -> u32 {
+        self.tile_size
+    }
+
+    /// Width in tiles.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Height in tiles.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn index(&self, x: u32, y: u32) -> Option<usize> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some((y * self.width + x) as usize)
+    }
+
+    /// The column and row of the tile containing `point`.
+    pub fn cell_at(&self, point: Point) -> Option<(u32, u32)> {
+        if self.shape == GridShape::Hex {
+            let (col, row) = self.hex_at(point).to_offset();
+            if col < 0 || row < 0 {
+                return None;
+            }
+            let (x, y) = (col as u32, row as u32);
+            return self.index(x, y).map(|_| (x, y));
+        }
+
+        if point.x() < self.origin.x() || point.y() < self.origin.y() {
+            return None;
+        }
+        let x = (point.x() - self.origin.x()) / self.tile_size;
+        let y = (point.y() - self.origin.y()) / self.tile_size;
+        self.index(x, y).map(|_| (x, y))
+    }
+
+    /// The world location of the middle of a tile.
+    pub fn center(&self, x: u32, y: u32) -> Point {
+        let half = self.tile_size / 2;
+        if self.shape == GridShape::Hex {
+            let (dx, dy) = self.hex(x, y).to_pixel(f64::from(self.tile_size));
+            return Point::new(
+                self.origin.x() + half + dx.round() as u32,
+                self.origin.y() + half + dy.round() as u32,
+            );
+        }
+
+        Point::new(
+            self.origin.x() + x * self.tile_size + half,
+            self.origin.y() + y * self.tile_size + half,
+        )
+    }
+
+    /// The hexagon at a column and row of a hex map.
+    pub fn hex(&self, x: u32, y: u32) -> Hex {
+        Hex::from_offset(x as i32, y as i32)
+    }
+
+    /// The hexagon containing `point` on a hex map, which may be off the
+    /// edge of it.
+    pub fn hex_at(&self, point: Point) -> Hex {
+        let half = f64::from(self.tile_size / 2);
+        Hex::from_pixel(
+            f64::from(*point.x()) - f64::from(*self.origin.x()) - half,
+            f64::from(*point.y()) - f64::from(*self.origin.y()) - half,
+            f64::from(self.tile_size),
+        )
+    }
+
+    /// The columns and rows of the tiles next to a tile, on the map.
+    /// Hexagons have six neighbors and squares have four.
+    pub fn neighbors(&self, x: u32, y: u32) -> Vec<(u32, u32)> {
+        let cells: Vec<(i64, i64)> = match self.shape {
+            GridShape::Square => vec![(1, 0), (0, 1), (-1, 0), (0, -1)]
+                .into_iter()
+                .map(|(dx, dy)| (i64::from(x) + dx, i64::from(y) + dy))
+                .collect(),
+            GridShape::Hex => self
+                .hex(x, y)
+                .neighbors()
+                .iter()
+                .map(|hex| {
+                    let (col, row) = hex.to_offset();
+                    (i64::from(col), i64::from(row))
+                })
+                .collect(),
+        };
+
+        cells
+            .into_iter()
+            .filter(|&(x, y)| {
+                x >= 0 && y >= 0 && x < i64::from(self.width) && y < i64::from(self.height)
+            })
+            .map(|(x, y)| (x as u32, y as u32))
+            .collect()
+    }
+
+    pub fn tile(&self, x: u32, y: u32) -> Option<Tile> {
+        let i = self.index(x, y)?;
+        Some(Tile {
+            terrain: self.terrain.get(i)?,
+            buildable: self.buildable.get(i)?,
+            decoration: self.decoration.get(i)?,
+            path_hint: self.path_hints.get(i)?,
+        })
+    }
+
+    /// Every layer of the tile containing `point`. Off the map is cliff which
+    /// can't be built on.
+    pub fn tile_at(&self, point: Point) -> Tile {
+        let off_map = Tile {
+            terrain: Terrain::Cliff,
+            buildable: Buildability::Never,
+            ..Tile::default()
+        };
+
+        self.cell_at(point)
+            .and_then(|(x, y)| self.tile(x, y))
+            .unwrap_or(off_map)
+    }
+
+    /// The terrain at `point`. Off the map is treated as cliff, so nothing
+    /// walks or builds there.
+    pub fn terrain_at(&self, point: Point) -> Terrain {
+        self.tile_at(point).terrain
+    }
+
+    /// Whether towers which don't need specific terrain can be built at
+    /// `point`.
+    pub fn is_buildable_at(&self, point: Point) -> bool {
+        let tile = self.tile_at(point);
+        match tile.buildable {
+            Buildability::Terrain => tile.terrain.is_buildable(),
+            Buildability::Always => true,
+            Buildability::Never => false,
+        }
+    }
+
+    /// Cost for ground units to cross the tile containing `point`, with its
+    /// path hint applied. `None` if they can't cross it.
+    pub fn movement_cost_at(&self, point: Point) -> Option<u32> {
+        let tile = self.tile_at(point);
+        tile.terrain
+            .movement_cost()
+            .map(|cost| tile.path_hint.adjust(cost))
+    }
+
+    pub fn set_tile(&mut self, x: u32, y: u32, tile: Tile) {
+        if let Some(i) = self.index(x, y) {
+            self.terrain.set(i, tile.terrain);
+            self.buildable.set(i, tile.buildable);
+            self.decoration.set(i, tile.decoration);
+            self.path_hints.set(i, tile.path_hint);
+        }
+    }
+
+    pub fn set_terrain(&mut self, x: u32, y: u32, terrain: Terrain) {
+        if let Some(i) = self.index(x, y) {
+            self.terrain.set(i, terrain);
+        }
+    }
+
+    pub fn set_buildable(&mut self, x: u32, y: u32, buildable: Buildability) {
+        if let Some(i) = self.index(x, y) {
+            self.buildable.set(i, buildable);
+        }
+    }
+
+    pub fn set_decoration(&mut self, x: u32, y: u32, decoration: Decoration) {
+        if let Some(i) = self.index(x, y) {
+            self.decoration.set(i, decoration);
+        }
+    }
+
+    pub fn set_path_hint(&mut self, x: u32, y: u32, hint: PathHint) {
+        if let Some(i) = self.index(x, y) {
+            self.path_hints.set(i, hint);
+        }
+    }
+
+    /// Changes every tile whose center is inside `area`.
+    pub fn fill<F: FnMut(&mut Tile)>(&mut self, area: BoundingBox, mut change: F) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if !area.contains(self.center(x, y)) {
+                    continue;
+                }
+                if let Some(mut tile) = self.tile(x, y) {
+                    change(&mut tile);
+                    self.set_tile(x, y, tile);
+                }
+            }
+        }
+    }
+
+    /// Sets the terrain of every tile whose center is inside `area`.
+    pub fn fill_terrain(&mut self, area: BoundingBox, terrain: Terrain) {
+        self.fill(area, |tile| tile.terrain = terrain);
+    }
+}
+
+/// Distance between the centers of two rows of hexagons.
+fn hex_row_height(tile_size: u32) -> f64 {
+    f64::from(tile_size) * 3f64.sqrt() / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bincode::{deserialize, serialize};
+    use crate::game::tiles::*;
+
+    fn bounds() -> BoundingBox {
+        BoundingBox::new(Point::new(0, 0), Point::new(40, 40))
+    }
+
+    #[test]
+    fn filling_and_looking_up_tiles() {
+        let mut tiles = TileMap::new(bounds(), 4);
+        assert_eq!(tiles.width(), 11);
+
+        tiles.fill_terrain(
+            BoundingBox::new(Point::new(0, 16), Point::new(41, 24)),
+            Terrain::Road,
+        );
+        assert_eq!(tiles.terrain_at(Point::new(10, 18)), Terrain::Road);
+        assert_eq!(tiles.terrain_at(Point::new(10, 10)), Terrain::Grass);
+        assert_eq!(tiles.terrain_at(Point::new(10, 100)), Terrain::Cliff);
+        assert_eq!(tiles.cell_at(Point::new(10, 18)), Some((2, 4)));
+
+        tiles.set_terrain(0, 0, Terrain::Water);
+        tiles.set_decoration(0, 0, 3);
+        assert_eq!(tiles.tile(0, 0).unwrap().terrain, Terrain::Water);
+        assert_eq!(tiles.tile(0, 0).unwrap().decoration, 3);
+        assert_eq!(tiles.tile(11, 0), None);
+    }
+
+    #[test]
+    fn layers_override_terrain() {
+        let mut tiles = TileMap::new(bounds(), 8);
+        assert_eq!(tiles.width(), 6);
+
+        tiles.set_terrain(1, 1, Terrain::Road);
+        let road = tiles.center(1, 1);
+        assert!(!tiles.is_buildable_at(road));
+        tiles.set_buildable(1, 1, Buildability::Always);
+        assert!(tiles.is_buildable_at(road));
+        tiles.set_buildable(0, 0, Buildability::Never);
+        assert!(!tiles.is_buildable_at(Point::new(2, 2)));
+
+        assert_eq!(tiles.movement_cost_at(road), Some(50));
+        tiles.set_path_hint(1, 1, PathHint::Avoid);
+        assert_eq!(tiles.movement_cost_at(road), Some(100));
+    }
+
+    #[test]
+    fn layers_are_run_length_encoded() {
+        let mut tiles = TileMap::new(BoundingBox::new(Point::new(0, 0), Point::new(400, 400)), 4);
+        tiles.fill_terrain(
+            BoundingBox::new(Point::new(0, 0), Point::new(410, 40)),
+            Terrain::Water,
+        );
+        tiles.set_path_hint(5, 5, PathHint::Prefer);
+
+        let encoded = serialize(&tiles).unwrap();
+        assert!(encoded.len() < 200);
+        assert_eq!(deserialize::<TileMap>(&encoded).unwrap(), tiles);
+    }
+
+    #[test]
+    fn hex_tiles() {
+        let mut tiles = TileMap::with_shape(bounds(), 8, GridShape::Hex);
+        assert_eq!(tiles.width(), 6);
+        assert_eq!(tiles.height(), 6);
+
+        // Odd rows are pushed half a hexagon right
+        assert_eq!(tiles.center(0, 0), Point::new(4, 4));
+        assert_eq!(tiles.center(0, 1), Point::new(8, 11));
+        for y in 0..tiles.height() {
+            for x in 0..tiles.width() {
+                assert_eq!(tiles.cell_at(tiles.center(x, y)), Some((x, y)));
+            }
+        }
+
+        tiles.set_terrain(2, 2, Terrain::Water);
+        let water = tiles.center(2, 2);
+        assert_eq!(
+            tiles.terrain_at(Point::new(water.x() + 3, *water.y())),
+            Terrain::Water
+        );
+        assert_eq!(tiles.neighbors(2, 2).len(), 6);
+        assert_eq!(tiles.neighbors(0, 0).len(), 2);
+    }
+}
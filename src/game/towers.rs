@@ -0,0 +1,173 @@
+//! Towers firing at their targets, and the projectiles they fire.
+//!
+//! A tower fires whenever its cooldown has run out and it has a target. Each
+//! shot is a projectile which flies after the target; the collision pass
+//! works out what it hits. Projectiles whose target is gone are dropped.
+use super::collision::CollisionShape;
+use super::entities::{GameEntity, GameEntityType, Projectile};
+use super::hero::step_towards;
+use super::schedule::SystemOutput;
+use super::GameState;
+
+/// World units tower projectiles move per tick.
+pub const PROJECTILE_SPEED: u32 = 6;
+
+/// Towers with this ability damage every enemy around the one they hit.
+pub const SPLASH_ABILITY: &str = "splash";
+
+/// How far from the enemy hit splash damage reaches.
+pub const SPLASH_RADIUS: u32 = 6;
+
+/// Counts down every tower's cooldown, and fires at its target once it's
+/// ready. Projectiles appear at the end of the tick.
+pub fn fire_towers(state: &GameState, output: &mut SystemOutput) {
+    for entity in state.entities.iter() {
+        // Only lock towers for writing, other systems may be reading the rest
+        if entity.borrow().tower.is_none() {
+            continue;
+        }
+        let mut entity = entity.borrow_mut();
+        let id = entity.id;
+        let owner = entity.owner;
+        let location = entity.location;
+
+        let tower = match entity.tower.as_mut() {
+            Some(tower) => tower,
+            None => continue,
+        };
+        tower.cooldown = tower.cooldown.saturating_sub(1);
+        if tower.cooldown > 0 {
+            continue;
+        }
+        let target = match tower.target {
+            Some(ref target) if target.is_alive() => target.clone(),
+            _ => continue,
+        };
+        tower.cooldown = tower.effective_fire_interval();
+
+        let mut projectile = GameEntity::new(0, GameEntityType::Projectile, location);
+        projectile.owner = owner;
+        projectile.shape = Some(CollisionShape::Circle { radius: 0 });
+        projectile.projectile = Some(Projectile {
+            source: id,
+            damage: tower.effective_damage(),
+            damage_type: tower.damage_type,
+            effects: tower.effects.clone(),
+            splash: tower.abilities.iter().any(|a| a == SPLASH_ABILITY),
+            target: Some(target),
+            speed: PROJECTILE_SPEED,
+            previous_location: location,
+        });
+        output.commands.spawn(projectile);
+    }
+}
+
+/// Moves every projectile towards its target, or drops it if the target is
+/// gone. The projectiles move once the stage is done.
+pub fn move_projectiles(state: &GameState, output: &mut SystemOutput) {
+    for entity in state.entities.iter() {
+        let entity = entity.borrow();
+        let projectile = match entity.projectile {
+            Some(ref projectile) => projectile,
+            None => continue,
+        };
+        let target = match projectile.target {
+            Some(ref target) => target,
+            None => continue,
+        };
+
+        match target.upgrade() {
+            Some(target) => {
+                let destination = target.borrow().location;
+                let next = step_towards(entity.location, destination, projectile.speed);
+                output.moves.push((entity.id, next));
+            }
+            None => output.commands.despawn(entity.id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::archetypes::Effect;
+    use crate::game::combat::{CombatLog, DamageType};
+    use crate::game::entities::{EntityHandle, Tower};
+    use crate::game::schedule::SystemOutput;
+    use crate::game::towers::*;
+    use crate::geometry::Point;
+
+    #[test]
+    fn towers_fire_once_per_interval() {
+        let mut state = GameState::new(Vec::new());
+        let mut enemy = GameEntity::new(0, GameEntityType::Enemy, Point::new(30, 10));
+        enemy.shape = Some(CollisionShape::Circle { radius: 1 });
+        let enemy = state.add_entity(enemy);
+
+        let mut tower = GameEntity::new(0, GameEntityType::Structure, Point::new(10, 10));
+        tower.tower = Some(Tower {
+            range: 30,
+            damage: 3,
+            damage_type: DamageType::Fire,
+            fire_interval: 3,
+            cooldown: 0,
+            effects: vec![Effect {
+                status: "burn".to_string(),
+                duration: 5,
+                magnitude: 1,
+            }],
+            abilities: vec![SPLASH_ABILITY.to_string()],
+            target: Some(EntityHandle::new(&state.entity(enemy).unwrap())),
+            buffs: Vec::new(),
+        });
+        let tower = state.add_entity(tower);
+
+        let mut shots = Vec::new();
+        for _ in 0..7 {
+            let mut output = SystemOutput::new();
+            fire_towers(&state, &mut output);
+            let (spawns, _) = output.commands.take();
+            shots.push(spawns);
+        }
+        let fired: Vec<usize> = shots.iter().map(Vec::len).collect();
+        assert_eq!(fired, vec![1, 0, 0, 1, 0, 0, 1]);
+
+        let shot = shots[0][0].projectile.as_ref().unwrap();
+        assert_eq!(shot.source, tower);
+        assert_eq!(shot.damage, 3);
+        assert_eq!(shot.effects[0].status, "burn");
+        assert!(shot.splash);
+        assert_eq!(shot.target.as_ref().map(EntityHandle::id), Some(enemy));
+    }
+
+    #[test]
+    fn projectiles_chase_their_target() {
+        let mut state = GameState::new(Vec::new());
+        let enemy = state.spawn_entity(GameEntityType::Enemy, Point::new(30, 10));
+
+        let mut projectile = GameEntity::new(0, GameEntityType::Projectile, Point::new(10, 10));
+        projectile.projectile = Some(Projectile {
+            source: 0,
+            damage: 1,
+            damage_type: DamageType::Physical,
+            effects: Vec::new(),
+            splash: false,
+            target: Some(EntityHandle::new(&state.entity(enemy).unwrap())),
+            speed: 6,
+            previous_location: Point::new(10, 10),
+        });
+        let projectile = state.add_entity(projectile);
+
+        let mut output = SystemOutput::new();
+        move_projectiles(&state, &mut output);
+        assert_eq!(output.moves, vec![(projectile, Point::new(16, 10))]);
+        output.apply(&mut state, &mut CombatLog::new());
+        let location = state.entity(projectile).unwrap().borrow().location;
+        assert_eq!(location, Point::new(16, 10));
+
+        state.remove_entity(enemy);
+        let mut output = SystemOutput::new();
+        move_projectiles(&state, &mut output);
+        assert!(output.moves.is_empty());
+        assert!(output.commands.is_despawning(projectile));
+    }
+}
@@ -0,0 +1,359 @@
+//! Checks maps for problems before they're played.
+//!
+//! Nothing here stops a map from loading. Errors are problems which will
+//! break the game, such as enemies which can never reach their goal, while
+//! warnings are things which are probably mistakes.
+use std::fmt;
+
+use super::archetypes::ArchetypeRegistry;
+use super::map::GameMap;
+use super::pathfinding;
+use super::placement;
+use super::waves::WaveSet;
+use super::GameState;
+use crate::geometry::{BoundingBox, Point, Polygon};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapProblem {
+    pub severity: Severity,
+    pub message: String,
+    /// Where on the map the problem is, if it's anywhere in particular.
+    pub location: Option<Point>,
+}
+
+impl fmt::Display for MapProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        match self.location {
+            Some(location) => write!(
+                f,
+                "{} at ({}, {}): {}",
+                severity,
+                location.x(),
+                location.y(),
+                self.message
+            ),
+            None => write!(f, "{}: {}", severity, self.message),
+        }
+    }
+}
+
+/// Everything `validate_map` found.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MapReport {
+    pub problems: Vec<MapProblem>,
+}
+
+impl MapReport {
+    fn add(&mut self, severity: Severity, location: Option<Point>, message: String) {
+        self.problems.push(MapProblem {
+            severity,
+            message,
+            location,
+        });
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &MapProblem> {
+        self.problems
+            .iter()
+            .filter(|problem| problem.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &MapProblem> {
+        self.problems
+            .iter()
+            .filter(|problem| problem.severity == Severity::Warning)
+    }
+
+    /// Whether the map can be played, warnings or not.
+    pub fn is_valid(&self) -> bool {
+        self.errors().next().is_none()
+    }
+}
+
+/// Whether `point` is on the map, edges included, since that's where enemies
+/// usually come from.
+fn on_map(map: &GameMap, point: Point) -> bool {
+    let lower_left = map.dimensions.lower_left();
+    let upper_right = map.dimensions.upper_right();
+    (lower_left.x()..=upper_right.x()).contains(&point.x())
+        && (lower_left.y()..=upper_right.y()).contains(&point.y())
+}
+
+fn overlap(a: BoundingBox, b: BoundingBox) -> Option<BoundingBox> {
+    let lower_left = Point::new(
+        *a.lower_left().x().max(b.lower_left().x()),
+        *a.lower_left().y().max(b.lower_left().y()),
+    );
+    let upper_right = Point::new(
+        *a.upper_right().x().min(b.upper_right().x()),
+        *a.upper_right().y().min(b.upper_right().y()),
+    );
+
+    if lower_left.x() < upper_right.x() && lower_left.y() < upper_right.y() {
+        Some(BoundingBox::new(lower_left, upper_right))
+    } else {
+        None
+    }
+}
+
+/// Checks `map`, and the waves that will be played on it if there are any,
+/// reporting every problem found.
+pub fn validate_map(
+    map: &GameMap,
+    waves: Option<&WaveSet>,
+    archetypes: &ArchetypeRegistry,
+) -> MapReport {
+    let mut report = MapReport::default();
+
+    // Waves can spawn enemies away from the map's own spawn points
+    let mut spawns = map.spawns.clone();
+    if let Some(waves) = waves {
+        for (i, wave) in waves.waves.iter().enumerate() {
+            for group in wave.groups.iter() {
+                if archetypes.enemy(&group.archetype).is_none() {
+                    report.add(
+                        Severity::Error,
+                        Some(group.location),
+                        format!("wave {} uses unknown enemy '{}'", i + 1, group.archetype),
+                    );
+                }
+                if !spawns.contains(&group.location) {
+                    spawns.push(group.location);
+                }
+            }
+        }
+    }
+
+    if spawns.is_empty() {
+        report.add(Severity::Warning, None, "no spawn points".to_string());
+    }
+    if map.goals.is_empty() {
+        report.add(Severity::Warning, None, "no goals".to_string());
+    }
+
+    for &spawn in spawns.iter() {
+        if !on_map(map, spawn) {
+            report.add(
+                Severity::Error,
+                Some(spawn),
+                "spawn point is outside the map".to_string(),
+            );
+        }
+    }
+    for &goal in map.goals.iter() {
+        if !on_map(map, goal) {
+            report.add(
+                Severity::Error,
+                Some(goal),
+                "goal is outside the map".to_string(),
+            );
+        }
+    }
+
+    check_routes(map, &spawns, &mut report);
+
+    for (i, a) in map.zones.iter().enumerate() {
+        for b in map.zones[i + 1..].iter() {
+            if let Some(shared) = overlap(a.get_bounding_box(), b.get_bounding_box()) {
+                report.add(
+                    Severity::Warning,
+                    Some(shared.lower_left()),
+                    format!("zones '{}' and '{}' overlap", a.name, b.name),
+                );
+            }
+        }
+    }
+
+    check_buildable(map, archetypes, &mut report);
+
+    report
+}
+
+/// Every goal has to be reachable from every spawn. Scripts can open closed
+/// zones, so a route through one is only a warning.
+fn check_routes(map: &GameMap, spawns: &[Point], report: &mut MapReport) {
+    let mut state = GameState::new(map.zones.clone());
+    state.bounds = map.dimensions;
+    state.tiles = map.tiles.clone();
+
+    let mut opened = GameState::new(map.zones.clone());
+    opened.bounds = map.dimensions;
+    opened.tiles = map.tiles.clone();
+    for zone in opened.zones.iter_mut() {
+        zone.open = true;
+    }
+
+    let inside = |point: &&Point| on_map(map, **point);
+    for &spawn in spawns.iter().filter(inside) {
+        for &goal in map.goals.iter().filter(inside) {
+            let reachable = |state: &GameState| {
+                pathfinding::find_path(state, map.dimensions, spawn, goal, 0).is_some()
+            };
+
+            if reachable(&state) {
+                continue;
+            }
+            let message = format!(
+                "goal at ({}, {}) can't be reached from this spawn point",
+                goal.x(),
+                goal.y()
+            );
+            if reachable(&opened) {
+                report.add(
+                    Severity::Warning,
+                    Some(spawn),
+                    format!("{} until closed zones are opened", message),
+                );
+            } else {
+                report.add(Severity::Error, Some(spawn), message);
+            }
+        }
+    }
+}
+
+/// At least one tower has to be buildable somewhere.
+fn check_buildable(map: &GameMap, archetypes: &ArchetypeRegistry, report: &mut MapReport) {
+    let tiles = &map.tiles;
+    let mut buildable = Vec::new();
+
+    for name in archetypes.tower_names() {
+        let tower = match archetypes.tower(name) {
+            Some(tower) => tower,
+            None => continue,
+        };
+        let anywhere = (0..tiles.height()).any(|y| {
+            (0..tiles.width()).any(|x| {
+                let center = tiles.center(x, y);
+                map.dimensions.contains(center)
+                    && placement::check_tile(tower, tiles, center).is_ok()
+            })
+        });
+
+        if anywhere {
+            buildable.push(name);
+        } else {
+            report.add(
+                Severity::Warning,
+                None,
+                format!("'{}' can't be built anywhere", name),
+            );
+        }
+    }
+
+    if buildable.is_empty() && !archetypes.tower_names().is_empty() {
+        report.add(
+            Severity::Error,
+            None,
+            "no tower can be built anywhere on the map".to_string(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::archetypes::test_archetypes;
+    use crate::game::entities::Zone;
+    use crate::game::map::DEFAULT_MAP_DIMENSIONS;
+    use crate::game::terrain::Terrain;
+    use crate::game::validation::*;
+
+    fn test_map() -> GameMap {
+        let mut map = GameMap::new("Test Map", DEFAULT_MAP_DIMENSIONS);
+        map.spawns.push(Point::new(0, 50));
+        map.goals.push(Point::new(98, 50));
+        map
+    }
+
+    #[test]
+    fn a_playable_map_only_has_warnings() {
+        let report = validate_map(&test_map(), None, &test_archetypes());
+
+        assert!(report.is_valid());
+        let warnings: Vec<String> = report.warnings().map(|w| w.to_string()).collect();
+        assert_eq!(warnings, vec!["warning: 'harpoon' can't be built anywhere"]);
+    }
+
+    #[test]
+    fn problems_are_reported_with_locations() {
+        let mut map = test_map();
+        map.spawns.push(Point::new(150, 50));
+        // A river of water all the way across the map
+        map.tiles.fill_terrain(
+            BoundingBox::new(Point::new(40, 0), Point::new(60, 110)),
+            Terrain::Water,
+        );
+        map.zones.push(Zone::new(
+            "north",
+            BoundingBox::new(Point::new(0, 60), Point::new(30, 90)),
+        ));
+        map.zones.push(Zone::new(
+            "west",
+            BoundingBox::new(Point::new(10, 0), Point::new(20, 70)),
+        ));
+        let waves = WaveSet::parse(
+            r#"{ "waves": [{ "groups": [{ "archetype": "dragon", "count": 1, "location": [0, 50] }] }] }"#,
+        )
+        .unwrap();
+
+        let report = validate_map(&map, Some(&waves), &test_archetypes());
+        let errors: Vec<String> = report.errors().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "error at (0, 50): wave 1 uses unknown enemy 'dragon'",
+                "error at (150, 50): spawn point is outside the map",
+                "error at (0, 50): goal at (98, 50) can't be reached from this spawn point",
+            ]
+        );
+        let overlap = report.warnings().next().unwrap();
+        assert_eq!(overlap.location, Some(Point::new(10, 60)));
+    }
+
+    #[test]
+    fn closed_zones_only_block_with_a_warning() {
+        let mut map = test_map();
+        map.tiles.fill_terrain(
+            BoundingBox::new(Point::new(40, 0), Point::new(60, 110)),
+            Terrain::Cliff,
+        );
+        map.tiles.fill_terrain(
+            BoundingBox::new(Point::new(40, 40), Point::new(60, 60)),
+            Terrain::Grass,
+        );
+        let mut gate = Zone::new(
+            "gate",
+            BoundingBox::new(Point::new(36, 36), Point::new(64, 64)),
+        );
+        gate.open = false;
+        map.zones.push(gate);
+
+        let report = validate_map(&map, None, &test_archetypes());
+        assert!(report.is_valid());
+        assert!(report
+            .warnings()
+            .any(|w| w.message.ends_with("until closed zones are opened")));
+    }
+
+    #[test]
+    fn maps_need_somewhere_to_build() {
+        let mut map = test_map();
+        map.tiles
+            .fill_terrain(DEFAULT_MAP_DIMENSIONS, Terrain::Road);
+
+        let report = validate_map(&map, None, &test_archetypes());
+        assert!(!report.is_valid());
+        assert!(report
+            .errors()
+            .any(|e| e.message == "no tower can be built anywhere on the map"));
+    }
+}
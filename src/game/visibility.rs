@@ -0,0 +1,416 @@
+//! Fog of war. Each player sees the map around their towers and heroes, out
+//! to each one's sight range, unless terrain blocks the line of sight.
+//!
+//! Enemies a player can't see can't be targeted by them. Stealthy enemies
+//! also have to be within range of one of the player's detectors.
+use std::collections::BTreeMap;
+
+use super::entities::{GameEntity, PlayerId, Sight};
+use super::map::GameMap;
+use super::schedule::SystemOutput;
+use super::GameState;
+use crate::geometry::{BoundingBox, Point, Polygon};
+use crate::serde_derive::{Deserialize, Serialize};
+
+/// Size of the cells visibility is tracked in, in world units.
+pub const VISIBILITY_CELL_SIZE: u32 = 4;
+
+/// Enemy archetype ability which hides them from everything but detectors.
+pub const STEALTH_ABILITY: &str = "stealth";
+/// Tower archetype ability which reveals stealthy enemies in sight.
+pub const DETECTOR_ABILITY: &str = "detector";
+
+type Cell = (u32, u32);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum CellVisibility {
+    /// Never seen.
+    Unexplored,
+    /// Seen before but not right now, so shown under fog.
+    Explored,
+    Visible,
+}
+
+/// The cells on `bounds`, `VISIBILITY_CELL_SIZE` units across.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct CellLayout {
+    origin: Point,
+    width: u32,
+    height: u32,
+}
+
+impl CellLayout {
+    fn new(bounds: BoundingBox) -> CellLayout {
+        let origin = bounds.lower_left();
+        CellLayout {
+            origin,
+            width: (bounds.upper_right().x() - origin.x()) / VISIBILITY_CELL_SIZE + 1,
+            height: (bounds.upper_right().y() - origin.y()) / VISIBILITY_CELL_SIZE + 1,
+        }
+    }
+
+    fn len(&self) -> usize {
+        (self.width * self.height) as usize
+    }
+
+    fn index(&self, cell: Cell) -> usize {
+        (cell.1 * self.width + cell.0) as usize
+    }
+
+    fn cell_at(&self, point: Point) -> Option<Cell> {
+        if point.x() < self.origin.x() || point.y() < self.origin.y() {
+            return None;
+        }
+        let x = (point.x() - self.origin.x()) / VISIBILITY_CELL_SIZE;
+        let y = (point.y() - self.origin.y()) / VISIBILITY_CELL_SIZE;
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some((x, y))
+    }
+
+    fn center(&self, cell: Cell) -> Point {
+        let half = VISIBILITY_CELL_SIZE / 2;
+        Point::new(
+            self.origin.x() + cell.0 * VISIBILITY_CELL_SIZE + half,
+            self.origin.y() + cell.1 * VISIBILITY_CELL_SIZE + half,
+        )
+    }
+}
+
+/// What a single player can see.
+#[derive(Debug, Clone)]
+pub struct VisibilityGrid {
+    layout: CellLayout,
+    cells: Vec<CellVisibility>,
+    /// Cells where stealthy enemies are revealed.
+    detected: Vec<bool>,
+}
+
+impl VisibilityGrid {
+    fn new(layout: CellLayout) -> VisibilityGrid {
+        VisibilityGrid {
+            layout,
+            cells: vec![CellVisibility::Unexplored; layout.len()],
+            detected: vec![false; layout.len()],
+        }
+    }
+
+    /// Width in cells.
+    pub fn width(&self) -> u32 {
+        self.layout.width
+    }
+
+    /// Height in cells.
+    pub fn height(&self) -> u32 {
+        self.layout.height
+    }
+
+    /// Anything outside the map is unexplored.
+    pub fn visibility(&self, point: Point) -> CellVisibility {
+        match self.layout.cell_at(point) {
+            Some(cell) => self.cells[self.layout.index(cell)],
+            None => CellVisibility::Unexplored,
+        }
+    }
+
+    pub fn is_visible(&self, point: Point) -> bool {
+        self.visibility(point) == CellVisibility::Visible
+    }
+
+    pub fn is_detected(&self, point: Point) -> bool {
+        match self.layout.cell_at(point) {
+            Some(cell) => self.detected[self.layout.index(cell)],
+            None => false,
+        }
+    }
+
+    /// Fog overlay for rendering: the opacity of the fog over each cell,
+    /// row by row starting from the bottom of the map.
+    pub fn fog_mask(&self) -> Vec<u8> {
+        self.cells
+            .iter()
+            .map(|cell| match cell {
+                CellVisibility::Unexplored => 255,
+                CellVisibility::Explored => 128,
+                CellVisibility::Visible => 0,
+            })
+            .collect()
+    }
+
+    /// Everything visible last time fades to explored.
+    fn clear(&mut self) {
+        for cell in self.cells.iter_mut() {
+            if *cell == CellVisibility::Visible {
+                *cell = CellVisibility::Explored;
+            }
+        }
+        for detected in self.detected.iter_mut() {
+            *detected = false;
+        }
+    }
+}
+
+/// Visibility for every player, along with the terrain which blocks it.
+#[derive(Debug, Clone)]
+pub struct FogOfWar {
+    layout: CellLayout,
+    /// Cells which can't be seen through.
+    opaque: Vec<bool>,
+    players: BTreeMap<PlayerId, VisibilityGrid>,
+}
+
+impl FogOfWar {
+    /// Covers `bounds`, with sight blocked by every cell whose center is
+    /// inside one of `blockers`.
+    pub fn new(bounds: BoundingBox, blockers: &[BoundingBox]) -> FogOfWar {
+        let layout = CellLayout::new(bounds);
+        let mut opaque = vec![false; layout.len()];
+        for y in 0..layout.height {
+            for x in 0..layout.width {
+                let center = layout.center((x, y));
+                if blockers.iter().any(|blocker| blocker.contains(center)) {
+                    opaque[layout.index((x, y))] = true;
+                }
+            }
+        }
+
+        FogOfWar {
+            layout,
+            opaque,
+            players: BTreeMap::new(),
+        }
+    }
+
+    /// Covers `map`, with sight blocked by its sight blockers and by any
+    /// terrain which blocks it.
+    pub fn for_map(map: &GameMap) -> FogOfWar {
+        let mut fog = FogOfWar::new(map.dimensions, &map.sight_blockers);
+        for y in 0..fog.layout.height {
+            for x in 0..fog.layout.width {
+                if map
+                    .tiles
+                    .terrain_at(fog.layout.center((x, y)))
+                    .blocks_sight()
+                {
+                    let i = fog.layout.index((x, y));
+                    fog.opaque[i] = true;
+                }
+            }
+        }
+
+        fog
+    }
+
+    /// What `player` can see. `None` until they have something with sight.
+    pub fn grid(&self, player: PlayerId) -> Option<&VisibilityGrid> {
+        self.players.get(&player)
+    }
+
+    /// Whether `player` can see `entity` well enough to target it.
+    pub fn can_target(&self, player: PlayerId, entity: &GameEntity) -> bool {
+        let grid = match self.grid(player) {
+            Some(grid) => grid,
+            None => return false,
+        };
+
+        match entity.enemy {
+            Some(ref enemy) if enemy.stealth => grid.is_detected(entity.location),
+            _ => grid.is_visible(entity.location),
+        }
+    }
+
+    fn is_opaque(&self, cell: Cell) -> bool {
+        self.opaque[self.layout.index(cell)]
+    }
+
+    /// Walks the cells between `from` and `to` with Bresenham's algorithm.
+    /// Opaque cells block sight past them, but can be seen themselves.
+    fn line_of_sight(&self, from: Cell, to: Cell) -> bool {
+        let (mut x, mut y) = (i64::from(from.0), i64::from(from.1));
+        let (x1, y1) = (i64::from(to.0), i64::from(to.1));
+        let dx = (x1 - x).abs();
+        let dy = -(y1 - y).abs();
+        let sx = if x < x1 { 1 } else { -1 };
+        let sy = if y < y1 { 1 } else { -1 };
+        let mut error = dx + dy;
+
+        while (x, y) != (x1, y1) {
+            if (x, y) != (i64::from(from.0), i64::from(from.1))
+                && self.is_opaque((x as u32, y as u32))
+            {
+                return false;
+            }
+
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += sx;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += sy;
+            }
+        }
+
+        true
+    }
+
+    fn reveal(&mut self, player: PlayerId, location: Point, sight: Sight) {
+        let origin = match self.layout.cell_at(location) {
+            Some(cell) => cell,
+            None => return,
+        };
+
+        let reach = sight.range / VISIBILITY_CELL_SIZE + 1;
+        let range_squared = u64::from(sight.range) * u64::from(sight.range);
+        let min_x = origin.0.saturating_sub(reach);
+        let min_y = origin.1.saturating_sub(reach);
+        let max_x = (origin.0 + reach).min(self.layout.width - 1);
+        let max_y = (origin.1 + reach).min(self.layout.height - 1);
+
+        let mut seen = Vec::new();
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                let cell = (x, y);
+                let in_range = cell == origin
+                    || self.layout.center(cell).distance_squared(location) <= range_squared;
+                if in_range && self.line_of_sight(origin, cell) {
+                    seen.push(self.layout.index(cell));
+                }
+            }
+        }
+
+        let layout = self.layout;
+        let grid = self
+            .players
+            .entry(player)
+            .or_insert_with(|| VisibilityGrid::new(layout));
+        for i in seen {
+            grid.cells[i] = CellVisibility::Visible;
+            if sight.detects_stealth {
+                grid.detected[i] = true;
+            }
+        }
+    }
+}
+
+/// Works out what every player can see this tick. The new fog of war is
+/// swapped in once the stage is done. Does nothing if the game has no fog of
+/// war.
+pub fn update_visibility(state: &GameState, output: &mut SystemOutput) {
+    let mut fog = match state.fog {
+        Some(ref fog) => fog.clone(),
+        None => return,
+    };
+
+    for grid in fog.players.values_mut() {
+        grid.clear();
+    }
+    for entity in state.entities.iter() {
+        let entity = entity.borrow();
+        if let Some(sight) = entity.sight {
+            fog.reveal(entity.owner, entity.location, sight);
+        }
+    }
+
+    output.fog = Some(fog);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::combat::CombatLog;
+    use crate::game::entities::{Enemy, GameEntityType, LOCAL_PLAYER};
+    use crate::game::visibility::*;
+
+    fn update(state: &mut GameState) {
+        let mut output = SystemOutput::new();
+        update_visibility(state, &mut output);
+        output.apply(state, &mut CombatLog::new());
+    }
+
+    fn enemy(location: Point, stealth: bool) -> GameEntity {
+        let mut entity = GameEntity::new(0, GameEntityType::Enemy, location);
+        entity.enemy = Some(Enemy {
+            speed: 1,
+            bounty: 0,
+            lives_cost: 1,
+            damage: 0,
+            cooldown: 0,
+            stealth,
+            path: Vec::new(),
+            statuses: Vec::new(),
+        });
+        entity
+    }
+
+    fn watcher(state: &mut GameState, location: Point, detects_stealth: bool) {
+        let mut entity = GameEntity::new(0, GameEntityType::Structure, location);
+        entity.sight = Some(Sight {
+            range: 20,
+            detects_stealth,
+        });
+        state.add_entity(entity);
+    }
+
+    #[test]
+    fn terrain_blocks_line_of_sight() {
+        let bounds = BoundingBox::new(Point::new(0, 0), Point::new(100, 100));
+        let wall = BoundingBox::new(Point::new(28, 0), Point::new(32, 30));
+        let mut state = GameState::new(Vec::new());
+        state.fog = Some(FogOfWar::new(bounds, &[wall]));
+        watcher(&mut state, Point::new(20, 10), false);
+
+        update(&mut state);
+        let fog = state.fog.as_ref().unwrap();
+        let grid = fog.grid(LOCAL_PLAYER).unwrap();
+
+        assert!(grid.is_visible(Point::new(10, 10)));
+        // The wall itself can be seen, but not what's behind it
+        assert!(grid.is_visible(Point::new(29, 10)));
+        assert!(!grid.is_visible(Point::new(36, 10)));
+        // Out of range
+        assert!(!grid.is_visible(Point::new(20, 50)));
+        assert_eq!(
+            grid.visibility(Point::new(20, 50)),
+            CellVisibility::Unexplored
+        );
+        assert_eq!(
+            grid.fog_mask().len(),
+            (grid.width() * grid.height()) as usize
+        );
+
+        assert!(fog.can_target(LOCAL_PLAYER, &enemy(Point::new(12, 12), false)));
+        assert!(!fog.can_target(LOCAL_PLAYER, &enemy(Point::new(36, 10), false)));
+        assert!(!fog.can_target(1, &enemy(Point::new(12, 12), false)));
+    }
+
+    #[test]
+    fn stealthy_enemies_need_detectors() {
+        let bounds = BoundingBox::new(Point::new(0, 0), Point::new(100, 100));
+        let mut state = GameState::new(Vec::new());
+        state.fog = Some(FogOfWar::new(bounds, &[]));
+        watcher(&mut state, Point::new(20, 20), false);
+        update(&mut state);
+
+        let shade = enemy(Point::new(25, 20), true);
+        assert!(!state.fog.as_ref().unwrap().can_target(LOCAL_PLAYER, &shade));
+
+        let detector = Point::new(60, 20);
+        watcher(&mut state, detector, true);
+        update(&mut state);
+        let fog = state.fog.as_ref().unwrap();
+        assert!(!fog.can_target(LOCAL_PLAYER, &shade));
+        assert!(fog.can_target(LOCAL_PLAYER, &enemy(Point::new(55, 20), true)));
+
+        // Cells nothing can see any more stay explored
+        let id = state.entities[0].borrow().id;
+        state.remove_entity(id);
+        update(&mut state);
+        let grid = state.fog.as_ref().unwrap().grid(LOCAL_PLAYER).unwrap();
+        assert_eq!(
+            grid.visibility(Point::new(20, 20)),
+            CellVisibility::Explored
+        );
+    }
+}
@@ -0,0 +1,233 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+
+use super::archetypes::ArchetypeRegistry;
+use super::endless::EndlessConfig;
+use super::files::write_atomically;
+use crate::geometry::Point;
+use crate::serde_derive::{Deserialize, Serialize};
+
+pub const DEFAULT_WAVES: &str = "data/waves.json";
+
+#[derive(Debug)]
+pub struct WaveError {
+    details: String,
+}
+
+impl WaveError {
+    fn new(msg: &str) -> WaveError {
+        WaveError {
+            details: msg.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for WaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for WaveError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+/// A run of identical enemies sent during a wave.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SpawnGroup {
+    pub archetype: String,
+    pub count: u32,
+    pub location: Point,
+    /// Ticks after the start of the wave before the first enemy spawns.
+    #[serde(default)]
+    pub delay: u32,
+    /// Ticks between each enemy in the group.
+    #[serde(default = "default_interval")]
+    pub interval: u32,
+    /// Enemy health as a percentage of the archetype's.
+    #[serde(default = "default_health_percent")]
+    pub health_percent: u32,
+}
+
+fn default_interval() -> u32 {
+    1
+}
+
+fn default_health_percent() -> u32 {
+    100
+}
+
+impl SpawnGroup {
+    /// Health for an enemy in this group, given its archetype's health.
+    pub fn scale_health(&self, health: u32) -> u32 {
+        let scaled = u64::from(health) * u64::from(self.health_percent) / 100;
+        scaled.min(u64::from(u32::MAX)).max(1) as u32
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Wave {
+    pub groups: Vec<SpawnGroup>,
+}
+
+impl Wave {
+    /// Groups which should spawn an enemy `offset` ticks after the wave
+    /// started.
+    pub fn spawns_at(&self, offset: u32) -> Vec<&SpawnGroup> {
+        let mut spawns = Vec::new();
+
+        for group in self.groups.iter() {
+            if offset < group.delay {
+                continue;
+            }
+            let since_first = offset - group.delay;
+            let on_interval = since_first.checked_rem(group.interval) == Some(0);
+            if on_interval && since_first / group.interval < group.count {
+                spawns.push(group);
+            }
+        }
+
+        spawns
+    }
+
+    /// Ticks from the start of the wave until its last enemy has spawned.
+    pub fn duration(&self) -> u32 {
+        self.groups
+            .iter()
+            .map(|group| group.delay + (group.count - 1) * group.interval)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// The waves of a game, in order. Wave numbers start at 1.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct WaveSet {
+    pub waves: Vec<Wave>,
+    /// How to generate waves after the authored ones in endless mode.
+    #[serde(default)]
+    pub endless: Option<EndlessConfig>,
+}
+
+impl WaveSet {
+    pub fn load(path: &str, archetypes: &ArchetypeRegistry) -> Result<WaveSet, Box<dyn Error>> {
+        let json = fs::read_to_string(path)?;
+        WaveSet::from_json(&json, archetypes)
+            .map_err(|e| Box::new(WaveError::new(&format!("{}: {}", path, e))) as Box<dyn Error>)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let json = serde_json::to_string_pretty(self)?;
+        write_atomically(path, json)?;
+
+        Ok(())
+    }
+
+    /// Parses waves and checks every group against the available archetypes.
+    pub fn from_json(json: &str, archetypes: &ArchetypeRegistry) -> Result<WaveSet, WaveError> {
+        let waves = WaveSet::parse(json)?;
+        waves.validate(archetypes)?;
+
+        Ok(waves)
+    }
+
+    /// Parses waves without checking them, for tools which report problems
+    /// themselves.
+    pub fn parse(json: &str) -> Result<WaveSet, WaveError> {
+        serde_json::from_str(json).map_err(|e| WaveError::new(&format!("invalid wave file: {}", e)))
+    }
+
+    /// Reports every problem found, one per line.
+    pub fn validate(&self, archetypes: &ArchetypeRegistry) -> Result<(), WaveError> {
+        let mut problems = Vec::new();
+
+        for (i, wave) in self.waves.iter().enumerate() {
+            for group in wave.groups.iter() {
+                let mut problem = |msg: &str| {
+                    problems.push(format!("wave {} '{}': {}", i + 1, group.archetype, msg))
+                };
+
+                if archetypes.enemy(&group.archetype).is_none() {
+                    problem("unknown enemy archetype");
+                }
+                if group.count == 0 {
+                    problem("count must be greater than 0");
+                }
+                if group.interval == 0 {
+                    problem("interval must be greater than 0");
+                }
+                if group.health_percent == 0 {
+                    problem("health_percent must be greater than 0");
+                }
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(WaveError::new(&problems.join("\n")));
+        }
+
+        Ok(())
+    }
+
+    pub fn wave(&self, wave: u32) -> Option<&Wave> {
+        if wave == 0 {
+            return None;
+        }
+        self.waves.get(wave as usize - 1)
+    }
+
+    pub fn push(&mut self, wave: Wave) {
+        self.waves.push(wave);
+    }
+
+    pub fn len(&self) -> usize {
+        self.waves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waves.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::archetypes::test_archetypes;
+    use crate::game::waves::*;
+
+    #[test]
+    fn groups_spawn_on_schedule() {
+        let waves = WaveSet::from_json(
+            r#"{ "waves": [{ "groups": [
+                { "archetype": "grunt", "count": 3, "location": [0, 0], "delay": 2, "interval": 5 }
+            ] }] }"#,
+            &test_archetypes(),
+        )
+        .unwrap();
+
+        let wave = waves.wave(1).unwrap();
+        let spawned: Vec<u32> = (0..30).filter(|t| !wave.spawns_at(*t).is_empty()).collect();
+        assert_eq!(spawned, vec![2, 7, 12]);
+        assert_eq!(wave.duration(), 12);
+
+        assert!(waves.wave(0).is_none());
+        assert!(waves.wave(2).is_none());
+    }
+
+    #[test]
+    fn unknown_archetypes_are_rejected() {
+        let err = WaveSet::from_json(
+            r#"{ "waves": [{ "groups": [
+                { "archetype": "dragon", "count": 0, "location": [0, 0] }
+            ] }] }"#,
+            &test_archetypes(),
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(err.contains("wave 1 'dragon': unknown enemy archetype"));
+        assert!(err.contains("wave 1 'dragon': count must be greater than 0"));
+    }
+}
@@ -2,7 +2,7 @@ use crate::serde_derive::{Deserialize, Serialize};
 
 #[cfg(test)]
 mod tests {
-    use crate::geometry::Point;
+    use crate::geometry::{Hex, Point};
 
     #[test]
     fn point_tests() {
@@ -18,10 +18,41 @@ mod tests {
 
         assert!(p3.above(p));
         assert!(p.below(p3));
+
+        assert_eq!(p.distance_squared(p2), 4);
+        assert_eq!(p2.distance_squared(Point(5, 4)), 25);
+    }
+
+    #[test]
+    fn hex_tests() {
+        let origin = Hex::new(0, 0);
+        let far = Hex::new(3, -1);
+
+        assert_eq!(far.s(), -2);
+        assert_eq!(origin.distance(far), 3);
+        assert!(origin.neighbors().iter().all(|n| origin.distance(*n) == 1));
+
+        let line = origin.line_to(far);
+        assert_eq!(line.len(), 4);
+        assert_eq!(line[0], origin);
+        assert_eq!(line[3], far);
+        assert!(line.windows(2).all(|step| step[0].distance(step[1]) == 1));
+
+        assert_eq!(origin.ring(0), vec![origin]);
+        let ring = far.ring(2);
+        assert_eq!(ring.len(), 12);
+        assert!(ring.iter().all(|hex| far.distance(*hex) == 2));
+
+        for &(col, row) in [(0, 0), (3, 1), (2, 4), (-1, -3)].iter() {
+            assert_eq!(Hex::from_offset(col, row).to_offset(), (col, row));
+        }
+
+        let (x, y) = far.to_pixel(10.0);
+        assert_eq!(Hex::from_pixel(x + 3.0, y - 4.0, 10.0), far);
     }
 }
 
-#[derive(Debug, Copy, Clone, Deserialize, Serialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct Point(u32, u32);
 
 impl Point {
@@ -56,9 +87,18 @@ impl Point {
     pub fn left_of(self, point: Point) -> bool {
         self.x() < point.x()
     }
+
+    /// Squared euclidean distance between two points. Comparing squared
+    /// distances avoids a square root when all we need is ordering.
+    pub fn distance_squared(self, point: Point) -> u64 {
+        let dx = u64::from(self.0.max(point.0) - self.0.min(point.0));
+        let dy = u64::from(self.1.max(point.1) - self.1.min(point.1));
+
+        dx * dx + dy * dy
+    }
 }
 
-#[derive(Debug, Copy, Clone, Deserialize, Serialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct BoundingBox(Point, Point);
 
 pub trait Polygon<T> {
@@ -97,3 +137,137 @@ impl Polygon<u32> for BoundingBox {
             && point.left_of(self.upper_right())
     }
 }
+
+/// A hexagon on a grid of pointy-topped hexagons, in axial coordinates. The
+/// third cube coordinate, `s`, is implied by `q + r + s == 0`.
+///
+/// See <https://www.redblobgames.com/grids/hexagons/> for the maths.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+pub struct Hex {
+    q: i32,
+    r: i32,
+}
+
+/// Axial offsets of a hexagon's neighbors, starting east and going
+/// anticlockwise.
+const HEX_DIRECTIONS: [(i32, i32); 6] = [(1, 0), (1, -1), (0, -1), (-1, 0), (-1, 1), (0, 1)];
+
+impl Hex {
+    pub const fn new(q: i32, r: i32) -> Hex {
+        Hex { q, r }
+    }
+
+    pub fn q(self) -> i32 {
+        self.q
+    }
+
+    pub fn r(self) -> i32 {
+        self.r
+    }
+
+    pub fn s(self) -> i32 {
+        -self.q - self.r
+    }
+
+    /// Rounds fractional axial coordinates to the hexagon containing them.
+    pub fn round(q: f64, r: f64) -> Hex {
+        let s = -q - r;
+        let (mut rq, mut rr, rs) = (q.round(), r.round(), s.round());
+        let (dq, dr, ds) = ((rq - q).abs(), (rr - r).abs(), (rs - s).abs());
+
+        // Whichever was rounded the most is worked out from the other two
+        if dq > dr && dq > ds {
+            rq = -rr - rs;
+        } else if dr > ds {
+            rr = -rq - rs;
+        }
+
+        Hex::new(rq as i32, rr as i32)
+    }
+
+    pub fn neighbors(self) -> [Hex; 6] {
+        let mut neighbors = [self; 6];
+        for (neighbor, (dq, dr)) in neighbors.iter_mut().zip(HEX_DIRECTIONS.iter()) {
+            *neighbor = Hex::new(self.q + dq, self.r + dr);
+        }
+        neighbors
+    }
+
+    /// Number of steps between two hexagons.
+    pub fn distance(self, other: Hex) -> u32 {
+        let dq = (self.q - other.q).abs();
+        let dr = (self.r - other.r).abs();
+        let ds = (self.s() - other.s()).abs();
+
+        dq.max(dr).max(ds) as u32
+    }
+
+    /// Every hexagon on a straight line to `other`, including both ends.
+    pub fn line_to(self, other: Hex) -> Vec<Hex> {
+        let steps = self.distance(other);
+        if steps == 0 {
+            return vec![self];
+        }
+
+        // Nudged slightly so lines along hexagon edges always round the
+        // same way
+        let (q, r) = (f64::from(self.q) + 1e-6, f64::from(self.r) + 1e-6);
+        let (dq, dr) = (f64::from(other.q - self.q), f64::from(other.r - self.r));
+        (0..=steps)
+            .map(|i| {
+                let t = f64::from(i) / f64::from(steps);
+                Hex::round(q + dq * t, r + dr * t)
+            })
+            .collect()
+    }
+
+    /// The hexagons exactly `radius` steps away, going round anticlockwise.
+    pub fn ring(self, radius: u32) -> Vec<Hex> {
+        if radius == 0 {
+            return vec![self];
+        }
+
+        let radius = radius as i32;
+        let (start_q, start_r) = HEX_DIRECTIONS[4];
+        let mut hex = Hex::new(self.q + start_q * radius, self.r + start_r * radius);
+        let mut ring = Vec::with_capacity(6 * radius as usize);
+        for &(dq, dr) in HEX_DIRECTIONS.iter() {
+            for _ in 0..radius {
+                ring.push(hex);
+                hex = Hex::new(hex.q + dq, hex.r + dr);
+            }
+        }
+        ring
+    }
+
+    /// The hexagon at a column and row of a grid where odd rows are pushed
+    /// half a hexagon to the right.
+    pub fn from_offset(col: i32, row: i32) -> Hex {
+        Hex::new(col - (row - (row & 1)) / 2, row)
+    }
+
+    /// The column and row of the hexagon, the other way round from
+    /// `from_offset`.
+    pub fn to_offset(self) -> (i32, i32) {
+        (self.q + (self.r - (self.r & 1)) / 2, self.r)
+    }
+
+    /// Position of the hexagon's center, relative to the center of
+    /// `Hex::new(0, 0)`, for hexagons `width` across their flat sides.
+    pub fn to_pixel(self, width: f64) -> (f64, f64) {
+        let size = width / 3f64.sqrt();
+        (
+            width * (f64::from(self.q) + f64::from(self.r) / 2.0),
+            size * 1.5 * f64::from(self.r),
+        )
+    }
+
+    /// The hexagon containing a position, the other way round from
+    /// `to_pixel`.
+    pub fn from_pixel(x: f64, y: f64, width: f64) -> Hex {
+        let size = width / 3f64.sqrt();
+        let r = y / (size * 1.5);
+        let q = x / width - r / 2.0;
+        Hex::round(q, r)
+    }
+}
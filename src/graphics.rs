@@ -35,6 +35,13 @@ use std::iter;
 use std::sync::Arc;
 use std::time::Instant;
 
+use crate::game::clock::{ClockCommand, GameSpeed};
+use crate::game::editor::{EditorCommand, MapEditor};
+use crate::game::replay::GameCommand;
+use crate::game::terrain::Terrain;
+use crate::game::validation::Severity;
+use crate::game::ActiveGame;
+use crate::geometry::{BoundingBox, Point};
 use models::source_engine;
 
 #[derive(Copy, Clone)]
@@ -88,6 +95,15 @@ pub struct Normal {
 
 impl_vertex!(Normal, normal);
 
+/// A corner of the flat, top-down shapes the map editor draws.
+#[derive(Copy, Clone)]
+pub struct ColoredVertex {
+    position: (f32, f32),
+    color: (f32, f32, f32),
+}
+
+impl_vertex!(ColoredVertex, position, color);
+
 pub const NORMALS: [Normal; 11] = [
     Normal {
         normal: (0.0, 0.0, 0.0),
@@ -156,7 +172,9 @@ pub const INDICES: [u16; 642] = [
     125, 60, 55, 55, 121, 125, 126, 125, 121, 121, 122, 126, 127, 126, 122, 122, 123, 127,
 ];
 
-pub fn run() {
+/// Runs the render loop, ticking `game` along with it. The game keeps being
+/// rendered while the simulation is paused, or while a map is being edited.
+pub fn run(game: &mut ActiveGame) {
     let model = source_engine::read_source_engine_model("player/ctm_sas_variantA").unwrap();
     info!("Model id {}", model.mdl_file.header.id);
 
@@ -275,16 +293,44 @@ pub fn run() {
         .unwrap(),
     );
 
+    let editor_vs = editor_vs::Shader::load(device.clone()).unwrap();
+    let editor_fs = editor_fs::Shader::load(device.clone()).unwrap();
+
     let (mut pipeline, mut framebuffers) =
         window_size_dependent_setup(device.clone(), &vs, &fs, &images, render_pass.clone());
+    let mut editor_pipeline = build_editor_pipeline(
+        device.clone(),
+        &editor_vs,
+        &editor_fs,
+        images[0].dimensions(),
+        render_pass.clone(),
+    );
+    let mut title = String::new();
     let mut recreate_swapchain = false;
 
     let mut previous_frame = Box::new(sync::now(device.clone())) as Box<GpuFuture>;
     let rotation_start = Instant::now();
+    let mut last_frame = Instant::now();
+    let mut cursor_position = None;
 
     loop {
         previous_frame.cleanup_finished();
 
+        let now = Instant::now();
+        match game.editor.as_mut() {
+            Some(editor) => editor.update(),
+            None => game.update(now - last_frame),
+        }
+        last_frame = now;
+
+        if let Some(ref editor) = game.editor {
+            let new_title = editor_title(editor);
+            if new_title != title {
+                window.set_title(&new_title);
+                title = new_title;
+            }
+        }
+
         if recreate_swapchain {
             dimensions = if let Some(dimensions) = window.get_inner_size() {
                 let dimensions: (u32, u32) =
@@ -310,6 +356,13 @@ pub fn run() {
             );
             pipeline = new_pipeline;
             framebuffers = new_framebuffers;
+            editor_pipeline = build_editor_pipeline(
+                device.clone(),
+                &editor_vs,
+                &editor_fs,
+                new_images[0].dimensions(),
+                render_pass.clone(),
+            );
 
             recreate_swapchain = false;
         }
@@ -359,7 +412,7 @@ pub fn run() {
                 Err(err) => panic!("{:?}", err),
             };
 
-        let command_buffer =
+        let builder =
             AutoCommandBufferBuilder::primary_one_time_submit(device.clone(), queue.family())
                 .unwrap()
                 .begin_render_pass(
@@ -367,7 +420,28 @@ pub fn run() {
                     false,
                     vec![[0.0, 0.0, 1.0, 1.0].into(), 1f32.into()],
                 )
-                .unwrap()
+                .unwrap();
+        // The editor shows the map it's editing from above, in place of the
+        // game
+        let builder = match game.editor {
+            Some(ref editor) => {
+                let editor_buffer = CpuAccessibleBuffer::from_iter(
+                    device.clone(),
+                    BufferUsage::all(),
+                    editor_vertices(editor).into_iter(),
+                )
+                .unwrap();
+                builder
+                    .draw(
+                        editor_pipeline.clone(),
+                        &DynamicState::none(),
+                        vec![editor_buffer],
+                        (),
+                        (),
+                    )
+                    .unwrap()
+            }
+            None => builder
                 .draw(
                     pipeline.clone(),
                     &DynamicState::none(),
@@ -375,11 +449,9 @@ pub fn run() {
                     set.clone(),
                     (),
                 )
-                .unwrap()
-                .end_render_pass()
-                .unwrap()
-                .build()
-                .unwrap();
+                .unwrap(),
+        };
+        let command_buffer = builder.end_render_pass().unwrap().build().unwrap();
         // let command_buffer =
         //     AutoCommandBufferBuilder::primary_one_time_submit(device.clone(), queue.family())
         //         .unwrap()
@@ -425,6 +497,8 @@ pub fn run() {
         }
 
         let mut done = false;
+        let mut pressed_keys = Vec::new();
+        let mut clicks = Vec::new();
         events_loop.poll_events(|ev| match ev {
             winit::Event::WindowEvent {
                 event: winit::WindowEvent::CloseRequested,
@@ -434,6 +508,32 @@ pub fn run() {
                 event: winit::WindowEvent::Resized(_),
                 ..
             } => recreate_swapchain = true,
+            winit::Event::WindowEvent {
+                event:
+                    winit::WindowEvent::KeyboardInput {
+                        input:
+                            winit::KeyboardInput {
+                                state: winit::ElementState::Pressed,
+                                virtual_keycode: Some(key),
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => pressed_keys.push(key),
+            winit::Event::WindowEvent {
+                event: winit::WindowEvent::CursorMoved { position, .. },
+                ..
+            } => cursor_position = Some(position),
+            winit::Event::WindowEvent {
+                event:
+                    winit::WindowEvent::MouseInput {
+                        state: winit::ElementState::Pressed,
+                        button: winit::MouseButton::Left,
+                        ..
+                    },
+                ..
+            } => clicks.extend(cursor_position),
             // winit::Event::WindowEvent {
             //     event: winit::WindowEvent::Resized(_),
             //     ..
@@ -463,12 +563,249 @@ pub fn run() {
             // },
             _ => (),
         });
+        if let Some(editor) = game.editor.as_mut() {
+            for key in pressed_keys {
+                if let Some(command) = editor_command_for_key(key) {
+                    editor.execute_command(command);
+                }
+            }
+            if let Some(window_size) = window.get_inner_size() {
+                for click in clicks {
+                    let point = screen_to_world(click, window_size, editor.map().dimensions);
+                    editor.execute_command(EditorCommand::Click(point));
+                }
+            }
+        } else {
+            for key in pressed_keys {
+                if let Some(command) = command_for_key(key) {
+                    game.execute_command(command);
+                }
+            }
+            if let Some(window_size) = window.get_inner_size() {
+                for click in clicks {
+                    let destination = screen_to_world(click, window_size, game.map.dimensions);
+                    game.execute_command(GameCommand::MoveHero { destination });
+                }
+            }
+        }
         if done {
             return;
         }
     }
 }
 
+/// Maps a point in the window onto the map, which fills the window viewed
+/// from above.
+fn screen_to_world(
+    position: winit::dpi::LogicalPosition,
+    window_size: winit::dpi::LogicalSize,
+    map: BoundingBox,
+) -> Point {
+    let lower_left = map.lower_left();
+    let upper_right = map.upper_right();
+    let map_width = f64::from(upper_right.x() - lower_left.x());
+    let map_height = f64::from(upper_right.y() - lower_left.y());
+
+    let x = (position.x / window_size.width).max(0.0).min(1.0);
+    // Window coordinates grow downwards, map coordinates upwards
+    let y = 1.0 - (position.y / window_size.height).max(0.0).min(1.0);
+
+    Point::new(
+        lower_left.x() + (x * map_width).round() as u32,
+        lower_left.y() + (y * map_height).round() as u32,
+    )
+}
+
+/// Maps key presses onto game commands.
+fn command_for_key(key: winit::VirtualKeyCode) -> Option<GameCommand> {
+    match key {
+        winit::VirtualKeyCode::Space => Some(GameCommand::Clock(ClockCommand::TogglePause)),
+        winit::VirtualKeyCode::Key1 => Some(GameCommand::Clock(ClockCommand::SetSpeed(
+            GameSpeed::Normal,
+        ))),
+        winit::VirtualKeyCode::Key2 => Some(GameCommand::Clock(ClockCommand::SetSpeed(
+            GameSpeed::Double,
+        ))),
+        winit::VirtualKeyCode::Key4 => Some(GameCommand::Clock(ClockCommand::SetSpeed(
+            GameSpeed::Quadruple,
+        ))),
+        winit::VirtualKeyCode::Return => Some(GameCommand::StartWave),
+        winit::VirtualKeyCode::Z => Some(GameCommand::Undo),
+        winit::VirtualKeyCode::Y => Some(GameCommand::Redo),
+        // Single-stepping is only meant for tracking down bugs
+        winit::VirtualKeyCode::Period if cfg!(debug_assertions) => {
+            Some(GameCommand::Clock(ClockCommand::Step))
+        }
+        _ => None,
+    }
+}
+
+/// Maps key presses onto map editor commands.
+fn editor_command_for_key(key: winit::VirtualKeyCode) -> Option<EditorCommand> {
+    match key {
+        winit::VirtualKeyCode::T => Some(EditorCommand::NextTerrain),
+        winit::VirtualKeyCode::S => Some(EditorCommand::SpawnTool),
+        winit::VirtualKeyCode::G => Some(EditorCommand::GoalTool),
+        winit::VirtualKeyCode::X => Some(EditorCommand::ZoneTool),
+        winit::VirtualKeyCode::E => Some(EditorCommand::NextEnemy),
+        winit::VirtualKeyCode::N => Some(EditorCommand::AddWave),
+        winit::VirtualKeyCode::Delete => Some(EditorCommand::RemoveWave),
+        winit::VirtualKeyCode::LBracket => Some(EditorCommand::PreviousWave),
+        winit::VirtualKeyCode::RBracket => Some(EditorCommand::NextWave),
+        winit::VirtualKeyCode::Z => Some(EditorCommand::Undo),
+        winit::VirtualKeyCode::Y => Some(EditorCommand::Redo),
+        winit::VirtualKeyCode::F5 => Some(EditorCommand::Save),
+        _ => None,
+    }
+}
+
+/// Sums up the editor's last validation, since there's nowhere else to show
+/// it yet.
+fn editor_title(editor: &MapEditor) -> String {
+    let report = editor.report();
+    let status = if !editor.is_validated() {
+        "checking".to_string()
+    } else if report.problems.is_empty() {
+        "no problems".to_string()
+    } else {
+        format!(
+            "{} errors, {} warnings",
+            report.errors().count(),
+            report.warnings().count()
+        )
+    };
+    let modified = if editor.is_modified() { "*" } else { "" };
+
+    format!("{}{} ({})", editor.map().name, modified, status)
+}
+
+fn terrain_color(terrain: Terrain) -> (f32, f32, f32) {
+    match terrain {
+        Terrain::Grass => (0.3, 0.6, 0.2),
+        Terrain::Road => (0.6, 0.5, 0.3),
+        Terrain::Water => (0.1, 0.3, 0.8),
+        Terrain::Cliff => (0.4, 0.4, 0.4),
+        Terrain::Mud => (0.4, 0.3, 0.1),
+    }
+}
+
+/// Builds the triangles for the editor's view of its map: every tile, zone
+/// outlines, spawn points, goals and wherever validation found a problem.
+fn editor_vertices(editor: &MapEditor) -> Vec<ColoredVertex> {
+    let map = editor.map();
+    let lower_left = map.dimensions.lower_left();
+    let upper_right = map.dimensions.upper_right();
+    let width = (upper_right.x() - lower_left.x()).max(1) as f32;
+    let height = (upper_right.y() - lower_left.y()).max(1) as f32;
+
+    // Map coordinates grow upwards, Vulkan's downwards
+    let to_screen = |x: f32, y: f32| {
+        (
+            (x - *lower_left.x() as f32) / width * 2.0 - 1.0,
+            1.0 - (y - *lower_left.y() as f32) / height * 2.0,
+        )
+    };
+    let mut vertices = Vec::new();
+    let mut rect = |left: f32, bottom: f32, right: f32, top: f32, color| {
+        let corners = [
+            to_screen(left, bottom),
+            to_screen(right, bottom),
+            to_screen(right, top),
+            to_screen(left, top),
+        ];
+        for i in [0, 1, 2, 0, 2, 3].iter() {
+            vertices.push(ColoredVertex {
+                position: corners[*i],
+                color,
+            });
+        }
+    };
+
+    let tiles = &map.tiles;
+    let half = tiles.tile_size() as f32 / 2.0;
+    for y in 0..tiles.height() {
+        for x in 0..tiles.width() {
+            if let Some(tile) = tiles.tile(x, y) {
+                let center = tiles.center(x, y);
+                let (cx, cy) = (*center.x() as f32, *center.y() as f32);
+                rect(
+                    cx - half,
+                    cy - half,
+                    cx + half,
+                    cy + half,
+                    terrain_color(tile.terrain),
+                );
+            }
+        }
+    }
+
+    let line = half / 2.0;
+    for zone in map.zones.iter() {
+        let bounds = zone.get_bounding_box();
+        let (left, bottom) = (
+            *bounds.lower_left().x() as f32,
+            *bounds.lower_left().y() as f32,
+        );
+        let (right, top) = (
+            *bounds.upper_right().x() as f32,
+            *bounds.upper_right().y() as f32,
+        );
+        let color = (0.9, 0.9, 0.2);
+        rect(left, bottom, right, bottom + line, color);
+        rect(left, top - line, right, top, color);
+        rect(left, bottom, left + line, top, color);
+        rect(right - line, bottom, right, top, color);
+    }
+
+    let mut marker = |point: Point, size: f32, color| {
+        let (x, y) = (*point.x() as f32, *point.y() as f32);
+        rect(x - size, y - size, x + size, y + size, color);
+    };
+    for spawn in map.spawns.iter() {
+        marker(*spawn, half * 2.0, (0.9, 0.1, 0.1));
+    }
+    for goal in map.goals.iter() {
+        marker(*goal, half * 2.0, (1.0, 1.0, 1.0));
+    }
+    for problem in editor.report().problems.iter() {
+        if let Some(location) = problem.location {
+            let color = match problem.severity {
+                Severity::Error => (1.0, 0.0, 1.0),
+                Severity::Warning => (1.0, 0.6, 0.0),
+            };
+            marker(location, half, color);
+        }
+    }
+
+    vertices
+}
+
+/// The editor's pipeline, rebuilt whenever the window is resized like the
+/// game's.
+fn build_editor_pipeline(
+    device: Arc<Device>,
+    vs: &editor_vs::Shader,
+    fs: &editor_fs::Shader,
+    dimensions: [u32; 2],
+    render_pass: Arc<RenderPassAbstract + Send + Sync>,
+) -> Arc<GraphicsPipelineAbstract + Send + Sync> {
+    Arc::new(
+        GraphicsPipeline::start()
+            .vertex_input_single_buffer::<ColoredVertex>()
+            .vertex_shader(vs.main_entry_point(), ())
+            .triangle_list()
+            .viewports(iter::once(Viewport {
+                origin: [0.0, 0.0],
+                dimensions: [dimensions[0] as f32, dimensions[1] as f32],
+                depth_range: 0.0..1.0,
+            }))
+            .fragment_shader(fs.main_entry_point(), ())
+            .render_pass(Subpass::from(render_pass, 0).unwrap())
+            .build(device)
+            .unwrap(),
+    )
+}
+
 /// This method is called once during initialization, then again whenever the window is resized
 fn window_size_dependent_setup(
     device: Arc<Device>,
@@ -539,4 +876,18 @@ mod fs {
     }
 }
 
+mod editor_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "source_assets/shaders/editor_vert.glsl"
+    }
+}
+
+mod editor_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "source_assets/shaders/editor_frag.glsl"
+    }
+}
+
 type ShaderData = Vec<u8>;
@@ -7,6 +7,10 @@ pub mod graphics;
 pub mod logging;
 
 extern crate bincode;
+extern crate rand;
+extern crate rand_pcg;
+extern crate rayon;
+extern crate rhai;
 extern crate serde;
 extern crate serde_derive;
 extern crate serde_json;
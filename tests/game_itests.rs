@@ -0,0 +1,412 @@
+extern crate rust_tower_defense;
+
+use rust_tower_defense::game;
+use rust_tower_defense::game::archetypes::ArchetypeRegistry;
+use rust_tower_defense::game::campaign::{Campaign, CampaignProgress};
+use rust_tower_defense::game::collision::CollisionShape;
+use rust_tower_defense::game::combat::DamageType;
+use rust_tower_defense::game::entities::{GameEntity, GameEntityType, Projectile};
+use rust_tower_defense::game::map::{GameMap, DEFAULT_MAP_DIMENSIONS};
+use rust_tower_defense::game::profile::{Difficulty, GameResult, PlayerProfile};
+use rust_tower_defense::game::reload::DataFile;
+use rust_tower_defense::game::replay::GameCommand;
+use rust_tower_defense::game::terrain::Terrain;
+use rust_tower_defense::game::tiles::{GridShape, TileMap};
+use rust_tower_defense::game::waves::WaveSet;
+use rust_tower_defense::geometry::{BoundingBox, Point};
+
+use std::env;
+use std::fs;
+
+fn test_map() -> GameMap {
+    GameMap::new("Test Map", DEFAULT_MAP_DIMENSIONS)
+}
+
+fn test_archetypes() -> ArchetypeRegistry {
+    ArchetypeRegistry::from_json(include_str!("fixtures/archetypes.json")).unwrap()
+}
+
+#[test]
+fn fast_projectile_damages_enemy() {
+    let mut game = game::start_game(test_map(), test_archetypes(), WaveSet::default());
+
+    let mut enemy = GameEntity::new(0, GameEntityType::Enemy, Point::new(50, 50));
+    enemy.shape = Some(CollisionShape::Circle { radius: 1 });
+    enemy.health = Some(10);
+    let enemy = game.state.add_entity(enemy);
+
+    let tower = game
+        .state
+        .spawn_entity(GameEntityType::Structure, Point::new(0, 0));
+
+    let mut projectile = GameEntity::new(0, GameEntityType::Projectile, Point::new(0, 50));
+    projectile.projectile = Some(Projectile {
+        source: tower,
+        damage: 4,
+        damage_type: DamageType::Physical,
+        effects: Vec::new(),
+        splash: false,
+        target: None,
+        speed: 0,
+        previous_location: Point::new(0, 50),
+    });
+    let projectile = game.state.add_entity(projectile);
+
+    // Moves straight past the enemy within a single tick
+    game.state
+        .set_entity_location(projectile, Point::new(100, 50));
+    game.tick();
+
+    assert!(game.state.entity(projectile).is_none());
+    assert_eq!(game.state.entity(enemy).unwrap().borrow().health, Some(6));
+    assert_eq!(game.combat_stats().tower(tower).unwrap().damage_dealt, 4);
+}
+
+#[test]
+fn towers_shoot_enemies_in_range() {
+    let mut game = game::start_game(test_map(), test_archetypes(), WaveSet::default());
+    game.execute_command(GameCommand::PlaceTower {
+        archetype: "arrow".to_string(),
+        location: Point::new(10, 10),
+    });
+    let tower = game.state.entities[0].borrow().id;
+    let gold = game.state.gold;
+
+    let mut grunt = game
+        .state
+        .archetypes
+        .instantiate("grunt", Point::new(25, 10))
+        .unwrap();
+    grunt.enemy.as_mut().unwrap().bounty = 5;
+    let grunt = game.state.add_entity(grunt);
+
+    // Ten health takes five shots of two damage, fifteen ticks apart
+    for _ in 0..4 * 15 + 5 {
+        game.tick();
+    }
+
+    assert!(game.state.entity(grunt).is_none());
+    let stats = game.combat_stats().tower(tower).unwrap();
+    assert_eq!(stats.damage_dealt, 10);
+    assert_eq!(stats.kills, 1);
+    assert_eq!(game.state.gold, gold + 5);
+}
+
+#[test]
+fn towers_cost_gold_and_cannot_overlap() {
+    let mut game = game::start_game(test_map(), test_archetypes(), WaveSet::default());
+    let gold = game.state.gold;
+
+    let place = |game: &mut game::ActiveGame, x, y| {
+        game.execute_command(GameCommand::PlaceTower {
+            archetype: "arrow".to_string(),
+            location: Point::new(x, y),
+        });
+    };
+
+    place(&mut game, 10, 10);
+    assert_eq!(game.state.entities.len(), 1);
+    assert_eq!(game.state.gold, gold - 40);
+
+    // Too close to the first tower
+    place(&mut game, 12, 10);
+    assert_eq!(game.state.entities.len(), 1);
+
+    place(&mut game, 30, 10);
+    assert_eq!(game.state.entities.len(), 2);
+    assert_eq!(game.state.gold, gold - 80);
+
+    // Out of gold
+    place(&mut game, 50, 10);
+    assert_eq!(game.state.entities.len(), 2);
+}
+
+#[test]
+fn towers_need_the_right_terrain() {
+    let mut map = test_map();
+    map.tiles.fill_terrain(
+        BoundingBox::new(Point::new(0, 0), Point::new(100, 20)),
+        Terrain::Water,
+    );
+    map.tiles.fill_terrain(
+        BoundingBox::new(Point::new(0, 20), Point::new(100, 28)),
+        Terrain::Road,
+    );
+    let mut game = game::start_game(map, test_archetypes(), WaveSet::default());
+
+    let place = |game: &mut game::ActiveGame, archetype: &str, y| {
+        game.execute_command(GameCommand::PlaceTower {
+            archetype: archetype.to_string(),
+            location: Point::new(10, y),
+        });
+        game.state.entities.len()
+    };
+
+    assert_eq!(place(&mut game, "arrow", 10), 0);
+    assert_eq!(place(&mut game, "arrow", 24), 0);
+    assert_eq!(place(&mut game, "harpoon", 50), 0);
+    assert_eq!(place(&mut game, "harpoon", 10), 1);
+    assert_eq!(place(&mut game, "arrow", 50), 2);
+}
+
+#[test]
+fn towers_on_hex_maps_fill_their_hexagon() {
+    let mut map = test_map();
+    map.tiles = TileMap::with_shape(DEFAULT_MAP_DIMENSIONS, 8, GridShape::Hex);
+    let (x, y) = map.tiles.cell_at(Point::new(30, 30)).unwrap();
+    let center = map.tiles.center(x, y);
+    map.tiles.set_terrain(x + 1, y, Terrain::Water);
+    let water = map.tiles.center(x + 1, y);
+    let mut game = game::start_game(map, test_archetypes(), WaveSet::default());
+
+    let place = |game: &mut game::ActiveGame, location| {
+        game.execute_command(GameCommand::PlaceTower {
+            archetype: "arrow".to_string(),
+            location,
+        });
+        game.state.entities.len()
+    };
+
+    assert_eq!(place(&mut game, Point::new(30, 30)), 1);
+    assert_eq!(game.state.entities[0].borrow().location, center);
+
+    // Anywhere else in the same hexagon is taken
+    assert_eq!(place(&mut game, Point::new(center.x() + 2, *center.y())), 1);
+    assert_eq!(place(&mut game, water), 1);
+}
+
+#[test]
+fn reloaded_archetypes_apply_to_future_spawns() {
+    let mut game = game::start_game(test_map(), test_archetypes(), WaveSet::default());
+    let path = env::temp_dir().join(format!("rtd_itest_archetypes_{}.json", std::process::id()));
+    let path = path.to_str().unwrap();
+
+    fs::write(path, r#"{ "towers": [ { "name": "arrow" } ] }"#).unwrap();
+    assert!(game.reload_data(DataFile::Archetypes, path).is_err());
+    assert_eq!(game.state.archetypes.tower("arrow").unwrap().cost, 40);
+
+    fs::write(
+        path,
+        r#"{
+            "towers": [
+                { "name": "arrow", "model": "arrow", "cost": 10, "range": 20, "damage": 2, "fire_interval": 15 }
+            ]
+        }"#,
+    )
+    .unwrap();
+    game.reload_data(DataFile::Archetypes, path).unwrap();
+    assert_eq!(game.state.archetypes.tower("arrow").unwrap().cost, 10);
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn reloaded_archetypes_keep_campaign_rules() {
+    let campaign = Campaign::from_json(
+        r#"{
+            "name": "Test Campaign",
+            "starting_towers": ["arrow"],
+            "upgrades": [{ "name": "longbows", "stat": "Range", "percent": 50 }],
+            "levels": [{ "id": "one", "name": "One", "waves": "w", "rewards": [{ "Upgrade": "longbows" }] }]
+        }"#,
+        &test_archetypes(),
+    )
+    .unwrap();
+    let mut progress = CampaignProgress::default();
+    progress.complete(campaign.level("one").unwrap());
+
+    let mut game = game::start_game(test_map(), test_archetypes(), WaveSet::default());
+    game.level_rules = Some(campaign.level_rules(&progress));
+    let path = env::temp_dir().join(format!("rtd_itest_campaign_{}.json", std::process::id()));
+    let path = path.to_str().unwrap();
+
+    fs::write(
+        path,
+        r#"{
+            "towers": [
+                { "name": "arrow", "model": "arrow", "cost": 40, "range": 20, "damage": 2, "fire_interval": 15 },
+                { "name": "cannon", "model": "cannon", "cost": 80, "range": 20, "damage": 8, "fire_interval": 40 }
+            ]
+        }"#,
+    )
+    .unwrap();
+    game.reload_data(DataFile::Archetypes, path).unwrap();
+    fs::remove_file(path).unwrap();
+
+    assert!(game.state.archetypes.tower("cannon").is_none());
+    assert_eq!(game.state.archetypes.tower("arrow").unwrap().range, 30);
+}
+
+#[test]
+fn clearing_the_last_wave_counts_towards_the_profile() {
+    let archetypes = test_archetypes();
+    let waves = WaveSet::from_json(
+        r#"{ "waves": [{ "groups": [{ "archetype": "grunt", "count": 2, "location": [0, 50], "interval": 3 }] }] }"#,
+        &archetypes,
+    )
+    .unwrap();
+    let mut game = game::start_game(test_map(), archetypes, waves);
+
+    game.start_wave(1);
+    for _ in 0..4 {
+        game.tick();
+    }
+    assert_eq!(game.state.entities.len(), 2);
+    assert!(!game.is_cleared());
+
+    let ids: Vec<_> = game.state.entities.iter().map(|e| e.borrow().id).collect();
+    for id in ids {
+        game.state.commands.despawn(id);
+    }
+    game.state.apply_commands();
+    assert!(game.is_cleared());
+
+    let mut profile = PlayerProfile::new();
+    profile.record_game(&GameResult::from_game(&game, Difficulty::Easy));
+    assert!(profile.has_cleared("Test Map", Difficulty::Easy));
+    assert!(profile.has_achievement("first_victory"));
+}
+
+#[test]
+fn endless_waves_follow_the_authored_ones() {
+    let play = |seed| {
+        let archetypes = test_archetypes();
+        let waves = WaveSet::from_json(
+            r#"{ "waves": [{ "groups": [{ "archetype": "grunt", "count": 1, "location": [0, 50] }] }] }"#,
+            &archetypes,
+        )
+        .unwrap();
+        let mut game = game::start_game(test_map(), archetypes, waves);
+        game.endless = true;
+        game.set_seed(seed);
+
+        game.start_wave(3);
+        assert_eq!(game.waves.len(), 3);
+        for _ in 0..1000 {
+            game.tick();
+        }
+        assert!(!game.is_cleared());
+
+        game.waves.wave(3).unwrap().clone()
+    };
+
+    assert_eq!(play(7), play(7));
+    assert!(play(7)
+        .groups
+        .iter()
+        .all(|group| group.health_percent > 100));
+}
+
+#[test]
+fn ticks_play_out_the_same_on_any_number_of_threads() {
+    let play = |threads| {
+        let mut game = game::start_game(test_map(), test_archetypes(), WaveSet::default());
+        game.scheduler.set_threads(threads).unwrap();
+
+        for i in 0..50 {
+            game.execute_command(GameCommand::PlaceTower {
+                archetype: "arrow".to_string(),
+                location: Point::new(10 + (i % 10) * 8, 10 + (i / 10) * 8),
+            });
+            game.state.gold += 40;
+
+            let grunt = game
+                .state
+                .archetypes
+                .instantiate("grunt", Point::new(12 + (i % 10) * 8, 14 + (i / 10) * 8))
+                .unwrap();
+            game.state.add_entity(grunt);
+        }
+
+        for i in 0..200 {
+            let from = Point::new(0, 12 + (i % 40) * 2);
+            let mut projectile = GameEntity::new(0, GameEntityType::Projectile, from);
+            projectile.projectile = Some(Projectile {
+                source: i,
+                damage: 3,
+                damage_type: DamageType::Physical,
+                effects: Vec::new(),
+                splash: false,
+                target: None,
+                speed: 0,
+                previous_location: from,
+            });
+            let projectile = game.state.add_entity(projectile);
+            game.state
+                .set_entity_location(projectile, Point::new(95, *from.y()));
+        }
+
+        for _ in 0..5 {
+            game.tick();
+        }
+
+        let entities: Vec<String> = game
+            .state
+            .entities
+            .iter()
+            .map(|entity| {
+                let entity = entity.borrow();
+                let target = entity
+                    .tower
+                    .as_ref()
+                    .and_then(|tower| tower.target.as_ref().map(|t| t.id()));
+                format!("{} {:?} {:?}", entity.id, entity.health, target)
+            })
+            .collect();
+        let log: Vec<String> = game
+            .combat_log
+            .entries()
+            .iter()
+            .map(|entry| format!("{:?}", entry))
+            .collect();
+
+        (entities, log)
+    };
+
+    let (entities, log) = play(1);
+    assert!(!log.is_empty());
+    assert_eq!(play(4), (entities, log));
+}
+
+#[test]
+fn build_phase_undo_is_replayed() {
+    let place = |x| GameCommand::PlaceTower {
+        archetype: "arrow".to_string(),
+        location: Point::new(x, 10),
+    };
+
+    let mut game = game::start_game(test_map(), test_archetypes(), WaveSet::default());
+    let gold = game.state.gold;
+    game.execute_command(place(10));
+    game.execute_command(place(30));
+    game.execute_command(GameCommand::Undo);
+    assert_eq!(game.state.gold, gold - 40);
+    game.execute_command(GameCommand::Redo);
+    game.execute_command(GameCommand::Undo);
+    game.tick();
+
+    // Starting a wave makes the remaining tower permanent
+    game.execute_command(GameCommand::StartWave);
+    game.execute_command(GameCommand::Undo);
+    assert_eq!(game.state.entities.len(), 1);
+    assert_eq!(game.state.gold, gold - 40);
+
+    let mut replayed = game::start_game(test_map(), test_archetypes(), WaveSet::default());
+    for tick in 0..=game.state.tick {
+        let commands: Vec<_> = game.replay.commands_at(tick).cloned().collect();
+        for command in commands {
+            replayed.execute_command(command);
+        }
+        if tick < game.state.tick {
+            replayed.tick();
+        }
+    }
+
+    assert_eq!(replayed.state.gold, game.state.gold);
+    assert_eq!(replayed.state.wave, 1);
+    assert_eq!(
+        replayed.state.entities[0].borrow().location,
+        Point::new(10, 10)
+    );
+    assert_eq!(replayed.replay.entries(), game.replay.entries());
+}